@@ -0,0 +1,4 @@
+-- Track whether jwt_config has been seeded from the static config.
+-- Until then, startup copies the [jwt] TTLs from config into the table;
+-- afterwards the table (edited via the admin dashboard) is authoritative.
+ALTER TABLE jwt_config ADD COLUMN IF NOT EXISTS seeded BOOLEAN NOT NULL DEFAULT FALSE;
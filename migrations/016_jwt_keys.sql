@@ -0,0 +1,21 @@
+-- JWT signing keyring: tokens carry a `kid` header naming the key that signed them.
+-- active:    signs new tokens (exactly one)
+-- verifying: previous key, still accepted until its grace period ends
+-- retired:   no longer accepted
+CREATE TABLE jwt_keys (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    kid VARCHAR(64) NOT NULL UNIQUE,
+    secret VARCHAR(255) NOT NULL,
+    status VARCHAR(20) NOT NULL DEFAULT 'active'
+        CHECK (status IN ('active', 'verifying', 'retired')),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    rotated_at TIMESTAMPTZ,
+    retired_at TIMESTAMPTZ
+);
+
+CREATE UNIQUE INDEX idx_jwt_keys_single_active ON jwt_keys(status) WHERE status = 'active';
+CREATE INDEX idx_jwt_keys_status ON jwt_keys(status);
+
+-- Carry over the existing secret so tokens issued before the upgrade (no kid) stay valid
+INSERT INTO jwt_keys (kid, secret, status, created_at)
+SELECT 'legacy', jwt_secret, 'active', jwt_secret_updated_at FROM system_config WHERE id = 1;
@@ -0,0 +1,7 @@
+-- Optional asymmetric keypair per signing key. When `algorithm` is EdDSA or RS256,
+-- access tokens are signed with `private_key` (base64 DER) and `public_jwk` is
+-- published at /.well-known/jwks.json. Refresh and admin tokens keep using `secret`.
+ALTER TABLE jwt_keys ADD COLUMN algorithm VARCHAR(10) NOT NULL DEFAULT 'HS256'
+    CHECK (algorithm IN ('HS256', 'EdDSA', 'RS256'));
+ALTER TABLE jwt_keys ADD COLUMN private_key TEXT;
+ALTER TABLE jwt_keys ADD COLUMN public_jwk JSONB;
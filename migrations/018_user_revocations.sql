@@ -0,0 +1,10 @@
+-- Deleted users whose access tokens may still be unexpired. The gateway keeps
+-- rejecting them until `expires_at` (issue time + access token TTL).
+-- Deactivated users are read from users.is_active instead.
+CREATE TABLE user_revocations (
+    user_id UUID PRIMARY KEY,
+    expires_at TIMESTAMPTZ NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_user_revocations_expires_at ON user_revocations(expires_at);
@@ -0,0 +1,10 @@
+-- Device metadata for refresh tokens so users can review their sessions.
+-- A session is a refresh token family; rotation carries the label and
+-- session start time forward to the new token.
+ALTER TABLE refresh_tokens ADD COLUMN user_agent TEXT;
+ALTER TABLE refresh_tokens ADD COLUMN ip_address VARCHAR(45);
+ALTER TABLE refresh_tokens ADD COLUMN label VARCHAR(100);
+ALTER TABLE refresh_tokens ADD COLUMN session_created_at TIMESTAMPTZ NOT NULL DEFAULT NOW();
+ALTER TABLE refresh_tokens ADD COLUMN last_used_at TIMESTAMPTZ NOT NULL DEFAULT NOW();
+
+UPDATE refresh_tokens SET session_created_at = created_at, last_used_at = created_at;
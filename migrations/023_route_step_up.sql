@@ -0,0 +1,5 @@
+-- Step-up authentication policy per proxied route.
+-- max_auth_age_secs: reject tokens whose login (auth_time) is older than this
+-- require_mfa: reject tokens whose login did not use a second factor
+ALTER TABLE proxy_routes ADD COLUMN max_auth_age_secs INTEGER;
+ALTER TABLE proxy_routes ADD COLUMN require_mfa BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,10 @@
+-- Roles/groups of a user, emitted as the `roles` access token claim
+CREATE TABLE user_roles (
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    role VARCHAR(64) NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (user_id, role)
+);
+
+-- Routes with a non-empty list only accept users holding one of the roles
+ALTER TABLE proxy_routes ADD COLUMN required_roles TEXT[] NOT NULL DEFAULT '{}';
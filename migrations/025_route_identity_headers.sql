@@ -0,0 +1,3 @@
+-- Token claims forwarded to the upstream as headers: {"claim": "Header-Name"}
+ALTER TABLE proxy_routes
+    ADD COLUMN identity_headers JSONB NOT NULL DEFAULT '{"sub": "X-User-Id"}';
@@ -0,0 +1,8 @@
+-- Per-upstream keys for the signed X-Arc-Identity assertion
+CREATE TABLE identity_keys (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    upstream_address VARCHAR(255) NOT NULL UNIQUE,
+    kid VARCHAR(32) NOT NULL,
+    secret VARCHAR(128) NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,4 @@
+-- required: valid token needed; optional: token validated when present; none: no auth
+ALTER TABLE proxy_routes ADD COLUMN auth_mode VARCHAR(16) NOT NULL DEFAULT 'required';
+UPDATE proxy_routes SET auth_mode = CASE WHEN require_auth THEN 'required' ELSE 'none' END;
+ALTER TABLE proxy_routes DROP COLUMN require_auth;
@@ -0,0 +1,5 @@
+-- Token sources besides the Authorization header, for WebSocket/SSE clients
+ALTER TABLE proxy_routes
+    ADD COLUMN token_query_param VARCHAR(64),
+    ADD COLUMN token_cookie VARCHAR(64),
+    ADD COLUMN token_subprotocol BOOLEAN NOT NULL DEFAULT FALSE;
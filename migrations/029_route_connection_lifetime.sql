@@ -0,0 +1,4 @@
+-- Limits for long-lived WebSocket/SSE connections
+ALTER TABLE proxy_routes
+    ADD COLUMN max_connection_secs INTEGER,
+    ADD COLUMN close_at_token_expiry BOOLEAN NOT NULL DEFAULT FALSE;
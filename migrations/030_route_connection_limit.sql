@@ -0,0 +1,2 @@
+-- Limit on concurrent WebSocket/SSE connections per user or API key
+ALTER TABLE proxy_routes ADD COLUMN max_connections_per_user INTEGER;
@@ -0,0 +1,18 @@
+-- Load-balanced upstream pools with active health checks
+CREATE TABLE upstream_pools (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    name VARCHAR(100) NOT NULL UNIQUE,
+    -- round_robin, least_connections or consistent_hash
+    strategy VARCHAR(32) NOT NULL DEFAULT 'round_robin',
+    -- [{"address": "host:port", "weight": 1}]
+    targets JSONB NOT NULL DEFAULT '[]',
+    -- NULL disables health checks
+    health_check_path VARCHAR(255) DEFAULT '/health',
+    health_check_interval_secs INTEGER NOT NULL DEFAULT 10,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+ALTER TABLE proxy_routes
+    ADD COLUMN upstream_pool VARCHAR(100)
+        REFERENCES upstream_pools(name) ON UPDATE CASCADE ON DELETE RESTRICT;
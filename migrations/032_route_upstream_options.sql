@@ -0,0 +1,8 @@
+-- Per-route upstream timeouts, retries and connection reuse
+ALTER TABLE proxy_routes
+    ADD COLUMN connect_timeout_ms INTEGER,
+    ADD COLUMN read_timeout_ms INTEGER,
+    ADD COLUMN write_timeout_ms INTEGER,
+    ADD COLUMN idle_timeout_ms INTEGER,
+    ADD COLUMN max_retries INTEGER NOT NULL DEFAULT 0,
+    ADD COLUMN upstream_keepalive BOOLEAN NOT NULL DEFAULT TRUE;
@@ -0,0 +1,6 @@
+-- Per-route in-flight limit with a bounded wait queue
+ALTER TABLE proxy_routes
+    ADD COLUMN max_in_flight INTEGER,
+    ADD COLUMN max_queue INTEGER NOT NULL DEFAULT 0,
+    ADD COLUMN queue_timeout_ms INTEGER NOT NULL DEFAULT 5000,
+    ADD COLUMN fair_queue BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,19 @@
+-- Proxied requests, response bytes and time per user or API key per route,
+-- aggregated into hourly buckets
+CREATE TABLE usage_buckets (
+    subject_type VARCHAR(16) NOT NULL,
+    subject_id VARCHAR(128) NOT NULL,
+    route VARCHAR(255) NOT NULL,
+    bucket_start TIMESTAMPTZ NOT NULL,
+    requests BIGINT NOT NULL DEFAULT 0,
+    response_bytes BIGINT NOT NULL DEFAULT 0,
+    duration_ms BIGINT NOT NULL DEFAULT 0,
+    PRIMARY KEY (subject_type, subject_id, route, bucket_start)
+);
+
+CREATE INDEX idx_usage_buckets_bucket_start ON usage_buckets(bucket_start);
+
+-- Requests allowed per user or API key on a route, per UTC day and month
+ALTER TABLE proxy_routes
+    ADD COLUMN daily_quota INTEGER,
+    ADD COLUMN monthly_quota INTEGER;
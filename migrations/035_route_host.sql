@@ -0,0 +1,6 @@
+-- Virtual hosts: a route may be limited to one host name or `*.domain`
+ALTER TABLE proxy_routes ADD COLUMN host VARCHAR(255);
+
+-- The same path may now exist once per host
+ALTER TABLE proxy_routes DROP CONSTRAINT IF EXISTS proxy_routes_path_prefix_key;
+CREATE UNIQUE INDEX idx_proxy_routes_host_path ON proxy_routes (COALESCE(host, ''), path_prefix);
@@ -0,0 +1,6 @@
+-- Route matching: pattern type, explicit priority, allowed methods and path rewrites
+ALTER TABLE proxy_routes
+    ADD COLUMN match_type VARCHAR(16) NOT NULL DEFAULT 'prefix',
+    ADD COLUMN priority INTEGER NOT NULL DEFAULT 0,
+    ADD COLUMN methods TEXT[] NOT NULL DEFAULT '{}',
+    ADD COLUMN rewrite VARCHAR(255);
@@ -0,0 +1,4 @@
+-- Successor of a rotated refresh token, encrypted with a key derived from the
+-- rotated token. A client that retries or races a refresh within the grace
+-- window gets the same successor instead of tripping reuse detection.
+ALTER TABLE refresh_tokens ADD COLUMN successor_token TEXT;
@@ -0,0 +1,4 @@
+-- Key an upstream is being rotated to. The gateway keeps signing with the
+-- current key until the new one is promoted, so the upstream can accept both.
+ALTER TABLE identity_keys ADD COLUMN next_kid VARCHAR(32);
+ALTER TABLE identity_keys ADD COLUMN next_secret VARCHAR(128);
@@ -0,0 +1,4 @@
+-- Routes on the same host and path may differ in match type or methods
+DROP INDEX IF EXISTS idx_proxy_routes_host_path;
+CREATE UNIQUE INDEX idx_proxy_routes_host_path_match
+    ON proxy_routes (COALESCE(host, ''), path_prefix, match_type, methods);
@@ -0,0 +1,11 @@
+-- When a verifying key is retired, fixed when it is rotated out so later TTL
+-- changes do not shorten or extend the grace of keys already rotated out
+ALTER TABLE jwt_keys ADD COLUMN verify_until TIMESTAMPTZ;
+
+UPDATE jwt_keys
+SET verify_until = rotated_at + make_interval(secs => (
+    SELECT GREATEST(access_token_ttl_secs, refresh_token_ttl_secs) FROM jwt_config WHERE id = 1
+))
+WHERE status = 'verifying';
+
+UPDATE jwt_keys SET verify_until = rotated_at WHERE status = 'verifying' AND verify_until IS NULL;
@@ -8,6 +8,7 @@ use axum::{
 use serde::Serialize;
 
 use crate::api::AppState;
+use crate::gateway::token_source::bearer_token;
 
 #[derive(Serialize)]
 struct AuthError {
@@ -25,13 +26,7 @@ pub async fn admin_auth_middleware(
     request: Request,
     next: Next,
 ) -> Response {
-    let token = request
-        .headers()
-        .get("authorization")
-        .and_then(|v| v.to_str().ok())
-        .and_then(|s| s.strip_prefix("Bearer "));
-
-    let token = match token {
+    let token = match bearer_token(request.headers()) {
         Some(t) => t,
         None => {
             return (
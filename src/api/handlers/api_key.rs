@@ -7,6 +7,15 @@ use crate::api::AppState;
 use crate::error::{AppError, Result};
 use crate::models::ApiKey;
 
+async fn refresh_api_key_cache(state: &AppState) {
+    if let Some(ref cache) = state.api_key_cache {
+        if let Ok(hashes) = state.api_key_service.list_key_hashes().await {
+            cache.update_keys(hashes);
+            tracing::info!("API key cache refreshed");
+        }
+    }
+}
+
 #[derive(Deserialize)]
 pub struct CreateApiKeyRequest {
     pub name: String,
@@ -49,6 +58,7 @@ pub async fn create_api_key(
         .await?;
 
     info!(admin_id = %admin_id, key_id = %api_key.id, "API key created");
+    refresh_api_key_cache(&state).await;
 
     Ok(Json(CreateApiKeyResponse { api_key, raw_key }))
 }
@@ -65,6 +75,7 @@ pub async fn delete_api_key(
     }
 
     info!(admin_id = %admin_id, key_id = %id, "API key deleted");
+    refresh_api_key_cache(&state).await;
 
     Ok(Json(serde_json::json!({ "success": true })))
 }
@@ -0,0 +1,12 @@
+use axum::{extract::State, http::header, response::IntoResponse, Json};
+
+use crate::api::AppState;
+use crate::error::Result;
+
+/// Serves the public keys of asymmetric signing keys at
+/// `/.well-known/jwks.json` so upstream services can verify access tokens
+/// offline. Empty while access tokens are signed with HS256.
+pub async fn jwks(State(state): State<AppState>) -> Result<impl IntoResponse> {
+    let jwks = state.system_config_service.get_jwks().await?;
+    Ok(([(header::CACHE_CONTROL, "public, max-age=300")], Json(jwks)))
+}
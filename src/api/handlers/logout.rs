@@ -0,0 +1,82 @@
+use axum::{extract::State, http::HeaderMap, Json};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::api::AppState;
+use crate::error::{AppError, Result};
+use crate::gateway::token_source::bearer_token;
+use crate::models::AccessTokenClaims;
+
+#[derive(Deserialize)]
+pub struct LogoutRequest {
+    pub refresh_token: String,
+}
+
+#[derive(Serialize)]
+pub struct LogoutAllResponse {
+    pub revoked_sessions: u64,
+}
+
+/// Claims of the caller's bearer access token, rejecting revoked tokens.
+pub(super) async fn authenticate(
+    state: &AppState,
+    headers: &HeaderMap,
+) -> Result<AccessTokenClaims> {
+    let token = bearer_token(headers).ok_or(AppError::InvalidToken)?;
+    let claims = state.token_service.validate_access_token(token).await?;
+    if let Some(ref cache) = state.revocation_cache {
+        if cache.is_user_revoked(&claims.sub) || cache.is_token_revoked(&claims.jti) {
+            return Err(AppError::TokenRevoked);
+        }
+    }
+    Ok(claims)
+}
+
+/// Deny-lists the access token in the gateway until it expires.
+async fn revoke_access_token(state: &AppState, claims: &AccessTokenClaims) -> Result<()> {
+    state.token_service.revoke_access_token(claims).await?;
+    if let Some(ref cache) = state.revocation_cache {
+        cache.revoke_token(claims.jti, claims.exp);
+    }
+    Ok(())
+}
+
+/// Ends the current session: revokes the presented refresh token, which
+/// must belong to the caller of the bearer access token, and deny-lists
+/// the access token too.
+pub async fn logout(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(req): Json<LogoutRequest>,
+) -> Result<Json<serde_json::Value>> {
+    let claims = authenticate(&state, &headers).await?;
+
+    let revoked = state
+        .token_service
+        .revoke_refresh_token(&req.refresh_token, claims.sub)
+        .await?;
+    if !revoked {
+        return Err(AppError::InvalidToken);
+    }
+    revoke_access_token(&state, &claims).await?;
+
+    Ok(Json(serde_json::json!({ "success": true })))
+}
+
+/// Ends every session of the caller identified by the bearer access token.
+pub async fn logout_all(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<LogoutAllResponse>> {
+    let claims = authenticate(&state, &headers).await?;
+
+    let revoked_sessions = state
+        .token_service
+        .revoke_all_refresh_tokens(claims.sub)
+        .await?;
+    revoke_access_token(&state, &claims).await?;
+
+    info!(user_id = %claims.sub, revoked_sessions, "User logged out everywhere");
+
+    Ok(Json(LogoutAllResponse { revoked_sessions }))
+}
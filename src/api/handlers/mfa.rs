@@ -0,0 +1,146 @@
+use std::net::SocketAddr;
+
+use axum::{
+    extract::{ConnectInfo, State},
+    http::HeaderMap,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::logout::authenticate;
+use super::sessions::session_metadata;
+use super::verify::AuthResponse;
+use crate::api::AppState;
+use crate::error::{AppError, Result};
+use crate::models::{AuthContext, UserInfo};
+
+#[derive(Serialize)]
+pub struct TotpSetupResponse {
+    pub secret: String,
+    pub otpauth_uri: String,
+}
+
+#[derive(Deserialize)]
+pub struct MfaCodeRequest {
+    pub code: String,
+}
+
+#[derive(Serialize)]
+pub struct RecoveryCodesResponse {
+    pub recovery_codes: Vec<String>,
+}
+
+#[derive(Deserialize)]
+pub struct LoginMfaRequest {
+    pub mfa_token: String,
+    pub code: String,
+}
+
+/// Starts TOTP enrollment. The secret is shown once, as text and as an
+/// `otpauth://` URI for QR codes.
+pub async fn totp_setup(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<TotpSetupResponse>> {
+    let claims = authenticate(&state, &headers).await?;
+    let enrollment = state
+        .mfa_service
+        .begin_enrollment(claims.sub, &claims.email)
+        .await?;
+
+    Ok(Json(TotpSetupResponse {
+        secret: enrollment.secret,
+        otpauth_uri: enrollment.otpauth_uri,
+    }))
+}
+
+pub async fn totp_confirm(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(req): Json<MfaCodeRequest>,
+) -> Result<Json<RecoveryCodesResponse>> {
+    let claims = authenticate(&state, &headers).await?;
+    let recovery_codes = state
+        .mfa_service
+        .confirm_enrollment(claims.sub, req.code.trim())
+        .await?;
+
+    Ok(Json(RecoveryCodesResponse { recovery_codes }))
+}
+
+pub async fn totp_disable(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(req): Json<MfaCodeRequest>,
+) -> Result<Json<serde_json::Value>> {
+    let claims = authenticate(&state, &headers).await?;
+    state.mfa_service.disable(claims.sub, &req.code).await?;
+
+    Ok(Json(serde_json::json!({ "success": true })))
+}
+
+pub async fn regenerate_recovery_codes(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(req): Json<MfaCodeRequest>,
+) -> Result<Json<RecoveryCodesResponse>> {
+    let claims = authenticate(&state, &headers).await?;
+    let recovery_codes = state
+        .mfa_service
+        .reset_recovery_codes(claims.sub, &req.code)
+        .await?;
+
+    Ok(Json(RecoveryCodesResponse { recovery_codes }))
+}
+
+/// Second login step for users with 2FA: exchanges the `mfa_token` from
+/// `/auth/login/verify` and a TOTP or recovery code for tokens.
+pub async fn login_mfa(
+    State(state): State<AppState>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+    Json(req): Json<LoginMfaRequest>,
+) -> Result<Json<AuthResponse>> {
+    let challenge_id = req
+        .mfa_token
+        .parse::<Uuid>()
+        .map_err(|_| AppError::InvalidToken)?;
+
+    let (user_id, amr) = state
+        .mfa_service
+        .verify_challenge(challenge_id, &req.code)
+        .await?;
+
+    let user = state
+        .user_service
+        .find_by_id(user_id)
+        .await?
+        .ok_or(AppError::InvalidCredentials)?;
+    if !user.is_active {
+        return Err(AppError::AccountDisabled);
+    }
+
+    let auth = AuthContext::new(amr);
+    let access = state
+        .token_service
+        .generate_access_token(user.id, &user.email, &auth)
+        .await?;
+    let refresh = state
+        .token_service
+        .generate_refresh_token(
+            user.id,
+            &auth,
+            &session_metadata(&headers, connect_info.as_ref()),
+        )
+        .await?;
+
+    Ok(Json(AuthResponse {
+        user: UserInfo {
+            id: user.id.to_string(),
+            email: user.email,
+        },
+        access_token: access,
+        refresh_token: refresh,
+    }))
+}
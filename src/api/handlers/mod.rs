@@ -4,13 +4,18 @@ pub mod api_key;
 pub mod api_key_auth;
 pub mod captcha;
 pub mod external;
+pub mod jwks;
+pub mod logout;
+pub mod mfa;
 pub mod password_reset;
 pub mod proxy_config;
 pub mod refresh;
 pub mod register;
+pub mod sessions;
 pub mod srp_login;
 pub mod stats;
 pub mod system_config;
+pub mod usage;
 pub mod verify;
 
 pub use admin::*;
@@ -19,11 +24,16 @@ pub use api_key::*;
 pub use api_key_auth::api_key_auth_middleware;
 pub use captcha::get_captcha;
 pub use external::*;
+pub use jwks::jwks;
+pub use logout::{logout, logout_all};
+pub use mfa::{login_mfa, regenerate_recovery_codes, totp_confirm, totp_disable, totp_setup};
 pub use password_reset::{request_password_reset, reset_password};
 pub use proxy_config::*;
 pub use refresh::refresh;
 pub use register::register;
+pub use sessions::{list_sessions, revoke_session, update_session};
 pub use srp_login::{srp_init, srp_verify};
 pub use stats::*;
 pub use system_config::*;
+pub use usage::{get_my_usage, get_user_usage};
 pub use verify::verify;
@@ -5,10 +5,19 @@ use axum::{
 use serde::Deserialize;
 use uuid::Uuid;
 
+use super::stats::is_valid_role;
 use crate::api::AppState;
-use crate::error::Result;
-use crate::gateway::config_cache::CachedRoute;
-use crate::models::{JwtConfigRow, ProxyRoute, RateLimitRule};
+use crate::error::{AppError, Result};
+use crate::gateway::config_cache::{AuthMode, CachedRoute};
+use crate::gateway::health_check;
+use crate::gateway::identity::IdentityHeaders;
+use crate::gateway::rate_limit::{CachedRateLimitRule, LimitBy};
+use crate::gateway::route_matcher::{check_pattern, normalize_methods, MatchType};
+use crate::gateway::upstream_pool::{CachedUpstreamPool, LoadBalance};
+use crate::gateway::virtual_host::HostPattern;
+use crate::models::{
+    JwtConfigRow, ProxyRoute, ProxyRouteInput, RateLimitRule, UpstreamPool, UpstreamPoolInput,
+};
 
 async fn refresh_route_cache(state: &AppState) {
     if let Some(ref cache) = state.config_cache {
@@ -16,12 +25,7 @@ async fn refresh_route_cache(state: &AppState) {
             let cached: Vec<CachedRoute> = routes
                 .into_iter()
                 .filter(|r| r.enabled)
-                .map(|r| CachedRoute {
-                    path_prefix: r.path_prefix,
-                    upstream_address: r.upstream_address,
-                    require_auth: r.require_auth,
-                    strip_prefix: r.strip_prefix,
-                })
+                .map(CachedRoute::from)
                 .collect();
             cache.update_routes(cached);
             tracing::info!("Route cache refreshed");
@@ -29,20 +33,231 @@ async fn refresh_route_cache(state: &AppState) {
     }
 }
 
-#[derive(Deserialize)]
-pub struct CreateRouteRequest {
-    pub path_prefix: String,
-    pub upstream_address: String,
-    pub require_auth: bool,
-    pub strip_prefix: Option<String>,
+async fn refresh_upstream_pool_cache(state: &AppState) {
+    if let Some(ref cache) = state.config_cache {
+        if let Ok(pools) = state.proxy_config_service.list_upstream_pools().await {
+            let cached: Vec<CachedUpstreamPool> = pools
+                .into_iter()
+                .map(CachedUpstreamPool::from_pool)
+                .collect();
+            cache.update_upstream_pools(cached);
+            tracing::info!("Upstream pool cache refreshed");
+        }
+    }
+}
+
+async fn refresh_rate_limit_cache(state: &AppState) {
+    if let Some(ref cache) = state.rate_limit_cache {
+        if let Ok(rules) = state.proxy_config_service.list_rate_limits().await {
+            let cached: Vec<CachedRateLimitRule> = rules
+                .into_iter()
+                .filter_map(CachedRateLimitRule::from_rule)
+                .collect();
+            cache.update_rules(cached);
+            tracing::info!("Rate limit cache refreshed");
+        }
+    }
+}
+
+fn validate_rate_limit(limit_by: &str, max_requests: i32, window_secs: i32) -> Result<()> {
+    if LimitBy::parse(limit_by).is_none() {
+        return Err(AppError::InvalidRequest(
+            "limit_by must be one of: ip, user, api_key".into(),
+        ));
+    }
+    if max_requests <= 0 || window_secs <= 0 {
+        return Err(AppError::InvalidRequest(
+            "max_requests and window_secs must be positive".into(),
+        ));
+    }
+    Ok(())
+}
+
+fn is_valid_token_source_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 64
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+}
+
+/// Upper bound on connect retries, so a dead upstream cannot hold a request
+/// for many connect timeouts.
+const MAX_UPSTREAM_RETRIES: i32 = 5;
+
+/// Validates a route and stores its host and methods in canonical form
+/// (lowercase host without trailing dot, uppercase methods); a blank host is
+/// cleared. Static routes from the config file go through it too.
+pub fn validate_route(route: &mut ProxyRouteInput) -> Result<()> {
+    route.host = match route.host.as_deref().map(str::trim) {
+        None | Some("") => None,
+        Some(host) => Some(
+            HostPattern::parse(host)
+                .ok_or_else(|| {
+                    AppError::InvalidRequest(
+                        "host must be a host name like api.example.com or *.example.com".into(),
+                    )
+                })?
+                .to_string(),
+        ),
+    };
+    let match_type = MatchType::parse(&route.match_type).ok_or_else(|| {
+        AppError::InvalidRequest("match_type must be one of: prefix, exact, regex, glob".into())
+    })?;
+    check_pattern(match_type, &route.path_prefix, route.rewrite.as_deref())
+        .map_err(AppError::InvalidRequest)?;
+    if route.rewrite.is_some() && route.strip_prefix.is_some() {
+        return Err(AppError::InvalidRequest(
+            "Use either rewrite or strip_prefix".into(),
+        ));
+    }
+    route.methods = normalize_methods(&route.methods).map_err(AppError::InvalidRequest)?;
+    let auth_mode = AuthMode::parse(route.auth_mode()).ok_or_else(|| {
+        AppError::InvalidRequest("auth_mode must be one of: required, optional, none".into())
+    })?;
+    if auth_mode != AuthMode::Required
+        && (route.max_auth_age_secs.is_some()
+            || route.require_mfa
+            || !route.required_roles.is_empty())
+    {
+        return Err(AppError::InvalidRequest(
+            "Step-up and required roles need auth_mode 'required'".into(),
+        ));
+    }
+    if route.max_auth_age_secs.is_some_and(|secs| secs <= 0) {
+        return Err(AppError::InvalidRequest(
+            "max_auth_age_secs must be positive".into(),
+        ));
+    }
+    if route.max_connection_secs.is_some_and(|secs| secs <= 0) {
+        return Err(AppError::InvalidRequest(
+            "max_connection_secs must be positive".into(),
+        ));
+    }
+    if route
+        .max_connections_per_user
+        .is_some_and(|limit| limit <= 0)
+    {
+        return Err(AppError::InvalidRequest(
+            "max_connections_per_user must be positive".into(),
+        ));
+    }
+    for (field, ms) in [
+        ("connect_timeout_ms", route.connect_timeout_ms),
+        ("read_timeout_ms", route.read_timeout_ms),
+        ("write_timeout_ms", route.write_timeout_ms),
+        ("idle_timeout_ms", route.idle_timeout_ms),
+    ] {
+        if ms.is_some_and(|ms| ms <= 0) {
+            return Err(AppError::InvalidRequest(format!(
+                "{} must be positive",
+                field
+            )));
+        }
+    }
+    if route.max_in_flight.is_some_and(|max| max <= 0) {
+        return Err(AppError::InvalidRequest(
+            "max_in_flight must be positive".into(),
+        ));
+    }
+    if route.max_queue < 0 || route.queue_timeout_ms <= 0 {
+        return Err(AppError::InvalidRequest(
+            "max_queue must not be negative and queue_timeout_ms must be positive".into(),
+        ));
+    }
+    if route.daily_quota.is_some_and(|q| q <= 0) || route.monthly_quota.is_some_and(|q| q <= 0) {
+        return Err(AppError::InvalidRequest(
+            "daily_quota and monthly_quota must be positive".into(),
+        ));
+    }
+    if !(0..=MAX_UPSTREAM_RETRIES).contains(&route.max_retries) {
+        return Err(AppError::InvalidRequest(format!(
+            "max_retries must be between 0 and {}",
+            MAX_UPSTREAM_RETRIES
+        )));
+    }
+    if let Some(bad) = route.required_roles.iter().find(|r| !is_valid_role(r)) {
+        return Err(AppError::InvalidRequest(format!(
+            "Invalid required role '{}'",
+            bad
+        )));
+    }
+    IdentityHeaders::from_map(&route.identity_headers).map_err(AppError::InvalidRequest)?;
+    for (field, name) in [
+        ("token_query_param", &route.token_query_param),
+        ("token_cookie", &route.token_cookie),
+    ] {
+        if name
+            .as_deref()
+            .is_some_and(|n| !is_valid_token_source_name(n))
+        {
+            return Err(AppError::InvalidRequest(format!(
+                "{} must be 1-64 characters from A-Z, a-z, 0-9, '_', '-', '.'",
+                field
+            )));
+        }
+    }
+    Ok(())
+}
+
+async fn ensure_pool_exists(state: &AppState, route: &ProxyRouteInput) -> Result<()> {
+    if let Some(ref name) = route.upstream_pool {
+        let pools = state.proxy_config_service.list_upstream_pools().await?;
+        if !pools.iter().any(|p| &p.name == name) {
+            return Err(AppError::InvalidRequest(format!(
+                "Unknown upstream pool '{}'",
+                name
+            )));
+        }
+    }
+    Ok(())
+}
+
+fn validate_upstream_pool(pool: &UpstreamPoolInput) -> Result<()> {
+    if pool.name.trim().is_empty() || pool.name.len() > 100 {
+        return Err(AppError::InvalidRequest(
+            "name must be 1-100 characters".into(),
+        ));
+    }
+    if LoadBalance::parse(&pool.strategy).is_none() {
+        return Err(AppError::InvalidRequest(
+            "strategy must be one of: round_robin, least_connections, consistent_hash".into(),
+        ));
+    }
+    if pool.targets.is_empty() {
+        return Err(AppError::InvalidRequest(
+            "A pool needs at least one target".into(),
+        ));
+    }
+    for target in &pool.targets {
+        if target.address.trim().is_empty() || target.weight <= 0 {
+            return Err(AppError::InvalidRequest(
+                "Targets need an address and a positive weight".into(),
+            ));
+        }
+    }
+    if pool
+        .health_check_path
+        .as_deref()
+        .is_some_and(|p| !health_check::is_valid_path(p))
+    {
+        return Err(AppError::InvalidRequest(
+            "health_check_path must be a path starting with '/', without spaces or control characters"
+                .into(),
+        ));
+    }
+    if pool.health_check_interval_secs <= 0 {
+        return Err(AppError::InvalidRequest(
+            "health_check_interval_secs must be positive".into(),
+        ));
+    }
+    Ok(())
 }
 
 #[derive(Deserialize)]
 pub struct UpdateRouteRequest {
-    pub path_prefix: String,
-    pub upstream_address: String,
-    pub require_auth: bool,
-    pub strip_prefix: Option<String>,
+    #[serde(flatten)]
+    pub route: ProxyRouteInput,
     pub enabled: bool,
 }
 
@@ -53,17 +268,11 @@ pub async fn list_routes(State(state): State<AppState>) -> Result<Json<Vec<Proxy
 
 pub async fn create_route(
     State(state): State<AppState>,
-    Json(req): Json<CreateRouteRequest>,
+    Json(mut req): Json<ProxyRouteInput>,
 ) -> Result<Json<ProxyRoute>> {
-    let route = state
-        .proxy_config_service
-        .create_route(
-            &req.path_prefix,
-            &req.upstream_address,
-            req.require_auth,
-            req.strip_prefix.as_deref(),
-        )
-        .await?;
+    validate_route(&mut req)?;
+    ensure_pool_exists(&state, &req).await?;
+    let route = state.proxy_config_service.create_route(&req).await?;
     refresh_route_cache(&state).await;
     Ok(Json(route))
 }
@@ -71,18 +280,13 @@ pub async fn create_route(
 pub async fn update_route(
     State(state): State<AppState>,
     Path(id): Path<Uuid>,
-    Json(req): Json<UpdateRouteRequest>,
+    Json(mut req): Json<UpdateRouteRequest>,
 ) -> Result<Json<ProxyRoute>> {
+    validate_route(&mut req.route)?;
+    ensure_pool_exists(&state, &req.route).await?;
     let route = state
         .proxy_config_service
-        .update_route(
-            id,
-            &req.path_prefix,
-            &req.upstream_address,
-            req.require_auth,
-            req.strip_prefix.as_deref(),
-            req.enabled,
-        )
+        .update_route(id, &req.route, req.enabled)
         .await?;
     refresh_route_cache(&state).await;
     Ok(Json(route))
@@ -94,6 +298,64 @@ pub async fn delete_route(State(state): State<AppState>, Path(id): Path<Uuid>) -
     Ok(Json(()))
 }
 
+pub async fn list_upstream_pools(State(state): State<AppState>) -> Result<Json<Vec<UpstreamPool>>> {
+    let pools = state.proxy_config_service.list_upstream_pools().await?;
+    Ok(Json(pools))
+}
+
+pub async fn create_upstream_pool(
+    State(state): State<AppState>,
+    Json(req): Json<UpstreamPoolInput>,
+) -> Result<Json<UpstreamPool>> {
+    validate_upstream_pool(&req)?;
+    let pool = state
+        .proxy_config_service
+        .create_upstream_pool(&req)
+        .await?;
+    refresh_upstream_pool_cache(&state).await;
+    Ok(Json(pool))
+}
+
+/// Renaming a pool also renames it on the routes that use it.
+pub async fn update_upstream_pool(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Json(req): Json<UpstreamPoolInput>,
+) -> Result<Json<UpstreamPool>> {
+    validate_upstream_pool(&req)?;
+    let pool = state
+        .proxy_config_service
+        .update_upstream_pool(id, &req)
+        .await?;
+    refresh_upstream_pool_cache(&state).await;
+    refresh_route_cache(&state).await;
+    Ok(Json(pool))
+}
+
+pub async fn delete_upstream_pool(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<()>> {
+    let pool = state
+        .proxy_config_service
+        .find_upstream_pool(id)
+        .await?
+        .ok_or(AppError::NotFound)?;
+    let routes = state.proxy_config_service.list_routes().await?;
+    if let Some(route) = routes
+        .iter()
+        .find(|r| r.upstream_pool.as_deref() == Some(pool.name.as_str()))
+    {
+        return Err(AppError::InvalidRequest(format!(
+            "Pool is used by route '{}'",
+            route.path_prefix
+        )));
+    }
+    state.proxy_config_service.delete_upstream_pool(id).await?;
+    refresh_upstream_pool_cache(&state).await;
+    Ok(Json(()))
+}
+
 #[derive(Deserialize)]
 pub struct CreateRateLimitRequest {
     pub name: String,
@@ -122,6 +384,7 @@ pub async fn create_rate_limit(
     State(state): State<AppState>,
     Json(req): Json<CreateRateLimitRequest>,
 ) -> Result<Json<RateLimitRule>> {
+    validate_rate_limit(&req.limit_by, req.max_requests, req.window_secs)?;
     let rule = state
         .proxy_config_service
         .create_rate_limit(
@@ -132,6 +395,7 @@ pub async fn create_rate_limit(
             req.window_secs,
         )
         .await?;
+    refresh_rate_limit_cache(&state).await;
     Ok(Json(rule))
 }
 
@@ -140,6 +404,7 @@ pub async fn update_rate_limit(
     Path(id): Path<Uuid>,
     Json(req): Json<UpdateRateLimitRequest>,
 ) -> Result<Json<RateLimitRule>> {
+    validate_rate_limit(&req.limit_by, req.max_requests, req.window_secs)?;
     let rule = state
         .proxy_config_service
         .update_rate_limit(
@@ -152,6 +417,7 @@ pub async fn update_rate_limit(
             req.enabled,
         )
         .await?;
+    refresh_rate_limit_cache(&state).await;
     Ok(Json(rule))
 }
 
@@ -160,6 +426,7 @@ pub async fn delete_rate_limit(
     Path(id): Path<Uuid>,
 ) -> Result<Json<()>> {
     state.proxy_config_service.delete_rate_limit(id).await?;
+    refresh_rate_limit_cache(&state).await;
     Ok(Json(()))
 }
 
@@ -171,7 +438,7 @@ pub struct UpdateJwtConfigRequest {
 }
 
 pub async fn get_jwt_config(State(state): State<AppState>) -> Result<Json<JwtConfigRow>> {
-    let config = state.proxy_config_service.get_jwt_config().await?;
+    let config = state.jwt_config_service.get_config().await?;
     Ok(Json(config))
 }
 
@@ -179,13 +446,35 @@ pub async fn update_jwt_config(
     State(state): State<AppState>,
     Json(req): Json<UpdateJwtConfigRequest>,
 ) -> Result<Json<JwtConfigRow>> {
+    if req.access_token_ttl_secs <= 0
+        || req.refresh_token_ttl_secs <= 0
+        || req.auto_refresh_threshold_secs < 0
+    {
+        return Err(AppError::InvalidRequest(
+            "Token TTLs must be positive".into(),
+        ));
+    }
+    if req.auto_refresh_threshold_secs >= req.access_token_ttl_secs {
+        return Err(AppError::InvalidRequest(
+            "auto_refresh_threshold_secs must be less than access_token_ttl_secs".into(),
+        ));
+    }
+
     let config = state
-        .proxy_config_service
-        .update_jwt_config(
+        .jwt_config_service
+        .update_config(
             req.access_token_ttl_secs,
             req.refresh_token_ttl_secs,
             req.auto_refresh_threshold_secs,
         )
         .await?;
+
+    if let Some(ref jwt_validator) = state.jwt_validator {
+        if let Err(e) = jwt_validator.refresh_jwt_config().await {
+            tracing::error!("Failed to refresh JWT validator config: {}", e);
+        }
+    }
+    tracing::info!("JWT config updated");
+
     Ok(Json(config))
 }
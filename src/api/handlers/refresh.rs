@@ -1,8 +1,15 @@
-use axum::{extract::State, Json};
+use std::net::SocketAddr;
+
+use axum::{
+    extract::{ConnectInfo, State},
+    http::HeaderMap,
+    Json,
+};
 use serde::{Deserialize, Serialize};
 
+use super::sessions::session_metadata;
 use crate::api::AppState;
-use crate::error::Result;
+use crate::error::{AppError, Result};
 
 #[derive(Deserialize)]
 pub struct RefreshRequest {
@@ -18,6 +25,8 @@ pub struct RefreshResponse {
 
 pub async fn refresh(
     State(state): State<AppState>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
     Json(req): Json<RefreshRequest>,
 ) -> Result<Json<RefreshResponse>> {
     let claims = state
@@ -29,21 +38,27 @@ pub async fn refresh(
         .user_service
         .find_by_id(claims.sub)
         .await?
-        .ok_or(crate::error::AppError::InvalidToken)?;
+        .ok_or(AppError::InvalidToken)?;
+
+    if !user.is_active {
+        return Err(AppError::AccountDisabled);
+    }
 
-    state
+    let (refresh, auth) = state
         .token_service
-        .revoke_refresh_token(&req.refresh_token)
+        .rotate_refresh_token(
+            &req.refresh_token,
+            user.id,
+            &session_metadata(&headers, connect_info.as_ref()),
+        )
         .await?;
-
     let access = state
         .token_service
-        .generate_access_token(user.id, &user.email)
+        .generate_access_token(user.id, &user.email, &auth)
         .await?;
-    let refresh = state.token_service.generate_refresh_token(user.id).await?;
 
     let access_claims = state.token_service.validate_access_token(&access).await?;
-    let should_refresh = state.token_service.should_refresh(&access_claims);
+    let should_refresh = state.token_service.should_refresh(&access_claims).await?;
 
     Ok(Json(RefreshResponse {
         access_token: access,
@@ -0,0 +1,145 @@
+use std::net::SocketAddr;
+
+use axum::{
+    extract::{ConnectInfo, Path, State},
+    http::HeaderMap,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+use uuid::Uuid;
+
+use super::logout::authenticate;
+use crate::api::middleware::client_ip;
+use crate::api::AppState;
+use crate::error::{AppError, Result};
+use crate::models::{Session, SessionMetadata};
+
+const MAX_LABEL_LEN: usize = 100;
+
+#[derive(Serialize)]
+pub struct SessionsResponse {
+    pub sessions: Vec<Session>,
+}
+
+#[derive(Deserialize)]
+pub struct UpdateSessionRequest {
+    pub label: Option<String>,
+}
+
+/// Device details recorded with a refresh token issued for this request.
+pub(crate) fn session_metadata(
+    headers: &HeaderMap,
+    connect_info: Option<&ConnectInfo<SocketAddr>>,
+) -> SessionMetadata {
+    SessionMetadata {
+        user_agent: headers
+            .get("user-agent")
+            .and_then(|v| v.to_str().ok())
+            .filter(|s| !s.is_empty())
+            .map(String::from),
+        ip_address: client_ip(headers, connect_info),
+    }
+}
+
+fn normalize_label(label: Option<String>) -> Result<Option<String>> {
+    let label = label
+        .map(|l| l.trim().to_string())
+        .filter(|l| !l.is_empty());
+    if let Some(ref l) = label {
+        if l.chars().count() > MAX_LABEL_LEN {
+            return Err(AppError::InvalidRequest(format!(
+                "Label must be at most {} characters",
+                MAX_LABEL_LEN
+            )));
+        }
+    }
+    Ok(label)
+}
+
+pub async fn list_sessions(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<SessionsResponse>> {
+    let claims = authenticate(&state, &headers).await?;
+    let sessions = state.token_service.list_sessions(claims.sub).await?;
+    Ok(Json(SessionsResponse { sessions }))
+}
+
+pub async fn update_session(
+    State(state): State<AppState>,
+    Path(session_id): Path<Uuid>,
+    headers: HeaderMap,
+    Json(req): Json<UpdateSessionRequest>,
+) -> Result<Json<serde_json::Value>> {
+    let claims = authenticate(&state, &headers).await?;
+    let label = normalize_label(req.label)?;
+
+    if !state
+        .token_service
+        .update_session_label(claims.sub, session_id, label.as_deref())
+        .await?
+    {
+        return Err(AppError::NotFound);
+    }
+
+    Ok(Json(serde_json::json!({ "success": true })))
+}
+
+/// Signs out one device. Its access token stays valid until it expires.
+pub async fn revoke_session(
+    State(state): State<AppState>,
+    Path(session_id): Path<Uuid>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>> {
+    let claims = authenticate(&state, &headers).await?;
+
+    if !state
+        .token_service
+        .revoke_session(claims.sub, session_id)
+        .await?
+    {
+        return Err(AppError::NotFound);
+    }
+
+    info!(user_id = %claims.sub, session_id = %session_id, "Session revoked");
+
+    Ok(Json(serde_json::json!({ "success": true })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_label() {
+        assert_eq!(normalize_label(None).unwrap(), None);
+        assert_eq!(normalize_label(Some("   ".into())).unwrap(), None);
+        assert_eq!(
+            normalize_label(Some(" Work laptop ".into())).unwrap(),
+            Some("Work laptop".to_string())
+        );
+        assert!(normalize_label(Some("x".repeat(MAX_LABEL_LEN + 1))).is_err());
+    }
+
+    #[test]
+    fn test_session_metadata_without_connect_info() {
+        let mut headers = HeaderMap::new();
+        headers.insert("user-agent", "Mozilla/5.0".parse().unwrap());
+
+        let metadata = session_metadata(&headers, None);
+        assert_eq!(metadata.user_agent.as_deref(), Some("Mozilla/5.0"));
+        assert_eq!(metadata.ip_address, None);
+    }
+
+    #[test]
+    fn test_session_metadata_uses_real_ip_from_gateway() {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-real-ip", "203.0.113.7".parse().unwrap());
+        let connect_info = ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 40000)));
+
+        let metadata = session_metadata(&headers, Some(&connect_info));
+        assert_eq!(metadata.user_agent, None);
+        assert_eq!(metadata.ip_address.as_deref(), Some("203.0.113.7"));
+    }
+}
@@ -1,10 +1,17 @@
-use axum::{extract::State, Json};
+use std::net::SocketAddr;
+
+use axum::{
+    extract::{ConnectInfo, State},
+    http::HeaderMap,
+    Json,
+};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+use super::sessions::session_metadata;
 use crate::api::AppState;
-use crate::error::Result;
-use crate::models::UserInfo;
+use crate::error::{AppError, Result};
+use crate::models::{AuthContext, UserInfo, AMR_PASSWORD};
 
 #[derive(Deserialize)]
 pub struct SrpInitRequest {
@@ -25,12 +32,19 @@ pub struct SrpVerifyRequest {
     pub client_proof: String,
 }
 
+/// Tokens are omitted when the user has 2FA enabled; the login is then
+/// completed at `/auth/login/mfa` with `mfa_token`.
 #[derive(Serialize)]
 pub struct SrpVerifyResponse {
     pub user: UserInfo,
     pub server_proof: String,
-    pub access_token: String,
-    pub refresh_token: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub access_token: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refresh_token: Option<String>,
+    pub mfa_required: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mfa_token: Option<String>,
 }
 
 pub async fn srp_init(
@@ -51,31 +65,66 @@ pub async fn srp_init(
 
 pub async fn srp_verify(
     State(state): State<AppState>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
     Json(req): Json<SrpVerifyRequest>,
 ) -> Result<Json<SrpVerifyResponse>> {
     let session_id = req
         .session_id
         .parse::<Uuid>()
-        .map_err(|_| crate::error::AppError::InvalidRequest("Invalid session_id".into()))?;
+        .map_err(|_| AppError::InvalidRequest("Invalid session_id".into()))?;
 
     let (user_id, email, server_proof) = state
         .srp_service
         .verify_login(session_id, &req.client_proof)
         .await?;
 
+    let user = state
+        .user_service
+        .find_by_id(user_id)
+        .await?
+        .ok_or(AppError::InvalidCredentials)?;
+    if !user.is_active {
+        return Err(AppError::AccountDisabled);
+    }
+
+    let user_info = UserInfo {
+        id: user_id.to_string(),
+        email: email.clone(),
+    };
+
+    if state.mfa_service.is_enabled(user_id).await? {
+        let challenge = state.mfa_service.create_challenge(user_id).await?;
+        return Ok(Json(SrpVerifyResponse {
+            user: user_info,
+            server_proof,
+            access_token: None,
+            refresh_token: None,
+            mfa_required: true,
+            mfa_token: Some(challenge.to_string()),
+        }));
+    }
+
+    let auth = AuthContext::new(vec![AMR_PASSWORD.to_string()]);
     let access = state
         .token_service
-        .generate_access_token(user_id, &email)
+        .generate_access_token(user_id, &email, &auth)
+        .await?;
+    let refresh = state
+        .token_service
+        .generate_refresh_token(
+            user_id,
+            &auth,
+            &session_metadata(&headers, connect_info.as_ref()),
+        )
         .await?;
-    let refresh = state.token_service.generate_refresh_token(user_id).await?;
 
     Ok(Json(SrpVerifyResponse {
-        user: UserInfo {
-            id: user_id.to_string(),
-            email,
-        },
+        user: user_info,
         server_proof,
-        access_token: access,
-        refresh_token: refresh,
+        access_token: Some(access),
+        refresh_token: Some(refresh),
+        mfa_required: false,
+        mfa_token: None,
     }))
 }
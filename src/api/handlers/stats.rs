@@ -9,6 +9,15 @@ use uuid::Uuid;
 
 use crate::api::AppState;
 use crate::error::{AppError, Result};
+use crate::gateway::circuit_breaker::CircuitStatus;
+use crate::gateway::concurrency::RouteConcurrency;
+use crate::gateway::connections::ActiveConnections;
+use crate::gateway::upstream_pool::PoolStatus;
+use crate::models::Session;
+
+const MAX_ROLE_LEN: usize = 64;
+
+type UserRow = (Uuid, String, bool, bool, DateTime<Utc>, Vec<String>);
 
 #[derive(Serialize)]
 pub struct StatsResponse {
@@ -16,6 +25,14 @@ pub struct StatsResponse {
     pub total_requests: u64,
     pub system_status: String,
     pub server_start_time: DateTime<Utc>,
+    /// Open WebSocket and SSE connections held by users or API keys
+    pub active_connections: usize,
+}
+
+#[derive(Serialize)]
+pub struct ConnectionStatsResponse {
+    pub total: usize,
+    pub connections: Vec<ActiveConnections>,
 }
 
 #[derive(Serialize)]
@@ -25,6 +42,7 @@ pub struct UserListItem {
     pub email_verified: bool,
     pub is_active: bool,
     pub created_at: DateTime<Utc>,
+    pub roles: Vec<String>,
 }
 
 #[derive(Serialize)]
@@ -33,6 +51,13 @@ pub struct UserListResponse {
     pub total: i64,
 }
 
+#[derive(Serialize)]
+pub struct UserDetailResponse {
+    #[serde(flatten)]
+    pub user: UserListItem,
+    pub sessions: Vec<Session>,
+}
+
 #[derive(Serialize)]
 pub struct ActivityItem {
     pub id: String,
@@ -67,12 +92,89 @@ pub async fn get_stats(State(state): State<AppState>) -> Result<Json<StatsRespon
         total_requests,
         system_status: "healthy".to_string(),
         server_start_time: get_server_start_time(),
+        active_connections: state
+            .connection_tracker
+            .as_ref()
+            .map(|t| t.total())
+            .unwrap_or(0),
+    }))
+}
+
+/// Health and in-flight requests of every upstream pool target.
+pub async fn get_upstream_stats(State(state): State<AppState>) -> Result<Json<Vec<PoolStatus>>> {
+    let pools = state
+        .config_cache
+        .as_ref()
+        .map(|c| c.upstream_pool_status())
+        .unwrap_or_default();
+    Ok(Json(pools))
+}
+
+/// Circuit breaker state of every upstream address with recent failures.
+pub async fn get_circuit_stats(State(state): State<AppState>) -> Result<Json<Vec<CircuitStatus>>> {
+    let circuits = state
+        .circuit_breaker
+        .as_ref()
+        .map(|b| b.snapshot(std::time::Instant::now()))
+        .unwrap_or_default();
+    Ok(Json(circuits))
+}
+
+/// Requests in flight and queued on routes with a concurrency limit.
+pub async fn get_concurrency_stats(
+    State(state): State<AppState>,
+) -> Result<Json<Vec<RouteConcurrency>>> {
+    let routes = state
+        .concurrency_limiter
+        .as_ref()
+        .map(|l| l.snapshot())
+        .unwrap_or_default();
+    Ok(Json(routes))
+}
+
+/// Open WebSocket and SSE connections per route and user or API key.
+pub async fn get_connection_stats(
+    State(state): State<AppState>,
+) -> Result<Json<ConnectionStatsResponse>> {
+    let connections = state
+        .connection_tracker
+        .as_ref()
+        .map(|t| t.snapshot())
+        .unwrap_or_default();
+
+    Ok(Json(ConnectionStatsResponse {
+        total: connections.iter().map(|c| c.connections).sum(),
+        connections,
     }))
 }
 
+/// Role names are case-sensitive identifiers such as `admin` or `team:billing`.
+pub(super) fn is_valid_role(role: &str) -> bool {
+    !role.is_empty()
+        && role.len() <= MAX_ROLE_LEN
+        && role
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.' | ':'))
+}
+
+fn normalize_roles(roles: Vec<String>) -> Result<Vec<String>> {
+    let mut roles: Vec<String> = roles.into_iter().map(|r| r.trim().to_string()).collect();
+    if let Some(bad) = roles.iter().find(|r| !is_valid_role(r)) {
+        return Err(AppError::InvalidRequest(format!(
+            "Invalid role '{}': use 1-{} characters from A-Z, a-z, 0-9, '_', '-', '.', ':'",
+            bad, MAX_ROLE_LEN
+        )));
+    }
+    roles.sort();
+    roles.dedup();
+    Ok(roles)
+}
+
 pub async fn get_users(State(state): State<AppState>) -> Result<Json<UserListResponse>> {
-    let users: Vec<(uuid::Uuid, String, bool, bool, DateTime<Utc>)> = sqlx::query_as(
-        "SELECT id, email, email_verified, is_active, created_at FROM users ORDER BY created_at DESC LIMIT 100"
+    let users: Vec<UserRow> = sqlx::query_as(
+        "SELECT id, email, email_verified, is_active, created_at, 
+         ARRAY(SELECT role FROM user_roles WHERE user_id = users.id ORDER BY role)::TEXT[] 
+         FROM users ORDER BY created_at DESC LIMIT 100",
     )
     .fetch_all(state.db_pool.as_ref())
     .await?;
@@ -84,12 +186,13 @@ pub async fn get_users(State(state): State<AppState>) -> Result<Json<UserListRes
     let user_list: Vec<UserListItem> = users
         .into_iter()
         .map(
-            |(id, email, email_verified, is_active, created_at)| UserListItem {
+            |(id, email, email_verified, is_active, created_at, roles)| UserListItem {
                 id: id.to_string(),
                 email,
                 email_verified,
                 is_active,
                 created_at,
+                roles,
             },
         )
         .collect();
@@ -100,6 +203,31 @@ pub async fn get_users(State(state): State<AppState>) -> Result<Json<UserListRes
     }))
 }
 
+pub async fn get_user(
+    State(state): State<AppState>,
+    Path(user_id): Path<Uuid>,
+) -> Result<Json<UserDetailResponse>> {
+    let user = state
+        .user_service
+        .find_by_id(user_id)
+        .await?
+        .ok_or(AppError::NotFound)?;
+    let roles = state.user_service.get_roles(user_id).await?;
+    let sessions = state.token_service.list_sessions(user_id).await?;
+
+    Ok(Json(UserDetailResponse {
+        user: UserListItem {
+            id: user.id.to_string(),
+            email: user.email,
+            email_verified: user.email_verified,
+            is_active: user.is_active,
+            created_at: user.created_at,
+            roles,
+        },
+        sessions,
+    }))
+}
+
 pub async fn get_activities(State(state): State<AppState>) -> Result<Json<ActivitiesResponse>> {
     let codes: Vec<(String, String, DateTime<Utc>)> = sqlx::query_as(
         "SELECT email, code_type, created_at FROM verification_codes 
@@ -147,21 +275,72 @@ pub async fn update_user_status(
         return Err(AppError::NotFound);
     }
 
+    if let Some(ref cache) = state.revocation_cache {
+        if req.is_active {
+            cache.restore_user(user_id);
+        } else {
+            cache.revoke_user(user_id, None);
+        }
+    }
+
     Ok(Json(serde_json::json!({ "success": true })))
 }
 
-pub async fn delete_user(
+#[derive(Deserialize)]
+pub struct UpdateUserRolesRequest {
+    pub roles: Vec<String>,
+}
+
+/// Replaces a user's roles. They reach the gateway with the user's next
+/// access token, i.e. on the next login or refresh.
+pub async fn update_user_roles(
     State(state): State<AppState>,
     Path(user_id): Path<Uuid>,
+    Json(req): Json<UpdateUserRolesRequest>,
 ) -> Result<Json<serde_json::Value>> {
-    let result = sqlx::query("DELETE FROM users WHERE id = $1")
-        .bind(user_id)
-        .execute(state.db_pool.as_ref())
-        .await?;
+    let roles = normalize_roles(req.roles)?;
+    if state.user_service.find_by_id(user_id).await?.is_none() {
+        return Err(AppError::NotFound);
+    }
+    state.user_service.set_roles(user_id, &roles).await?;
 
-    if result.rows_affected() == 0 {
+    Ok(Json(serde_json::json!({ "success": true, "roles": roles })))
+}
+
+pub async fn delete_user(
+    State(state): State<AppState>,
+    Path(user_id): Path<Uuid>,
+) -> Result<Json<serde_json::Value>> {
+    // Tokens signed before the deletion live at most as long as the longest
+    // token TTL, which is also how long rotated-out keys keep verifying
+    let expires_at = Utc::now() + state.jwt_config_service.key_grace_period().await?;
+    if !state.user_service.delete(user_id, expires_at).await? {
         return Err(AppError::NotFound);
     }
+    if let Some(ref cache) = state.revocation_cache {
+        cache.revoke_user(user_id, Some(expires_at.timestamp()));
+    }
 
     Ok(Json(serde_json::json!({ "success": true })))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_valid_role() {
+        assert!(is_valid_role("admin"));
+        assert!(is_valid_role("team:billing.read-only_2"));
+        assert!(!is_valid_role(""));
+        assert!(!is_valid_role("has space"));
+        assert!(!is_valid_role(&"r".repeat(MAX_ROLE_LEN + 1)));
+    }
+
+    #[test]
+    fn test_normalize_roles() {
+        let roles = normalize_roles(vec![" ops ".into(), "admin".into(), "ops".into()]).unwrap();
+        assert_eq!(roles, vec!["admin".to_string(), "ops".to_string()]);
+        assert!(normalize_roles(vec!["".into()]).is_err());
+    }
+}
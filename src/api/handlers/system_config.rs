@@ -1,9 +1,15 @@
-use axum::{extract::State, Json};
+use axum::{
+    extract::{Path, State},
+    Json,
+};
 use serde::{Deserialize, Serialize};
+use uuid::Uuid;
 
 use crate::api::AppState;
-use crate::error::Result;
-use crate::models::{JwtSecretInfo, SmtpConfig};
+use crate::error::{AppError, Result};
+use crate::identity_assertion::IdentitySigner;
+use crate::models::{IdentityKey, JwtKeyInfo, JwtSecretInfo, SmtpConfig};
+use crate::services::signing_key;
 
 #[derive(Deserialize)]
 pub struct UpdateSmtpConfigRequest {
@@ -14,6 +20,13 @@ pub struct UpdateSmtpConfigRequest {
 #[derive(Deserialize)]
 pub struct RotateJwtSecretRequest {
     pub confirmation: String,
+    /// Skip the grace period and invalidate tokens signed with older keys
+    /// immediately, e.g. after a key leak.
+    #[serde(default)]
+    pub retire_previous: bool,
+    /// Access token signing algorithm for the new key (`HS256`, `EdDSA` or
+    /// `RS256`); defaults to the current key's algorithm.
+    pub algorithm: Option<String>,
 }
 
 #[derive(Serialize)]
@@ -70,7 +83,11 @@ pub async fn get_jwt_secret_info(State(state): State<AppState>) -> Result<Json<J
         .system_config_service
         .get_jwt_secret_updated_at()
         .await?;
-    Ok(Json(JwtSecretInfo { updated_at }))
+    let keys = state.system_config_service.get_jwt_keys().await?;
+    Ok(Json(JwtSecretInfo {
+        updated_at,
+        keys: keys.iter().map(JwtKeyInfo::from).collect(),
+    }))
 }
 
 pub async fn rotate_jwt_secret(
@@ -78,12 +95,33 @@ pub async fn rotate_jwt_secret(
     Json(req): Json<RotateJwtSecretRequest>,
 ) -> Result<Json<RotateJwtSecretResponse>> {
     if req.confirmation != "确定刷新" {
-        return Err(crate::error::AppError::InvalidRequest(
+        return Err(AppError::InvalidRequest(
             "Please type '确定刷新' to confirm".into(),
         ));
     }
 
-    let updated_at = state.system_config_service.rotate_jwt_secret().await?;
+    let algorithm = match req.algorithm.as_deref() {
+        Some(name) => Some(signing_key::parse_algorithm(name).ok_or_else(|| {
+            AppError::InvalidRequest("algorithm must be one of: HS256, EdDSA, RS256".into())
+        })?),
+        None => None,
+    };
+
+    let grace = state.jwt_config_service.key_grace_period().await?;
+    let updated_at = state
+        .system_config_service
+        .rotate_jwt_secret(algorithm, grace)
+        .await?;
+
+    let message = if req.retire_previous {
+        state.system_config_service.retire_jwt_keys(true).await?;
+        "JWT secret rotated. All existing tokens are now invalid.".to_string()
+    } else {
+        format!(
+            "JWT secret rotated. Existing tokens remain valid for up to {} hours.",
+            grace.num_hours()
+        )
+    };
 
     if let Some(ref jwt_validator) = state.jwt_validator {
         jwt_validator.refresh_secret().await.ok();
@@ -91,6 +129,101 @@ pub async fn rotate_jwt_secret(
 
     Ok(Json(RotateJwtSecretResponse {
         updated_at,
-        message: "JWT secret rotated. All existing tokens are now invalid.".into(),
+        message,
+    }))
+}
+
+#[derive(Deserialize)]
+pub struct RotateIdentityKeyRequest {
+    pub upstream_address: String,
+}
+
+#[derive(Serialize)]
+pub struct RotateIdentityKeyResponse {
+    pub identity_key: IdentityKey,
+    /// Shown once; configure it in the upstream's identity verifier
+    pub secret: String,
+}
+
+#[derive(Serialize)]
+pub struct IdentityKeyListResponse {
+    pub identity_keys: Vec<IdentityKey>,
+}
+
+async fn refresh_identity_signers(state: &AppState) {
+    if let Some(ref cache) = state.config_cache {
+        if let Ok(keys) = state.system_config_service.list_identity_keys().await {
+            let signers = keys
+                .into_iter()
+                .map(|k| {
+                    let signer = IdentitySigner::new(&k.kid, k.secret.as_bytes());
+                    (k.upstream_address, signer)
+                })
+                .collect();
+            cache.update_identity_signers(signers);
+            tracing::info!("Identity key cache refreshed");
+        }
+    }
+}
+
+pub async fn list_identity_keys(
+    State(state): State<AppState>,
+) -> Result<Json<IdentityKeyListResponse>> {
+    let identity_keys = state.system_config_service.list_identity_keys().await?;
+    Ok(Json(IdentityKeyListResponse { identity_keys }))
+}
+
+/// Creates the key the gateway signs `X-Arc-Identity` with for one upstream,
+/// or a pending next key if it already has one.
+pub async fn rotate_identity_key(
+    State(state): State<AppState>,
+    Json(req): Json<RotateIdentityKeyRequest>,
+) -> Result<Json<RotateIdentityKeyResponse>> {
+    let upstream_address = req.upstream_address.trim();
+    if upstream_address.is_empty() || upstream_address.len() > 255 {
+        return Err(AppError::InvalidRequest(
+            "upstream_address must be 1-255 characters".into(),
+        ));
+    }
+
+    let identity_key = state
+        .system_config_service
+        .rotate_identity_key(upstream_address)
+        .await?;
+    refresh_identity_signers(&state).await;
+
+    Ok(Json(RotateIdentityKeyResponse {
+        secret: identity_key
+            .next_secret
+            .clone()
+            .unwrap_or_else(|| identity_key.secret.clone()),
+        identity_key,
     }))
 }
+
+/// Switches the gateway to the pending key of an upstream.
+pub async fn promote_identity_key(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<IdentityKey>> {
+    let identity_key = state
+        .system_config_service
+        .promote_identity_key(id)
+        .await?
+        .ok_or(AppError::NotFound)?;
+    refresh_identity_signers(&state).await;
+
+    Ok(Json(identity_key))
+}
+
+pub async fn delete_identity_key(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<serde_json::Value>> {
+    if !state.system_config_service.delete_identity_key(id).await? {
+        return Err(AppError::NotFound);
+    }
+    refresh_identity_signers(&state).await;
+
+    Ok(Json(serde_json::json!({ "success": true })))
+}
@@ -0,0 +1,128 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::HeaderMap,
+    Json,
+};
+use chrono::{Duration, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::logout::authenticate;
+use crate::api::AppState;
+use crate::error::{AppError, Result};
+use crate::gateway::connections::ConnectionSubject;
+use crate::gateway::usage::day_start;
+use crate::models::UsageDay;
+use crate::services::usage::USAGE_RETENTION_DAYS;
+
+const DEFAULT_USAGE_DAYS: i64 = 30;
+const MAX_USAGE_DAYS: i64 = USAGE_RETENTION_DAYS;
+
+#[derive(Deserialize)]
+pub struct UsageQuery {
+    pub days: Option<i64>,
+}
+
+/// Requests on one route in the current UTC day and month, with its quotas.
+#[derive(Serialize)]
+pub struct RouteUsage {
+    pub route: String,
+    pub requests_today: u64,
+    pub requests_this_month: u64,
+    pub daily_quota: Option<u64>,
+    pub monthly_quota: Option<u64>,
+}
+
+#[derive(Serialize)]
+pub struct UsageResponse {
+    /// Live counts for every route the subject used this month or that has a quota
+    pub current: Vec<RouteUsage>,
+    /// Stored usage per route and UTC day, oldest first
+    pub days: Vec<UsageDay>,
+}
+
+async fn usage_report(
+    state: &AppState,
+    subject: ConnectionSubject,
+    days: Option<i64>,
+) -> Result<UsageResponse> {
+    let days = days.unwrap_or(DEFAULT_USAGE_DAYS);
+    if !(1..=MAX_USAGE_DAYS).contains(&days) {
+        return Err(AppError::InvalidRequest(format!(
+            "days must be between 1 and {}",
+            MAX_USAGE_DAYS
+        )));
+    }
+    let now = Utc::now();
+    let since = day_start(now) - Duration::days(days - 1);
+    let history = state.usage_service.daily_usage(&subject, since).await?;
+
+    let quotas = state
+        .config_cache
+        .as_ref()
+        .map(|c| c.route_quotas())
+        .unwrap_or_default();
+    let mut current: Vec<RouteUsage> = state
+        .usage_meter
+        .as_ref()
+        .map(|m| m.usage(&subject, now))
+        .unwrap_or_default()
+        .into_iter()
+        .map(|u| RouteUsage {
+            route: u.route,
+            requests_today: u.requests_today,
+            requests_this_month: u.requests_this_month,
+            daily_quota: None,
+            monthly_quota: None,
+        })
+        .collect();
+    for (route, quota) in quotas {
+        let usage = match current.iter_mut().find(|u| u.route == route) {
+            Some(usage) => usage,
+            None => {
+                current.push(RouteUsage {
+                    route,
+                    requests_today: 0,
+                    requests_this_month: 0,
+                    daily_quota: None,
+                    monthly_quota: None,
+                });
+                current.last_mut().expect("just pushed")
+            }
+        };
+        usage.daily_quota = quota.daily;
+        usage.monthly_quota = quota.monthly;
+    }
+    current.sort_by(|a, b| a.route.cmp(&b.route));
+
+    Ok(UsageResponse {
+        current,
+        days: history,
+    })
+}
+
+/// The caller's usage of proxied routes.
+pub async fn get_my_usage(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<UsageQuery>,
+) -> Result<Json<UsageResponse>> {
+    let claims = authenticate(&state, &headers).await?;
+    let subject = ConnectionSubject::User(claims.sub.to_string());
+    Ok(Json(usage_report(&state, subject, query.days).await?))
+}
+
+/// Usage report of one user for admins.
+pub async fn get_user_usage(
+    State(state): State<AppState>,
+    Path(user_id): Path<Uuid>,
+    Query(query): Query<UsageQuery>,
+) -> Result<Json<UsageResponse>> {
+    state
+        .user_service
+        .find_by_id(user_id)
+        .await?
+        .ok_or(AppError::NotFound)?;
+    let subject = ConnectionSubject::User(user_id.to_string());
+    Ok(Json(usage_report(&state, subject, query.days).await?))
+}
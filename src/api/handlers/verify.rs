@@ -1,10 +1,17 @@
-use axum::{extract::State, Json};
+use std::net::SocketAddr;
+
+use axum::{
+    extract::{ConnectInfo, State},
+    http::HeaderMap,
+    Json,
+};
 use serde::{Deserialize, Serialize};
 use tracing::warn;
 
+use super::sessions::session_metadata;
 use crate::api::AppState;
 use crate::error::{AppError, Result};
-use crate::models::UserInfo;
+use crate::models::{AuthContext, UserInfo, AMR_PASSWORD};
 
 const MAX_VERIFICATION_ATTEMPTS: i32 = 5;
 
@@ -25,6 +32,8 @@ pub struct AuthResponse {
 
 pub async fn verify(
     State(state): State<AppState>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
     Json(req): Json<VerifyRequest>,
 ) -> Result<Json<AuthResponse>> {
     let mut tx = state.db_pool.begin().await?;
@@ -77,11 +86,19 @@ pub async fn verify(
 
     tx.commit().await?;
 
+    let auth = AuthContext::new(vec![AMR_PASSWORD.to_string()]);
     let access = state
         .token_service
-        .generate_access_token(user_id, &req.email)
+        .generate_access_token(user_id, &req.email, &auth)
+        .await?;
+    let refresh = state
+        .token_service
+        .generate_refresh_token(
+            user_id,
+            &auth,
+            &session_metadata(&headers, connect_info.as_ref()),
+        )
         .await?;
-    let refresh = state.token_service.generate_refresh_token(user_id).await?;
 
     Ok(Json(AuthResponse {
         user: UserInfo {
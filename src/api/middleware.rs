@@ -10,7 +10,7 @@ use std::{
 
 use axum::{
     extract::{ConnectInfo, Request},
-    http::StatusCode,
+    http::{HeaderMap, StatusCode},
     middleware::Next,
     response::{IntoResponse, Response},
     Json,
@@ -159,18 +159,24 @@ const TRUSTED_PROXIES: &[&str] = &[
 ];
 
 fn extract_client_ip(request: &Request) -> Option<String> {
-    let connect_ip = request
-        .extensions()
-        .get::<ConnectInfo<SocketAddr>>()
-        .map(|ci| ci.0.ip());
+    client_ip(
+        request.headers(),
+        request.extensions().get::<ConnectInfo<SocketAddr>>(),
+    )
+}
 
-    let connect_ip = connect_ip?;
+/// Client IP of a request, honouring `X-Real-IP`/`X-Forwarded-For` only when
+/// the connection comes from a trusted proxy.
+pub fn client_ip(
+    headers: &HeaderMap,
+    connect_info: Option<&ConnectInfo<SocketAddr>>,
+) -> Option<String> {
+    let connect_ip = connect_info.map(|ci| ci.0.ip())?;
 
     let is_trusted = is_trusted_proxy(&connect_ip);
 
     if is_trusted {
-        if let Some(real_ip) = request
-            .headers()
+        if let Some(real_ip) = headers
             .get("x-real-ip")
             .and_then(|v| v.to_str().ok())
             .filter(|s| !s.is_empty())
@@ -178,11 +184,7 @@ fn extract_client_ip(request: &Request) -> Option<String> {
             return Some(real_ip.trim().to_string());
         }
 
-        if let Some(forwarded) = request
-            .headers()
-            .get("x-forwarded-for")
-            .and_then(|v| v.to_str().ok())
-        {
+        if let Some(forwarded) = headers.get("x-forwarded-for").and_then(|v| v.to_str().ok()) {
             if let Some(first_ip) = forwarded.split(',').next() {
                 let ip = first_ip.trim();
                 if !ip.is_empty() {
@@ -1,6 +1,6 @@
 use axum::{
     middleware as axum_middleware,
-    routing::{get, post, put},
+    routing::{get, patch, post, put},
     Router,
 };
 use sqlx::PgPool;
@@ -11,10 +11,13 @@ use middleware::{rate_limit_middleware, request_counter_middleware, RateLimiter}
 
 use axum::routing::delete;
 
-use crate::gateway::{JwtValidator, ProxyConfigCache};
+use crate::gateway::{
+    ApiKeyCache, CircuitBreaker, ConcurrencyLimiter, ConnectionTracker, JwtValidator,
+    ProxyConfigCache, RateLimitCache, RevocationCache, UsageMeter,
+};
 use crate::services::{
-    AdminService, ApiKeyService, CaptchaService, EmailService, ProxyConfigService, SrpService,
-    SystemConfigService, TokenService, UserService,
+    AdminService, ApiKeyService, CaptchaService, EmailService, JwtConfigService, MfaService,
+    ProxyConfigService, SrpService, SystemConfigService, TokenService, UsageService, UserService,
 };
 
 pub mod handlers;
@@ -29,12 +32,22 @@ pub struct AppState {
     pub admin_service: Arc<AdminService>,
     pub proxy_config_service: Arc<ProxyConfigService>,
     pub system_config_service: Arc<SystemConfigService>,
+    pub jwt_config_service: Arc<JwtConfigService>,
     pub api_key_service: Arc<ApiKeyService>,
     pub srp_service: Arc<SrpService>,
+    pub mfa_service: Arc<MfaService>,
     pub captcha_service: Arc<CaptchaService>,
     pub captcha_enabled: bool,
     pub jwt_validator: Option<Arc<JwtValidator>>,
     pub config_cache: Option<Arc<ProxyConfigCache>>,
+    pub rate_limit_cache: Option<Arc<RateLimitCache>>,
+    pub revocation_cache: Option<Arc<RevocationCache>>,
+    pub api_key_cache: Option<Arc<ApiKeyCache>>,
+    pub connection_tracker: Option<Arc<ConnectionTracker>>,
+    pub concurrency_limiter: Option<Arc<ConcurrencyLimiter>>,
+    pub circuit_breaker: Option<Arc<CircuitBreaker>>,
+    pub usage_service: Arc<UsageService>,
+    pub usage_meter: Option<Arc<UsageMeter>>,
     pub request_counter: Arc<AtomicU64>,
 }
 
@@ -48,7 +61,23 @@ pub fn create_auth_router(state: AppState) -> Router {
         .route("/register/verify", post(handlers::verify))
         .route("/login/init", post(handlers::srp_init))
         .route("/login/verify", post(handlers::srp_verify))
+        .route("/login/mfa", post(handlers::login_mfa))
         .route("/refresh", post(handlers::refresh))
+        .route("/logout", post(handlers::logout))
+        .route("/logout/all", post(handlers::logout_all))
+        .route("/mfa/totp/setup", post(handlers::totp_setup))
+        .route("/mfa/totp/confirm", post(handlers::totp_confirm))
+        .route("/mfa/totp/disable", post(handlers::totp_disable))
+        .route(
+            "/mfa/recovery-codes",
+            post(handlers::regenerate_recovery_codes),
+        )
+        .route("/sessions", get(handlers::list_sessions))
+        .route("/usage", get(handlers::get_my_usage))
+        .route(
+            "/sessions/:id",
+            patch(handlers::update_session).delete(handlers::revoke_session),
+        )
         .route("/password/reset", post(handlers::request_password_reset))
         .route("/password/reset/confirm", post(handlers::reset_password))
         .route("/captcha", get(handlers::get_captcha))
@@ -58,6 +87,7 @@ pub fn create_auth_router(state: AppState) -> Router {
 
     Router::new()
         .nest("/auth", auth_routes)
+        .route("/.well-known/jwks.json", get(handlers::jwks))
         .with_state(state)
         .layer(axum_middleware::from_fn(move |req, next| {
             rate_limit_middleware(req, next, global_rate_limiter.clone())
@@ -76,11 +106,19 @@ pub fn create_admin_router(state: AppState) -> Router {
 
     let protected_admin_routes = Router::new()
         .route("/stats", get(handlers::get_stats))
+        .route("/stats/connections", get(handlers::get_connection_stats))
+        .route("/stats/upstreams", get(handlers::get_upstream_stats))
+        .route("/stats/circuits", get(handlers::get_circuit_stats))
+        .route("/stats/concurrency", get(handlers::get_concurrency_stats))
         .route("/users", get(handlers::get_users))
         .route(
             "/users/:id",
-            put(handlers::update_user_status).delete(handlers::delete_user),
+            get(handlers::get_user)
+                .put(handlers::update_user_status)
+                .delete(handlers::delete_user),
         )
+        .route("/users/:id/roles", put(handlers::update_user_roles))
+        .route("/users/:id/usage", get(handlers::get_user_usage))
         .route("/activities", get(handlers::get_activities))
         .layer(axum_middleware::from_fn_with_state(
             state.clone(),
@@ -116,6 +154,14 @@ pub fn create_admin_router(state: AppState) -> Router {
             "/routes/:id",
             put(handlers::update_route).delete(handlers::delete_route),
         )
+        .route(
+            "/upstream-pools",
+            get(handlers::list_upstream_pools).post(handlers::create_upstream_pool),
+        )
+        .route(
+            "/upstream-pools/:id",
+            put(handlers::update_upstream_pool).delete(handlers::delete_upstream_pool),
+        )
         .route(
             "/rate-limits",
             get(handlers::list_rate_limits).post(handlers::create_rate_limit),
@@ -136,6 +182,15 @@ pub fn create_admin_router(state: AppState) -> Router {
             "/jwt-secret",
             get(handlers::get_jwt_secret_info).post(handlers::rotate_jwt_secret),
         )
+        .route(
+            "/identity-keys",
+            get(handlers::list_identity_keys).post(handlers::rotate_identity_key),
+        )
+        .route("/identity-keys/:id", delete(handlers::delete_identity_key))
+        .route(
+            "/identity-keys/:id/promote",
+            post(handlers::promote_identity_key),
+        )
         .route(
             "/api-keys",
             get(handlers::list_api_keys).post(handlers::create_api_key),
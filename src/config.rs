@@ -1,6 +1,9 @@
 use serde::{Deserialize, Deserializer};
+use std::collections::BTreeMap;
 use std::sync::Arc;
 
+use crate::models::default_identity_headers;
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct AppConfig {
     pub server: ServerConfig,
@@ -11,6 +14,8 @@ pub struct AppConfig {
     pub routing: RoutesConfig,
     #[serde(default)]
     pub captcha: CaptchaConfig,
+    #[serde(default)]
+    pub metering: MeteringConfig,
 }
 
 fn deserialize_routes<'de, D>(deserializer: D) -> Result<Vec<RouteConfig>, D::Error>
@@ -40,15 +45,94 @@ pub struct ServerConfig {
 #[derive(Debug, Clone, Deserialize)]
 pub struct UpstreamConfig {
     pub default_upstream: String,
+    /// Seconds between DNS re-resolutions of every upstream address
+    #[serde(default = "default_dns_refresh_secs")]
+    pub dns_refresh_secs: u64,
+    /// Consecutive connect failures or 5xx responses that open an upstream's
+    /// circuit; 0 disables circuit breaking
+    #[serde(default = "default_circuit_failure_threshold")]
+    pub circuit_failure_threshold: u32,
+    /// Seconds an open circuit rejects requests before probing again
+    #[serde(default = "default_circuit_cooldown_secs")]
+    pub circuit_cooldown_secs: u64,
+}
+
+fn default_dns_refresh_secs() -> u64 {
+    30
+}
+
+fn default_circuit_failure_threshold() -> u32 {
+    5
+}
+
+fn default_circuit_cooldown_secs() -> u64 {
+    30
 }
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct RouteConfig {
+    /// Host name or `*.domain`; routes without a host serve any host
+    pub host: Option<String>,
     pub path: String,
+    /// `prefix` (default), `exact`, `regex` or `glob`
+    pub match_type: Option<String>,
+    /// Higher priorities are matched first
+    #[serde(default)]
+    pub priority: i32,
+    /// Allowed methods; empty allows every method
+    #[serde(default)]
+    pub methods: Vec<String>,
+    /// Upstream path template using the pattern's captures, e.g. `/v2/{1}`
+    pub rewrite: Option<String>,
     pub upstream: String,
     #[serde(default)]
     pub auth: bool,
+    /// `required`, `optional` or `none`; overrides `auth` when set
+    pub auth_mode: Option<String>,
     pub strip_prefix: Option<String>,
+    #[serde(default)]
+    pub max_auth_age_secs: Option<i64>,
+    #[serde(default)]
+    pub require_mfa: bool,
+    #[serde(default)]
+    pub required_roles: Vec<String>,
+    #[serde(default = "default_identity_headers")]
+    pub identity_headers: BTreeMap<String, String>,
+    pub token_query_param: Option<String>,
+    pub token_cookie: Option<String>,
+    #[serde(default)]
+    pub token_subprotocol: bool,
+    pub max_connection_secs: Option<i64>,
+    #[serde(default)]
+    pub close_at_token_expiry: bool,
+    pub max_connections_per_user: Option<usize>,
+    /// Upstream pool (managed in the admin API) used instead of `upstream`
+    pub upstream_pool: Option<String>,
+    pub connect_timeout_ms: Option<u64>,
+    pub read_timeout_ms: Option<u64>,
+    pub write_timeout_ms: Option<u64>,
+    pub idle_timeout_ms: Option<u64>,
+    #[serde(default)]
+    pub max_retries: usize,
+    #[serde(default = "default_upstream_keepalive")]
+    pub upstream_keepalive: bool,
+    pub max_in_flight: Option<usize>,
+    #[serde(default)]
+    pub max_queue: usize,
+    #[serde(default = "default_queue_timeout_ms")]
+    pub queue_timeout_ms: u64,
+    #[serde(default)]
+    pub fair_queue: bool,
+    pub daily_quota: Option<u64>,
+    pub monthly_quota: Option<u64>,
+}
+
+fn default_upstream_keepalive() -> bool {
+    true
+}
+
+fn default_queue_timeout_ms() -> u64 {
+    5000
 }
 
 #[derive(Debug, Clone, Deserialize, Default)]
@@ -63,6 +147,25 @@ pub struct CaptchaConfig {
     pub enabled: bool,
 }
 
+#[derive(Debug, Clone, Deserialize)]
+pub struct MeteringConfig {
+    /// Seconds between writes of metered usage to the database
+    #[serde(default = "default_usage_flush_secs")]
+    pub flush_secs: u64,
+}
+
+impl Default for MeteringConfig {
+    fn default() -> Self {
+        Self {
+            flush_secs: default_usage_flush_secs(),
+        }
+    }
+}
+
+fn default_usage_flush_secs() -> u64 {
+    10
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct DatabaseConfig {
     pub url: String,
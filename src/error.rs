@@ -37,6 +37,9 @@ pub enum AppError {
     #[error("Email not verified")]
     EmailNotVerified,
 
+    #[error("Account disabled")]
+    AccountDisabled,
+
     #[error("Email already exists")]
     EmailExists,
 
@@ -81,7 +84,9 @@ impl AppError {
             | Self::InvalidToken
             | Self::TokenExpired
             | Self::TokenRevoked => StatusCode::UNAUTHORIZED,
-            Self::EmailNotVerified | Self::Forbidden => StatusCode::FORBIDDEN,
+            Self::EmailNotVerified | Self::AccountDisabled | Self::Forbidden => {
+                StatusCode::FORBIDDEN
+            }
             Self::EmailExists => StatusCode::CONFLICT,
             Self::NotFound => StatusCode::NOT_FOUND,
             Self::RateLimited => StatusCode::TOO_MANY_REQUESTS,
@@ -101,6 +106,7 @@ impl AppError {
             Self::TokenExpired => "TOKEN_EXPIRED",
             Self::TokenRevoked => "TOKEN_REVOKED",
             Self::EmailNotVerified => "EMAIL_NOT_VERIFIED",
+            Self::AccountDisabled => "ACCOUNT_DISABLED",
             Self::EmailExists => "EMAIL_EXISTS",
             Self::NotFound => "NOT_FOUND",
             Self::RateLimited => "RATE_LIMITED",
@@ -159,6 +165,12 @@ impl AppError {
                     "Security: revoked token presented"
                 );
             }
+            Self::AccountDisabled => {
+                tracing::warn!(
+                    error_code = "ACCOUNT_DISABLED",
+                    "Security: disabled account access"
+                );
+            }
             Self::RateLimited => {
                 tracing::warn!(error_code = "RATE_LIMITED", "Security: rate limit exceeded");
             }
@@ -260,6 +272,10 @@ mod tests {
             AppError::EmailNotVerified.status_code(),
             StatusCode::FORBIDDEN
         );
+        assert_eq!(
+            AppError::AccountDisabled.status_code(),
+            StatusCode::FORBIDDEN
+        );
         assert_eq!(AppError::EmailExists.status_code(), StatusCode::CONFLICT);
         assert_eq!(
             AppError::RateLimited.status_code(),
@@ -278,6 +294,7 @@ mod tests {
         assert_eq!(AppError::TokenExpired.error_code(), "TOKEN_EXPIRED");
         assert_eq!(AppError::TokenRevoked.error_code(), "TOKEN_REVOKED");
         assert_eq!(AppError::RateLimited.error_code(), "RATE_LIMITED");
+        assert_eq!(AppError::AccountDisabled.error_code(), "ACCOUNT_DISABLED");
     }
 
     #[test]
@@ -0,0 +1,57 @@
+use std::collections::HashSet;
+use std::sync::RwLock;
+
+use crate::services::ApiKeyService;
+
+/// Hashes of the API keys that exist, so the gateway can tell a real
+/// `X-API-Key` from a made-up one without a database query per request.
+#[derive(Default)]
+pub struct ApiKeyCache {
+    hashes: RwLock<HashSet<String>>,
+}
+
+impl ApiKeyCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn update_keys(&self, hashes: Vec<String>) {
+        if let Ok(mut current) = self.hashes.write() {
+            *current = hashes.into_iter().collect();
+        }
+    }
+
+    /// Hash of `raw_key` if it belongs to an existing key.
+    pub fn verify(&self, raw_key: &str) -> Option<String> {
+        let hash = ApiKeyService::hash_key(raw_key);
+        match self.hashes.read() {
+            Ok(hashes) => hashes.contains(&hash).then_some(hash),
+            Err(e) => {
+                tracing::warn!("Failed to acquire API key lock: {}", e);
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_known_keys_verify() {
+        let cache = ApiKeyCache::new();
+        assert_eq!(cache.verify("real-key"), None);
+
+        cache.update_keys(vec![ApiKeyService::hash_key("real-key")]);
+        assert_eq!(
+            cache.verify("real-key"),
+            Some(ApiKeyService::hash_key("real-key"))
+        );
+        assert_eq!(cache.verify("made-up-key"), None);
+
+        // Deleted keys stop verifying on the next update
+        cache.update_keys(Vec::new());
+        assert_eq!(cache.verify("real-key"), None);
+    }
+}
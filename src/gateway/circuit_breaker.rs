@@ -0,0 +1,230 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+    /// Requests flow to the upstream
+    Closed,
+    /// The upstream failed repeatedly; requests are rejected until the cooldown ends
+    Open,
+    /// The cooldown ended and one probe request decides whether to close again
+    HalfOpen,
+}
+
+/// Whether a request may be sent to an upstream.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Admission {
+    Allow,
+    /// The circuit is open; retry after this long
+    Reject(Duration),
+}
+
+#[derive(Debug)]
+struct Circuit {
+    state: CircuitState,
+    consecutive_failures: u32,
+    /// When the circuit opened, or when the half-open probe was sent
+    since: Instant,
+}
+
+impl Circuit {
+    fn new(now: Instant) -> Self {
+        Self {
+            state: CircuitState::Closed,
+            consecutive_failures: 0,
+            since: now,
+        }
+    }
+}
+
+/// State of one upstream address, for the admin API.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CircuitStatus {
+    pub upstream: String,
+    pub state: CircuitState,
+    pub consecutive_failures: u32,
+    /// Seconds until an open circuit lets a probe through
+    pub retry_after_secs: Option<u64>,
+}
+
+/// Passive outlier detection: counts consecutive connect failures and 5xx
+/// responses per upstream address and stops sending requests to an upstream
+/// that keeps failing.
+pub struct CircuitBreaker {
+    /// Consecutive failures that open a circuit; 0 disables the breaker
+    failure_threshold: u32,
+    cooldown: Duration,
+    circuits: Mutex<HashMap<String, Circuit>>,
+}
+
+impl CircuitBreaker {
+    pub fn new(failure_threshold: u32, cooldown: Duration) -> Self {
+        Self {
+            failure_threshold,
+            cooldown,
+            circuits: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, Circuit>> {
+        match self.circuits.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    /// Decides whether a request may go to `upstream`. Once the cooldown of
+    /// an open circuit ends, one request is let through as a probe; another
+    /// probe is allowed if it has not finished within a cooldown.
+    pub fn admit(&self, upstream: &str, now: Instant) -> Admission {
+        if self.failure_threshold == 0 {
+            return Admission::Allow;
+        }
+        let mut circuits = self.lock();
+        let Some(circuit) = circuits.get_mut(upstream) else {
+            return Admission::Allow;
+        };
+        match circuit.state {
+            CircuitState::Closed => Admission::Allow,
+            CircuitState::Open | CircuitState::HalfOpen => {
+                let waited = now.saturating_duration_since(circuit.since);
+                if waited >= self.cooldown {
+                    circuit.state = CircuitState::HalfOpen;
+                    circuit.since = now;
+                    Admission::Allow
+                } else {
+                    Admission::Reject(self.cooldown - waited)
+                }
+            }
+        }
+    }
+
+    /// Whether `admit` would reject `upstream` right now.
+    pub fn is_open(&self, upstream: &str, now: Instant) -> bool {
+        if self.failure_threshold == 0 {
+            return false;
+        }
+        self.lock().get(upstream).is_some_and(|c| {
+            c.state != CircuitState::Closed
+                && now.saturating_duration_since(c.since) < self.cooldown
+        })
+    }
+
+    pub fn record_success(&self, upstream: &str) {
+        if self.failure_threshold == 0 {
+            return;
+        }
+        let mut circuits = self.lock();
+        // Healthy upstreams are not tracked
+        circuits.remove(upstream);
+    }
+
+    /// Counts a failure. Returns true when it opened the circuit.
+    pub fn record_failure(&self, upstream: &str, now: Instant) -> bool {
+        if self.failure_threshold == 0 {
+            return false;
+        }
+        let mut circuits = self.lock();
+        let circuit = circuits
+            .entry(upstream.to_string())
+            .or_insert_with(|| Circuit::new(now));
+        circuit.consecutive_failures = circuit.consecutive_failures.saturating_add(1);
+        let opens = match circuit.state {
+            CircuitState::Closed => circuit.consecutive_failures >= self.failure_threshold,
+            // The probe failed
+            CircuitState::HalfOpen => true,
+            CircuitState::Open => false,
+        };
+        if opens {
+            circuit.state = CircuitState::Open;
+            circuit.since = now;
+        }
+        opens
+    }
+
+    /// Upstreams with recent failures, open circuits first.
+    pub fn snapshot(&self, now: Instant) -> Vec<CircuitStatus> {
+        let circuits = self.lock();
+        let mut status: Vec<CircuitStatus> = circuits
+            .iter()
+            .map(|(upstream, c)| CircuitStatus {
+                upstream: upstream.clone(),
+                state: c.state,
+                consecutive_failures: c.consecutive_failures,
+                retry_after_secs: (c.state == CircuitState::Open).then(|| {
+                    self.cooldown
+                        .saturating_sub(now.saturating_duration_since(c.since))
+                        .as_secs()
+                }),
+            })
+            .collect();
+        status.sort_by(|a, b| {
+            (a.state == CircuitState::Closed)
+                .cmp(&(b.state == CircuitState::Closed))
+                .then_with(|| a.upstream.cmp(&b.upstream))
+        });
+        status
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COOLDOWN: Duration = Duration::from_secs(30);
+
+    #[test]
+    fn test_opens_after_consecutive_failures() {
+        let breaker = CircuitBreaker::new(3, COOLDOWN);
+        let now = Instant::now();
+        assert!(!breaker.record_failure("a:80", now));
+        assert!(!breaker.record_failure("a:80", now));
+        breaker.record_success("a:80");
+        assert!(!breaker.record_failure("a:80", now));
+        assert!(!breaker.record_failure("a:80", now));
+        assert_eq!(breaker.admit("a:80", now), Admission::Allow);
+
+        assert!(breaker.record_failure("a:80", now));
+        assert_eq!(breaker.admit("a:80", now), Admission::Reject(COOLDOWN));
+        assert!(breaker.is_open("a:80", now));
+        // Other upstreams are unaffected
+        assert_eq!(breaker.admit("b:80", now), Admission::Allow);
+    }
+
+    #[test]
+    fn test_half_open_probe() {
+        let breaker = CircuitBreaker::new(1, COOLDOWN);
+        let opened = Instant::now();
+        breaker.record_failure("a:80", opened);
+
+        let later = opened + COOLDOWN;
+        assert_eq!(breaker.admit("a:80", later), Admission::Allow);
+        // Only one probe at a time
+        assert!(matches!(breaker.admit("a:80", later), Admission::Reject(_)));
+
+        // A failed probe opens the circuit again
+        assert!(breaker.record_failure("a:80", later));
+        assert_eq!(breaker.snapshot(later)[0].state, CircuitState::Open);
+
+        let after = later + COOLDOWN;
+        assert_eq!(breaker.admit("a:80", after), Admission::Allow);
+        breaker.record_success("a:80");
+        assert_eq!(breaker.admit("a:80", after), Admission::Allow);
+        assert!(breaker.snapshot(after).is_empty());
+    }
+
+    #[test]
+    fn test_disabled() {
+        let breaker = CircuitBreaker::new(0, COOLDOWN);
+        let now = Instant::now();
+        for _ in 0..10 {
+            assert!(!breaker.record_failure("a:80", now));
+        }
+        assert_eq!(breaker.admit("a:80", now), Admission::Allow);
+        assert!(breaker.snapshot(now).is_empty());
+    }
+}
@@ -0,0 +1,357 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use serde::Serialize;
+use tokio::sync::oneshot;
+
+/// In-flight limit of a route, for upstreams that degrade under load.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConcurrencyLimit {
+    pub max_in_flight: usize,
+    /// Requests allowed to wait for a slot; more are rejected at once
+    pub max_queue: usize,
+    pub queue_timeout: Duration,
+    /// Hand free slots to the waiting user with the fewest requests in
+    /// flight instead of strictly first come, first served
+    pub fair: bool,
+}
+
+struct Waiter {
+    id: u64,
+    user: Option<String>,
+    /// Receives the slot; dropping an unreceived guard frees it again
+    tx: oneshot::Sender<SlotGuard>,
+}
+
+#[derive(Default)]
+struct RouteSlots {
+    in_flight: usize,
+    /// In-flight requests per user; anonymous requests share one entry
+    per_user: HashMap<Option<String>, usize>,
+    queue: VecDeque<Waiter>,
+}
+
+impl RouteSlots {
+    fn add_user(&mut self, user: Option<String>) {
+        *self.per_user.entry(user).or_default() += 1;
+    }
+
+    fn remove_user(&mut self, user: &Option<String>) {
+        if let Some(count) = self.per_user.get_mut(user) {
+            *count = count.saturating_sub(1);
+            if *count == 0 {
+                self.per_user.remove(user);
+            }
+        }
+    }
+
+    /// Index of the waiter that gets the next free slot.
+    fn next_waiter(&self, fair: bool) -> Option<usize> {
+        if !fair {
+            return (!self.queue.is_empty()).then_some(0);
+        }
+        self.queue
+            .iter()
+            .enumerate()
+            .min_by_key(|(i, w)| (self.per_user.get(&w.user).copied().unwrap_or(0), *i))
+            .map(|(i, _)| i)
+    }
+}
+
+/// Requests in flight and waiting on one route.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RouteConcurrency {
+    pub route: String,
+    pub in_flight: usize,
+    pub queued: usize,
+}
+
+enum Acquire {
+    Granted(SlotGuard),
+    Queued(u64, oneshot::Receiver<SlotGuard>),
+    Full,
+}
+
+/// Limits requests in flight per route, with a bounded wait queue.
+#[derive(Default)]
+pub struct ConcurrencyLimiter {
+    routes: Mutex<HashMap<String, RouteSlots>>,
+    next_id: AtomicU64,
+}
+
+impl ConcurrencyLimiter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, RouteSlots>> {
+        match self.routes.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    /// Waits up to `queue_timeout` for a slot on `route`. Returns `None` when
+    /// the queue is full or the wait timed out.
+    pub async fn acquire(
+        self: &Arc<Self>,
+        route: &str,
+        user: Option<&str>,
+        limit: &ConcurrencyLimit,
+    ) -> Option<SlotGuard> {
+        let (id, mut rx) = match self.try_acquire(route, user, limit) {
+            Acquire::Granted(guard) => return Some(guard),
+            Acquire::Full => return None,
+            Acquire::Queued(id, rx) => (id, rx),
+        };
+        if let Ok(result) = tokio::time::timeout(limit.queue_timeout, &mut rx).await {
+            return result.ok();
+        }
+        // A slot may have been handed over after the timeout fired
+        if self.cancel(route, id) {
+            None
+        } else {
+            rx.await.ok()
+        }
+    }
+
+    fn try_acquire(
+        self: &Arc<Self>,
+        route: &str,
+        user: Option<&str>,
+        limit: &ConcurrencyLimit,
+    ) -> Acquire {
+        let mut routes = self.lock();
+        let slots = routes.entry(route.to_string()).or_default();
+        // Waiters whose request went away
+        slots.queue.retain(|w| !w.tx.is_closed());
+
+        if slots.in_flight < limit.max_in_flight && slots.queue.is_empty() {
+            slots.in_flight += 1;
+            slots.add_user(user.map(str::to_string));
+            return Acquire::Granted(self.guard(route, user, limit.fair));
+        }
+        if slots.queue.len() >= limit.max_queue {
+            if slots.in_flight == 0 && slots.queue.is_empty() {
+                routes.remove(route);
+            }
+            return Acquire::Full;
+        }
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = oneshot::channel();
+        slots.queue.push_back(Waiter {
+            id,
+            user: user.map(str::to_string),
+            tx,
+        });
+        Acquire::Queued(id, rx)
+    }
+
+    /// Removes a waiter. Returns false if it already got a slot.
+    fn cancel(&self, route: &str, id: u64) -> bool {
+        let mut routes = self.lock();
+        let Some(slots) = routes.get_mut(route) else {
+            return false;
+        };
+        match slots.queue.iter().position(|w| w.id == id) {
+            Some(index) => {
+                slots.queue.remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn guard(self: &Arc<Self>, route: &str, user: Option<&str>, fair: bool) -> SlotGuard {
+        SlotGuard {
+            limiter: self.clone(),
+            route: route.to_string(),
+            user: user.map(str::to_string),
+            fair,
+        }
+    }
+
+    /// Routes with requests in flight, busiest first.
+    pub fn snapshot(&self) -> Vec<RouteConcurrency> {
+        let mut routes: Vec<RouteConcurrency> = self
+            .lock()
+            .iter()
+            .map(|(route, slots)| RouteConcurrency {
+                route: route.clone(),
+                in_flight: slots.in_flight,
+                queued: slots.queue.iter().filter(|w| !w.tx.is_closed()).count(),
+            })
+            .collect();
+        routes.sort_by(|a, b| {
+            b.in_flight
+                .cmp(&a.in_flight)
+                .then_with(|| a.route.cmp(&b.route))
+        });
+        routes
+    }
+
+    /// Passes the slot to the next waiter, or frees it.
+    fn release(self: &Arc<Self>, route: &str, user: &Option<String>, fair: bool) {
+        let (tx, guard) = {
+            let mut routes = self.lock();
+            let Some(slots) = routes.get_mut(route) else {
+                return;
+            };
+            slots.remove_user(user);
+            let mut next = None;
+            while let Some(index) = slots.next_waiter(fair) {
+                let Some(waiter) = slots.queue.remove(index) else {
+                    break;
+                };
+                if !waiter.tx.is_closed() {
+                    next = Some(waiter);
+                    break;
+                }
+            }
+            let Some(waiter) = next else {
+                slots.in_flight = slots.in_flight.saturating_sub(1);
+                if slots.in_flight == 0 {
+                    routes.remove(route);
+                }
+                return;
+            };
+            slots.add_user(waiter.user.clone());
+            let guard = self.guard(route, waiter.user.as_deref(), fair);
+            (waiter.tx, guard)
+        };
+        // Sent without the lock: if the waiter went away in the meantime,
+        // the returned guard is dropped here and passes the slot on again
+        let _ = tx.send(guard);
+    }
+}
+
+/// Holds a slot until dropped with the request context.
+pub struct SlotGuard {
+    limiter: Arc<ConcurrencyLimiter>,
+    route: String,
+    user: Option<String>,
+    fair: bool,
+}
+
+impl Drop for SlotGuard {
+    fn drop(&mut self) {
+        self.limiter.release(&self.route, &self.user, self.fair);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limit(max_in_flight: usize, max_queue: usize) -> ConcurrencyLimit {
+        ConcurrencyLimit {
+            max_in_flight,
+            max_queue,
+            queue_timeout: Duration::from_secs(1),
+            fair: false,
+        }
+    }
+
+    fn granted(acquire: Acquire) -> SlotGuard {
+        match acquire {
+            Acquire::Granted(guard) => guard,
+            _ => panic!("expected a slot"),
+        }
+    }
+
+    fn queued(acquire: Acquire) -> (u64, oneshot::Receiver<SlotGuard>) {
+        match acquire {
+            Acquire::Queued(id, rx) => (id, rx),
+            _ => panic!("expected to wait"),
+        }
+    }
+
+    #[test]
+    fn test_queue_is_fifo_and_bounded() {
+        let limiter = Arc::new(ConcurrencyLimiter::new());
+        let limit = limit(1, 2);
+        let first = granted(limiter.try_acquire("/gpu", Some("a"), &limit));
+        let (_, mut second) = queued(limiter.try_acquire("/gpu", Some("b"), &limit));
+        let (_, mut third) = queued(limiter.try_acquire("/gpu", Some("c"), &limit));
+        assert!(matches!(
+            limiter.try_acquire("/gpu", Some("d"), &limit),
+            Acquire::Full
+        ));
+        // Other routes have their own slots
+        let _other = granted(limiter.try_acquire("/cpu", Some("d"), &limit));
+
+        drop(first);
+        let _slot = second.try_recv().unwrap();
+        assert!(third.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_fair_handoff_prefers_idle_users() {
+        let limiter = Arc::new(ConcurrencyLimiter::new());
+        let limit = ConcurrencyLimit {
+            fair: true,
+            ..limit(2, 10)
+        };
+        let busy = granted(limiter.try_acquire("/gpu", Some("a"), &limit));
+        let _also_busy = granted(limiter.try_acquire("/gpu", Some("a"), &limit));
+        let (_, mut same_user) = queued(limiter.try_acquire("/gpu", Some("a"), &limit));
+        let (_, mut other_user) = queued(limiter.try_acquire("/gpu", Some("b"), &limit));
+
+        drop(busy);
+        let _slot = other_user.try_recv().unwrap();
+        assert!(same_user.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_snapshot_counts_in_flight_and_queued() {
+        let limiter = Arc::new(ConcurrencyLimiter::new());
+        let limit = limit(1, 2);
+        let first = granted(limiter.try_acquire("/gpu", Some("a"), &limit));
+        let (_, _waiting) = queued(limiter.try_acquire("/gpu", Some("b"), &limit));
+
+        assert_eq!(
+            limiter.snapshot(),
+            vec![RouteConcurrency {
+                route: "/gpu".to_string(),
+                in_flight: 1,
+                queued: 1,
+            }]
+        );
+        drop(first);
+        // The waiter took over the slot
+        assert_eq!(limiter.snapshot()[0].queued, 0);
+    }
+
+    #[test]
+    fn test_cancelled_waiter_is_skipped() {
+        let limiter = Arc::new(ConcurrencyLimiter::new());
+        let limit = limit(1, 2);
+        let first = granted(limiter.try_acquire("/gpu", None, &limit));
+        let (id, _timed_out) = queued(limiter.try_acquire("/gpu", None, &limit));
+        let (_, gone) = queued(limiter.try_acquire("/gpu", None, &limit));
+        assert!(limiter.cancel("/gpu", id));
+        drop(gone);
+
+        drop(first);
+        assert!(limiter.lock().get("/gpu").is_none());
+        let _next = granted(limiter.try_acquire("/gpu", None, &limit));
+    }
+
+    #[test]
+    fn test_unreceived_handoff_frees_slot() {
+        let limiter = Arc::new(ConcurrencyLimiter::new());
+        let limit = limit(1, 2);
+        let first = granted(limiter.try_acquire("/gpu", Some("a"), &limit));
+        let (_, waiting) = queued(limiter.try_acquire("/gpu", Some("b"), &limit));
+
+        // The slot is handed over, but the waiter is gone before it polls
+        drop(first);
+        assert_eq!(limiter.snapshot()[0].in_flight, 1);
+        drop(waiting);
+
+        assert!(limiter.lock().get("/gpu").is_none());
+        let _next = granted(limiter.try_acquire("/gpu", Some("c"), &limit));
+    }
+}
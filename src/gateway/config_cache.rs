@@ -1,167 +1,879 @@
-use std::collections::HashMap;
-use std::net::{SocketAddr, ToSocketAddrs};
-use std::sync::RwLock;
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use tokio::sync::Notify;
+use tracing::warn;
+
+use super::concurrency::ConcurrencyLimit;
+use super::identity::IdentityHeaders;
+use super::route_matcher::{MatchType, RouteMatch, RouteMatcher};
+use super::token_source::TokenSources;
+use super::upstream_options::UpstreamOptions;
+use super::upstream_pool::{CachedUpstreamPool, PoolStatus};
+use super::usage::Quota;
+use super::virtual_host::HostPattern;
+use crate::identity_assertion::IdentitySigner;
+use crate::models::{ProxyRoute, AUTH_MODE_NONE, AUTH_MODE_OPTIONAL, AUTH_MODE_REQUIRED};
+
+/// How long one upstream name may take to resolve, so a slow DNS server
+/// cannot stall the refresh of every other upstream.
+const DNS_LOOKUP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How the gateway treats bearer tokens on a route.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AuthMode {
+    /// Requests without a valid token are rejected
+    Required,
+    /// A valid token identifies the user; otherwise the request is anonymous
+    Optional,
+    /// Tokens are ignored
+    None,
+}
+
+impl AuthMode {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            AUTH_MODE_REQUIRED => Some(Self::Required),
+            AUTH_MODE_OPTIONAL => Some(Self::Optional),
+            AUTH_MODE_NONE => Some(Self::None),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Required => AUTH_MODE_REQUIRED,
+            Self::Optional => AUTH_MODE_OPTIONAL,
+            Self::None => AUTH_MODE_NONE,
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct CachedRoute {
+    /// Only requests for this host match; `None` matches any host
+    pub host: Option<HostPattern>,
+    /// Path pattern, matched as `match_type` says
     pub path_prefix: String,
+    pub match_type: MatchType,
+    pub priority: i32,
+    /// Allowed methods, uppercase; empty allows every method
+    pub methods: Vec<String>,
+    /// Upstream path template filled with the pattern's captures
+    pub rewrite: Option<String>,
     pub upstream_address: String,
-    pub require_auth: bool,
+    pub auth_mode: AuthMode,
     pub strip_prefix: Option<String>,
+    pub max_auth_age_secs: Option<i64>,
+    pub require_mfa: bool,
+    pub required_roles: Vec<String>,
+    pub identity_headers: IdentityHeaders,
+    pub token_sources: TokenSources,
+    pub max_connection_secs: Option<i64>,
+    pub close_at_token_expiry: bool,
+    pub max_connections_per_user: Option<usize>,
+    pub upstream_pool: Option<String>,
+    pub upstream_options: UpstreamOptions,
+    pub concurrency_limit: Option<ConcurrencyLimit>,
+    pub quota: Quota,
+}
+
+impl CachedRoute {
+    /// Identifies the route in connection, concurrency and usage accounting.
+    pub fn route_key(&self) -> String {
+        route_key(
+            self.host.as_ref(),
+            &self.path_prefix,
+            self.match_type,
+            &self.methods,
+        )
+    }
+}
+
+impl From<ProxyRoute> for CachedRoute {
+    fn from(route: ProxyRoute) -> Self {
+        let identity_headers =
+            IdentityHeaders::from_value(&route.identity_headers).unwrap_or_else(|e| {
+                warn!(
+                    path_prefix = %route.path_prefix,
+                    error = %e,
+                    "Invalid identity headers, using defaults"
+                );
+                IdentityHeaders::default()
+            });
+        // Fail closed on values written outside the API
+        let auth_mode = AuthMode::parse(&route.auth_mode).unwrap_or(AuthMode::Required);
+        let host = route.host.as_deref().map(|host| {
+            HostPattern::parse(host).unwrap_or_else(|| {
+                warn!(
+                    path_prefix = %route.path_prefix,
+                    host = %host,
+                    "Invalid route host, the route matches no requests"
+                );
+                HostPattern::Exact(host.to_string())
+            })
+        });
+        let match_type = MatchType::parse(&route.match_type).unwrap_or_else(|| {
+            warn!(
+                path_prefix = %route.path_prefix,
+                match_type = %route.match_type,
+                "Unknown match type, matching the exact path"
+            );
+            MatchType::Exact
+        });
+        Self {
+            host,
+            path_prefix: route.path_prefix,
+            match_type,
+            priority: route.priority,
+            methods: route
+                .methods
+                .iter()
+                .map(|m| m.to_ascii_uppercase())
+                .collect(),
+            rewrite: route.rewrite,
+            upstream_address: route.upstream_address,
+            auth_mode,
+            strip_prefix: route.strip_prefix,
+            max_auth_age_secs: route.max_auth_age_secs.map(i64::from),
+            require_mfa: route.require_mfa,
+            required_roles: route.required_roles,
+            identity_headers,
+            token_sources: TokenSources {
+                query_param: route.token_query_param,
+                cookie: route.token_cookie,
+                subprotocol: route.token_subprotocol,
+            },
+            max_connection_secs: route.max_connection_secs.map(i64::from),
+            close_at_token_expiry: route.close_at_token_expiry,
+            max_connections_per_user: route
+                .max_connections_per_user
+                .map(|limit| limit.max(0) as usize),
+            upstream_pool: route.upstream_pool,
+            upstream_options: UpstreamOptions::from_millis(
+                route.connect_timeout_ms.map(i64::from),
+                route.read_timeout_ms.map(i64::from),
+                route.write_timeout_ms.map(i64::from),
+                route.idle_timeout_ms.map(i64::from),
+                route.max_retries.into(),
+                route.upstream_keepalive,
+            ),
+            concurrency_limit: route.max_in_flight.filter(|max| *max > 0).map(|max| {
+                ConcurrencyLimit {
+                    max_in_flight: max as usize,
+                    max_queue: route.max_queue.max(0) as usize,
+                    queue_timeout: Duration::from_millis(route.queue_timeout_ms.max(0) as u64),
+                    fair: route.fair_queue,
+                }
+            }),
+            quota: Quota {
+                daily: route.daily_quota.map(|q| q.max(0) as u64),
+                monthly: route.monthly_quota.map(|q| q.max(0) as u64),
+            },
+        }
+    }
+}
+
+/// Outcome of [`ProxyConfigCache::match_route`].
+pub enum RouteLookup {
+    Matched(Box<MatchedRoute>),
+    /// Routes match the path but not the method; the methods they allow
+    MethodNotAllowed(Vec<String>),
+    NotFound,
+}
+
+impl RouteLookup {
+    pub fn matched(self) -> Option<MatchedRoute> {
+        match self {
+            Self::Matched(route) => Some(*route),
+            _ => None,
+        }
+    }
 }
 
 pub struct ProxyConfigCache {
     static_routes: Vec<CachedRoute>,
-    dynamic_routes: RwLock<Vec<CachedRoute>>,
+    /// Static and dynamic routes, compiled; replaced on every route change
+    routes: RwLock<Arc<RouteMatcher>>,
     auth_upstream: String,
     default_upstream: Option<String>,
-    /// Pre-resolved DNS cache: "host:port" -> SocketAddr
-    resolved_addrs: RwLock<HashMap<String, SocketAddr>>,
+    /// DNS cache: "host:port" -> every address the name resolved to
+    resolved_addrs: RwLock<HashMap<String, Vec<SocketAddr>>>,
+    /// Rotates requests across the addresses of a name
+    resolved_next: AtomicUsize,
+    /// Wakes the background resolver after routes or pools change
+    resolve_requested: Notify,
+    /// `X-Arc-Identity` signers by upstream address
+    identity_signers: RwLock<HashMap<String, IdentitySigner>>,
+    /// Load-balanced upstream pools by name
+    upstream_pools: RwLock<HashMap<String, Arc<CachedUpstreamPool>>>,
 }
 
 impl ProxyConfigCache {
     pub fn new(auth_upstream: String, default_upstream: Option<String>) -> Self {
         Self {
             static_routes: Vec::new(),
-            dynamic_routes: RwLock::new(Vec::new()),
+            routes: RwLock::new(Arc::new(RouteMatcher::default())),
             auth_upstream,
             default_upstream,
             resolved_addrs: RwLock::new(HashMap::new()),
+            resolved_next: AtomicUsize::new(0),
+            resolve_requested: Notify::new(),
+            identity_signers: RwLock::new(HashMap::new()),
+            upstream_pools: RwLock::new(HashMap::new()),
         }
     }
 
+    fn route_matcher(&self) -> Arc<RouteMatcher> {
+        self.routes
+            .read()
+            .map(|matcher| matcher.clone())
+            .unwrap_or_default()
+    }
+
     pub fn set_static_routes(&mut self, routes: Vec<CachedRoute>) {
         self.static_routes = routes;
+        let dynamic = self.route_matcher().dynamic_routes().cloned().collect();
+        self.update_routes(dynamic);
     }
 
+    /// Replaces the routes from the database and recompiles the matcher.
     pub fn update_routes(&self, routes: Vec<CachedRoute>) {
-        if let Ok(mut dynamic) = self.dynamic_routes.write() {
-            *dynamic = routes;
+        let matcher = Arc::new(RouteMatcher::new(self.static_routes.clone(), routes));
+        if let Ok(mut routes) = self.routes.write() {
+            *routes = matcher;
+        }
+        self.request_resolve();
+    }
+
+    /// Finds the route for a request. `host` is the normalized request host.
+    pub fn match_route(&self, host: Option<&str>, method: &str, path: &str) -> RouteLookup {
+        if let Some(internal) = self.match_internal(path) {
+            return internal;
+        }
+
+        match self.route_matcher().find(host, method, path) {
+            RouteMatch::Found(route, captures) => {
+                let mut matched = MatchedRoute::from(route);
+                matched.upstream_path = route.rewrite.as_deref().map(|t| captures.expand(t));
+                RouteLookup::Matched(Box::new(matched))
+            }
+            RouteMatch::MethodNotAllowed(allowed) => RouteLookup::MethodNotAllowed(allowed),
+            RouteMatch::NotFound => {
+                self.default_upstream
+                    .as_ref()
+                    .map_or(RouteLookup::NotFound, |upstream| {
+                        RouteLookup::Matched(Box::new(MatchedRoute::internal(
+                            "/",
+                            upstream.clone(),
+                            AuthMode::Required,
+                            None,
+                        )))
+                    })
+            }
         }
     }
 
-    pub fn match_route(&self, path: &str) -> Option<MatchedRoute> {
+    /// Routes of the auth service, served on every host.
+    fn match_internal(&self, path: &str) -> Option<RouteLookup> {
+        if path == "/.well-known/jwks.json" {
+            return Some(RouteLookup::Matched(Box::new(MatchedRoute::internal(
+                "/.well-known/jwks.json",
+                self.auth_upstream.clone(),
+                AuthMode::None,
+                None,
+            ))));
+        }
+
         if path.starts_with("/.well-known/") {
-            return None;
+            return Some(RouteLookup::NotFound);
         }
 
         if path.starts_with("/arc-admin/") || path == "/arc-admin" {
-            return Some(MatchedRoute {
-                upstream_address: self.auth_upstream.clone(),
-                require_auth: false,
-                strip_prefix: Some("/arc-admin".to_string()),
-            });
+            return Some(RouteLookup::Matched(Box::new(MatchedRoute::internal(
+                "/arc-admin",
+                self.auth_upstream.clone(),
+                AuthMode::None,
+                Some("/arc-admin".to_string()),
+            ))));
         }
 
         if path.starts_with("/auth/") {
-            return Some(MatchedRoute {
-                upstream_address: self.auth_upstream.clone(),
-                require_auth: false,
-                strip_prefix: None,
-            });
+            return Some(RouteLookup::Matched(Box::new(MatchedRoute::internal(
+                "/auth/",
+                self.auth_upstream.clone(),
+                AuthMode::None,
+                None,
+            ))));
         }
 
-        if path.starts_with("/api/admin") || path.starts_with("/api/config") {
-            return Some(MatchedRoute {
-                upstream_address: self.auth_upstream.clone(),
-                require_auth: true,
-                strip_prefix: None,
-            });
+        for prefix in ["/api/admin", "/api/config"] {
+            if path.starts_with(prefix) {
+                return Some(RouteLookup::Matched(Box::new(MatchedRoute::internal(
+                    prefix,
+                    self.auth_upstream.clone(),
+                    AuthMode::Required,
+                    None,
+                ))));
+            }
         }
 
-        for route in &self.static_routes {
-            if path.starts_with(&route.path_prefix) {
-                return Some(MatchedRoute {
-                    upstream_address: route.upstream_address.clone(),
-                    require_auth: route.require_auth,
-                    strip_prefix: route.strip_prefix.clone(),
-                });
-            }
+        None
+    }
+
+    /// Keys of the routes that set a quota, with their quotas. A static
+    /// route shadows a database route with the same key.
+    pub fn route_quotas(&self) -> Vec<(String, Quota)> {
+        let mut seen = HashSet::new();
+        self.route_matcher()
+            .routes()
+            .map(|r| (r.route_key(), r.quota))
+            .filter(|(key, _)| seen.insert(key.clone()))
+            .filter(|(_, quota)| quota.is_set())
+            .collect()
+    }
+
+    pub fn update_identity_signers(&self, signers: HashMap<String, IdentitySigner>) {
+        if let Ok(mut cache) = self.identity_signers.write() {
+            *cache = signers;
         }
+    }
 
-        let dynamic = match self.dynamic_routes.read() {
-            Ok(guard) => guard,
-            Err(e) => {
-                tracing::warn!("Failed to acquire dynamic routes lock: {}", e);
-                return self.default_upstream.as_ref().map(|upstream| MatchedRoute {
-                    upstream_address: upstream.clone(),
-                    require_auth: true,
-                    strip_prefix: None,
-                });
-            }
-        };
-        for route in dynamic.iter() {
-            if path.starts_with(&route.path_prefix) {
-                return Some(MatchedRoute {
-                    upstream_address: route.upstream_address.clone(),
-                    require_auth: route.require_auth,
-                    strip_prefix: route.strip_prefix.clone(),
-                });
-            }
+    /// Signer for requests to `upstream`, if it verifies identity assertions.
+    pub fn identity_signer(&self, upstream: &str) -> Option<IdentitySigner> {
+        self.identity_signers
+            .read()
+            .ok()
+            .and_then(|cache| cache.get(upstream).cloned())
+    }
+
+    /// Replaces the pools, keeping the health and load of targets that are
+    /// still configured.
+    pub fn update_upstream_pools(&self, pools: Vec<CachedUpstreamPool>) {
+        if let Ok(mut cache) = self.upstream_pools.write() {
+            *cache = pools
+                .into_iter()
+                .map(|mut pool| {
+                    if let Some(previous) = cache.get(&pool.name) {
+                        pool.inherit_state(previous);
+                    }
+                    (pool.name.clone(), Arc::new(pool))
+                })
+                .collect();
         }
+        self.request_resolve();
+    }
 
-        self.default_upstream.as_ref().map(|upstream| MatchedRoute {
-            upstream_address: upstream.clone(),
-            require_auth: true,
-            strip_prefix: None,
-        })
+    pub fn upstream_pool(&self, name: &str) -> Option<Arc<CachedUpstreamPool>> {
+        self.upstream_pools
+            .read()
+            .ok()
+            .and_then(|cache| cache.get(name).cloned())
+    }
+
+    pub fn upstream_pools(&self) -> Vec<Arc<CachedUpstreamPool>> {
+        self.upstream_pools
+            .read()
+            .map(|cache| cache.values().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Health and load of every pool target, sorted by pool name.
+    pub fn upstream_pool_status(&self) -> Vec<PoolStatus> {
+        let mut status: Vec<PoolStatus> = self
+            .upstream_pools()
+            .iter()
+            .map(|pool| pool.status())
+            .collect();
+        status.sort_by(|a, b| a.name.cmp(&b.name));
+        status
     }
 
     pub fn auth_upstream(&self) -> &str {
         &self.auth_upstream
     }
 
-    pub fn resolve_all_upstreams(&self) {
-        let mut addrs_to_resolve = vec![self.auth_upstream.clone()];
+    /// Every upstream address the gateway may connect to, sorted.
+    fn upstream_addresses(&self) -> Vec<String> {
+        let mut addrs = vec![self.auth_upstream.clone()];
 
         if let Some(ref default) = self.default_upstream {
-            addrs_to_resolve.push(default.clone());
+            addrs.push(default.clone());
         }
 
-        for route in &self.static_routes {
-            addrs_to_resolve.push(route.upstream_address.clone());
+        for route in self.route_matcher().routes() {
+            addrs.push(route.upstream_address.clone());
         }
 
-        if let Ok(dynamic) = self.dynamic_routes.read() {
-            for route in dynamic.iter() {
-                addrs_to_resolve.push(route.upstream_address.clone());
+        for pool in self.upstream_pools() {
+            for target in pool.targets() {
+                addrs.push(target.address.clone());
             }
         }
 
-        let mut resolved = HashMap::new();
-        for addr in addrs_to_resolve {
-            if let Some(socket_addr) = Self::resolve_address(&addr) {
-                tracing::info!(upstream = %addr, resolved = %socket_addr, "DNS pre-resolved");
-                resolved.insert(addr, socket_addr);
+        addrs.sort();
+        addrs.dedup();
+        addrs
+    }
+
+    /// Asks the background resolver to run now, e.g. after a route change.
+    pub fn request_resolve(&self) {
+        self.resolve_requested.notify_one();
+    }
+
+    /// Completes when [`request_resolve`](Self::request_resolve) was called.
+    pub async fn resolve_requested(&self) {
+        self.resolve_requested.notified().await;
+    }
+
+    /// Re-resolves every upstream address. A name that fails to resolve
+    /// keeps its previous addresses. Only the resolved entries are written,
+    /// so addresses cached by [`resolve`](Self::resolve) meanwhile are kept;
+    /// addresses no longer used by any upstream are dropped.
+    pub async fn resolve_upstreams(&self) {
+        let addrs = self.upstream_addresses();
+        let stale: Vec<String> = match self.resolved_addrs.read() {
+            Ok(cache) => cache
+                .keys()
+                .filter(|addr| addrs.binary_search(addr).is_err())
+                .cloned()
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+        let mut resolved = Vec::with_capacity(addrs.len());
+        for addr in addrs {
+            if let Some(socket_addrs) = Self::lookup(&addr).await {
+                resolved.push((addr, socket_addrs));
             }
         }
 
         if let Ok(mut cache) = self.resolved_addrs.write() {
-            *cache = resolved;
+            for addr in &stale {
+                cache.remove(addr);
+            }
+            for (addr, socket_addrs) in resolved {
+                if cache.get(&addr) != Some(&socket_addrs) {
+                    tracing::info!(upstream = %addr, resolved = ?socket_addrs, "DNS resolved");
+                }
+                cache.insert(addr, socket_addrs);
+            }
         }
     }
 
-    fn resolve_address(addr: &str) -> Option<SocketAddr> {
-        match addr.to_socket_addrs() {
-            Ok(mut addrs) => addrs.next(),
-            Err(e) => {
+    /// Resolves an address missing from the cache and caches it.
+    pub async fn resolve(&self, addr: &str) -> Option<SocketAddr> {
+        let socket_addrs = Self::lookup(addr).await?;
+        if let Ok(mut cache) = self.resolved_addrs.write() {
+            cache.insert(addr.to_string(), socket_addrs);
+        }
+        self.get_resolved_addr(addr)
+    }
+
+    async fn lookup(addr: &str) -> Option<Vec<SocketAddr>> {
+        let lookup = tokio::net::lookup_host(with_default_port(addr));
+        match tokio::time::timeout(DNS_LOOKUP_TIMEOUT, lookup).await {
+            Ok(Ok(addrs)) => {
+                let mut addrs: Vec<SocketAddr> = addrs.collect();
+                addrs.sort();
+                addrs.dedup();
+                (!addrs.is_empty()).then_some(addrs)
+            }
+            Ok(Err(e)) => {
                 tracing::warn!(upstream = %addr, error = %e, "DNS resolution failed");
                 None
             }
+            Err(_) => {
+                tracing::warn!(upstream = %addr, "DNS resolution timed out");
+                None
+            }
         }
     }
 
+    /// One of the addresses `addr` resolved to, rotating between calls.
     pub fn get_resolved_addr(&self, addr: &str) -> Option<SocketAddr> {
-        self.resolved_addrs
-            .read()
-            .ok()
-            .and_then(|cache| cache.get(addr).copied())
+        let cache = self.resolved_addrs.read().ok()?;
+        let addrs = cache.get(addr).filter(|a| !a.is_empty())?;
+        let n = self.resolved_next.fetch_add(1, Ordering::Relaxed);
+        Some(addrs[n % addrs.len()])
+    }
+}
+
+/// Identifies a route in connection, concurrency and usage accounting: the
+/// host and path (`api.example.com/v1`, or `/v1` without a host), then the
+/// match type unless it is `prefix` and the allowed methods, e.g.
+/// `/orders (exact) GET,POST`.
+fn route_key(
+    host: Option<&HostPattern>,
+    path_prefix: &str,
+    match_type: MatchType,
+    methods: &[String],
+) -> String {
+    let mut key = match host {
+        Some(host) => format!("{}{}", host, path_prefix),
+        None => path_prefix.to_string(),
+    };
+    if match_type != MatchType::Prefix {
+        key.push_str(&format!(" ({})", match_type.as_str()));
+    }
+    if !methods.is_empty() {
+        let mut methods = methods.to_vec();
+        methods.sort();
+        key.push(' ');
+        key.push_str(&methods.join(","));
+    }
+    key
+}
+
+/// Upstream addresses without a port use port 80.
+fn with_default_port(addr: &str) -> String {
+    match addr.rsplit_once(':') {
+        Some((_, port)) if port.parse::<u16>().is_ok() => addr.to_string(),
+        _ => format!("{}:80", addr),
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct MatchedRoute {
+    /// See [`CachedRoute::route_key`]
+    key: String,
+    pub path_prefix: String,
+    /// Path sent upstream, from the route's rewrite template
+    pub upstream_path: Option<String>,
     pub upstream_address: String,
-    pub require_auth: bool,
+    pub auth_mode: AuthMode,
     pub strip_prefix: Option<String>,
+    pub max_auth_age_secs: Option<i64>,
+    pub require_mfa: bool,
+    pub required_roles: Vec<String>,
+    pub identity_headers: IdentityHeaders,
+    pub token_sources: TokenSources,
+    pub max_connection_secs: Option<i64>,
+    pub close_at_token_expiry: bool,
+    pub max_connections_per_user: Option<usize>,
+    pub upstream_pool: Option<String>,
+    pub upstream_options: UpstreamOptions,
+    pub concurrency_limit: Option<ConcurrencyLimit>,
+    pub quota: Quota,
+}
+
+impl MatchedRoute {
+    /// Route without step-up or role requirements (auth service and default upstream).
+    fn internal(
+        path_prefix: &str,
+        upstream_address: String,
+        auth_mode: AuthMode,
+        strip_prefix: Option<String>,
+    ) -> Self {
+        Self {
+            key: path_prefix.to_string(),
+            path_prefix: path_prefix.to_string(),
+            upstream_path: None,
+            upstream_address,
+            auth_mode,
+            strip_prefix,
+            max_auth_age_secs: None,
+            require_mfa: false,
+            required_roles: Vec::new(),
+            identity_headers: IdentityHeaders::default(),
+            token_sources: TokenSources::default(),
+            max_connection_secs: None,
+            close_at_token_expiry: false,
+            max_connections_per_user: None,
+            upstream_pool: None,
+            upstream_options: UpstreamOptions::default(),
+            concurrency_limit: None,
+            quota: Quota::default(),
+        }
+    }
+
+    pub fn route_key(&self) -> String {
+        self.key.clone()
+    }
+
+    /// How long a WebSocket or SSE connection may stay open, or `None` when
+    /// the route sets no limit. `token_exp` is the expiry of the token the
+    /// connection was authenticated with, in unix seconds.
+    pub fn connection_lifetime(&self, token_exp: Option<i64>, now: i64) -> Option<Duration> {
+        let until_expiry = token_exp
+            .filter(|_| self.close_at_token_expiry)
+            .map(|exp| exp - now);
+        let secs = [self.max_connection_secs, until_expiry]
+            .into_iter()
+            .flatten()
+            .min()?;
+        Some(Duration::from_secs(secs.max(0) as u64))
+    }
+}
+
+impl From<&CachedRoute> for MatchedRoute {
+    fn from(route: &CachedRoute) -> Self {
+        Self {
+            key: route.route_key(),
+            path_prefix: route.path_prefix.clone(),
+            upstream_path: None,
+            upstream_address: route.upstream_address.clone(),
+            auth_mode: route.auth_mode,
+            strip_prefix: route.strip_prefix.clone(),
+            max_auth_age_secs: route.max_auth_age_secs,
+            require_mfa: route.require_mfa,
+            required_roles: route.required_roles.clone(),
+            identity_headers: route.identity_headers.clone(),
+            token_sources: route.token_sources.clone(),
+            max_connection_secs: route.max_connection_secs,
+            close_at_token_expiry: route.close_at_token_expiry,
+            max_connections_per_user: route.max_connections_per_user,
+            upstream_pool: route.upstream_pool.clone(),
+            upstream_options: route.upstream_options.clone(),
+            concurrency_limit: route.concurrency_limit.clone(),
+            quota: route.quota,
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub(crate) fn route(path_prefix: &str, auth_mode: AuthMode) -> CachedRoute {
+        CachedRoute {
+            host: None,
+            path_prefix: path_prefix.to_string(),
+            match_type: MatchType::Prefix,
+            priority: 0,
+            methods: Vec::new(),
+            rewrite: None,
+            upstream_address: "127.0.0.1:8000".to_string(),
+            auth_mode,
+            strip_prefix: None,
+            max_auth_age_secs: None,
+            require_mfa: false,
+            required_roles: Vec::new(),
+            identity_headers: IdentityHeaders::default(),
+            token_sources: TokenSources::default(),
+            max_connection_secs: None,
+            close_at_token_expiry: false,
+            max_connections_per_user: None,
+            upstream_pool: None,
+            upstream_options: UpstreamOptions::default(),
+            concurrency_limit: None,
+            quota: Quota::default(),
+        }
+    }
+
+    #[test]
+    fn test_auth_mode_parse() {
+        for mode in [AuthMode::Required, AuthMode::Optional, AuthMode::None] {
+            assert_eq!(AuthMode::parse(mode.as_str()), Some(mode));
+        }
+        assert_eq!(AuthMode::parse("true"), None);
+    }
+
+    #[test]
+    fn test_match_route_auth_mode() {
+        let cache = ProxyConfigCache::new("127.0.0.1:3001".to_string(), None);
+        cache.update_routes(vec![
+            route("/demo", AuthMode::Optional),
+            route("/api", AuthMode::Required),
+        ]);
+
+        assert_eq!(
+            cache
+                .match_route(None, "GET", "/demo/page")
+                .matched()
+                .unwrap()
+                .auth_mode,
+            AuthMode::Optional
+        );
+        assert_eq!(
+            cache
+                .match_route(None, "GET", "/api/orders")
+                .matched()
+                .unwrap()
+                .auth_mode,
+            AuthMode::Required
+        );
+        assert_eq!(
+            cache
+                .match_route(None, "GET", "/auth/login/init")
+                .matched()
+                .unwrap()
+                .auth_mode,
+            AuthMode::None
+        );
+        assert!(cache
+            .match_route(None, "GET", "/unknown")
+            .matched()
+            .is_none());
+    }
+
+    #[test]
+    fn test_match_route_host() {
+        let cache = ProxyConfigCache::new("127.0.0.1:3001".to_string(), None);
+        let host_route = |host: &str, upstream: &str| CachedRoute {
+            host: HostPattern::parse(host),
+            upstream_address: upstream.to_string(),
+            ..route("/", AuthMode::None)
+        };
+        cache.update_routes(vec![
+            CachedRoute {
+                upstream_address: "fallback:80".to_string(),
+                ..route("/", AuthMode::None)
+            },
+            host_route("*.example.com", "tenants:80"),
+            host_route("api.example.com", "api:80"),
+        ]);
+
+        let upstream = |host: Option<&str>| {
+            cache
+                .match_route(host, "GET", "/v1")
+                .matched()
+                .unwrap()
+                .upstream_address
+        };
+        assert_eq!(upstream(Some("api.example.com")), "api:80");
+        assert_eq!(upstream(Some("acme.example.com")), "tenants:80");
+        assert_eq!(upstream(Some("example.com")), "fallback:80");
+        assert_eq!(upstream(None), "fallback:80");
+        assert_eq!(
+            cache
+                .match_route(Some("api.example.com"), "GET", "/v1")
+                .matched()
+                .unwrap()
+                .route_key(),
+            "api.example.com/"
+        );
+        // Internal routes are served on every host
+        assert_eq!(
+            cache
+                .match_route(Some("acme.example.com"), "GET", "/auth/login/init")
+                .matched()
+                .unwrap()
+                .upstream_address,
+            "127.0.0.1:3001"
+        );
+    }
+
+    #[test]
+    fn test_match_route_rewrite_and_methods() {
+        let cache =
+            ProxyConfigCache::new("127.0.0.1:3001".to_string(), Some("default:80".to_string()));
+        cache.update_routes(vec![CachedRoute {
+            match_type: MatchType::Regex,
+            methods: vec!["GET".to_string()],
+            rewrite: Some("/v2/users/{id}".to_string()),
+            ..route(r"/users/(?P<id>\d+)", AuthMode::None)
+        }]);
+
+        let matched = cache
+            .match_route(None, "GET", "/users/7")
+            .matched()
+            .unwrap();
+        assert_eq!(matched.upstream_path.as_deref(), Some("/v2/users/7"));
+        assert!(matches!(
+            cache.match_route(None, "POST", "/users/7"),
+            RouteLookup::MethodNotAllowed(_)
+        ));
+        // Unmatched paths still go to the default upstream
+        assert_eq!(
+            cache
+                .match_route(None, "POST", "/other")
+                .matched()
+                .unwrap()
+                .upstream_address,
+            "default:80"
+        );
+    }
+
+    #[test]
+    fn test_route_key_separates_routes_on_one_path() {
+        let cache = ProxyConfigCache::new("127.0.0.1:3001".to_string(), None);
+        let quota = Quota {
+            daily: Some(10),
+            monthly: None,
+        };
+        cache.update_routes(vec![
+            CachedRoute {
+                methods: vec!["POST".to_string(), "GET".to_string()],
+                quota,
+                ..route("/orders", AuthMode::None)
+            },
+            CachedRoute {
+                match_type: MatchType::Exact,
+                methods: vec!["DELETE".to_string()],
+                quota,
+                ..route("/orders", AuthMode::None)
+            },
+        ]);
+        let key = |method: &str| {
+            cache
+                .match_route(None, method, "/orders")
+                .matched()
+                .unwrap()
+                .route_key()
+        };
+        assert_eq!(key("GET"), "/orders GET,POST");
+        assert_eq!(key("DELETE"), "/orders (exact) DELETE");
+
+        let quotas: Vec<String> = cache.route_quotas().into_iter().map(|(k, _)| k).collect();
+        assert_eq!(quotas.len(), 2);
+        assert!(quotas.contains(&"/orders GET,POST".to_string()));
+    }
+
+    #[test]
+    fn test_connection_lifetime() {
+        let mut matched = MatchedRoute::from(&route("/ws", AuthMode::Required));
+        assert_eq!(matched.connection_lifetime(Some(1_600), 1_000), None);
+
+        matched.max_connection_secs = Some(3_600);
+        assert_eq!(
+            matched.connection_lifetime(Some(1_600), 1_000),
+            Some(Duration::from_secs(3_600))
+        );
+
+        matched.close_at_token_expiry = true;
+        assert_eq!(
+            matched.connection_lifetime(Some(1_600), 1_000),
+            Some(Duration::from_secs(600))
+        );
+        // Anonymous connections on optional routes only get the hard limit
+        assert_eq!(
+            matched.connection_lifetime(None, 1_000),
+            Some(Duration::from_secs(3_600))
+        );
+        assert_eq!(
+            matched.connection_lifetime(Some(900), 1_000),
+            Some(Duration::ZERO)
+        );
+    }
+
+    #[test]
+    fn test_resolved_addr_rotates() {
+        let cache = ProxyConfigCache::new("127.0.0.1:3001".to_string(), None);
+        let addrs: Vec<SocketAddr> = vec![
+            "10.0.0.1:7000".parse().unwrap(),
+            "10.0.0.2:7000".parse().unwrap(),
+        ];
+        cache
+            .resolved_addrs
+            .write()
+            .unwrap()
+            .insert("model:7000".to_string(), addrs.clone());
+
+        let picks: Vec<SocketAddr> = (0..4)
+            .filter_map(|_| cache.get_resolved_addr("model:7000"))
+            .collect();
+        assert_eq!(picks.iter().filter(|a| **a == addrs[0]).count(), 2);
+        assert_eq!(picks.iter().filter(|a| **a == addrs[1]).count(), 2);
+        assert!(cache.get_resolved_addr("unknown:7000").is_none());
+    }
+
+    #[test]
+    fn test_with_default_port() {
+        assert_eq!(with_default_port("model:7000"), "model:7000");
+        assert_eq!(with_default_port("model"), "model:80");
+        assert_eq!(with_default_port("[::1]"), "[::1]:80");
+    }
 }
@@ -0,0 +1,231 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use serde::Serialize;
+
+/// Who holds a long-lived connection: the authenticated user, else an
+/// existing API key, else the client IP.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+#[serde(tag = "type", content = "id", rename_all = "snake_case")]
+pub enum ConnectionSubject {
+    User(String),
+    ApiKey(String),
+    Ip(String),
+}
+
+impl ConnectionSubject {
+    /// The user id takes precedence over the API key hash, which must be
+    /// the hash of a key that exists.
+    pub fn from_identity(user_id: Option<&str>, api_key_hash: Option<&str>) -> Option<Self> {
+        user_id
+            .map(|id| Self::User(id.to_string()))
+            .or_else(|| api_key_hash.map(|hash| Self::ApiKey(hash.to_string())))
+    }
+
+    /// [`from_identity`](Self::from_identity), falling back to the client IP
+    /// so anonymous connections are limited too.
+    pub fn for_connection(
+        user_id: Option<&str>,
+        api_key_hash: Option<&str>,
+        ip: Option<&str>,
+    ) -> Option<Self> {
+        Self::from_identity(user_id, api_key_hash).or_else(|| ip.map(|ip| Self::Ip(ip.to_string())))
+    }
+
+    /// Type and id as stored in the database.
+    pub fn parts(&self) -> (&'static str, &str) {
+        match self {
+            Self::User(id) => ("user", id),
+            Self::ApiKey(hash) => ("api_key", hash),
+            Self::Ip(ip) => ("ip", ip),
+        }
+    }
+
+    pub fn from_parts(kind: &str, id: String) -> Option<Self> {
+        match kind {
+            "user" => Some(Self::User(id)),
+            "api_key" => Some(Self::ApiKey(id)),
+            "ip" => Some(Self::Ip(id)),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct ConnectionKey {
+    route: String,
+    subject: ConnectionSubject,
+}
+
+/// Open connections of one subject on one route.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ActiveConnections {
+    pub route: String,
+    pub subject: ConnectionSubject,
+    pub connections: usize,
+}
+
+/// Counts open WebSocket and SSE connections per route and subject.
+#[derive(Default)]
+pub struct ConnectionTracker {
+    active: Mutex<HashMap<ConnectionKey, usize>>,
+}
+
+impl ConnectionTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a connection, or returns `None` if the subject already holds
+    /// `limit` connections on the route. The count drops with the guard.
+    pub fn acquire(
+        self: &Arc<Self>,
+        route: &str,
+        subject: ConnectionSubject,
+        limit: Option<usize>,
+    ) -> Option<ConnectionGuard> {
+        let key = ConnectionKey {
+            route: route.to_string(),
+            subject,
+        };
+        let mut active = match self.active.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let count = active.entry(key.clone()).or_default();
+        if limit.is_some_and(|limit| *count >= limit) {
+            if *count == 0 {
+                active.remove(&key);
+            }
+            return None;
+        }
+        *count += 1;
+        Some(ConnectionGuard {
+            tracker: self.clone(),
+            key,
+        })
+    }
+
+    fn release(&self, key: &ConnectionKey) {
+        let mut active = match self.active.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        if let Some(count) = active.get_mut(key) {
+            *count = count.saturating_sub(1);
+            if *count == 0 {
+                active.remove(key);
+            }
+        }
+    }
+
+    /// Current counts, busiest first.
+    pub fn snapshot(&self) -> Vec<ActiveConnections> {
+        let active = match self.active.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let mut connections: Vec<ActiveConnections> = active
+            .iter()
+            .map(|(key, count)| ActiveConnections {
+                route: key.route.clone(),
+                subject: key.subject.clone(),
+                connections: *count,
+            })
+            .collect();
+        connections.sort_by(|a, b| {
+            b.connections
+                .cmp(&a.connections)
+                .then_with(|| a.route.cmp(&b.route))
+        });
+        connections
+    }
+
+    pub fn total(&self) -> usize {
+        match self.active.lock() {
+            Ok(guard) => guard.values().sum(),
+            Err(poisoned) => poisoned.into_inner().values().sum(),
+        }
+    }
+}
+
+/// Keeps a connection counted until dropped with the request context.
+pub struct ConnectionGuard {
+    tracker: Arc<ConnectionTracker>,
+    key: ConnectionKey,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.tracker.release(&self.key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(id: &str) -> ConnectionSubject {
+        ConnectionSubject::User(id.to_string())
+    }
+
+    #[test]
+    fn test_limit_per_route_and_subject() {
+        let tracker = Arc::new(ConnectionTracker::new());
+        let first = tracker.acquire("/sse", user("a"), Some(2)).unwrap();
+        let _second = tracker.acquire("/sse", user("a"), Some(2)).unwrap();
+        assert!(tracker.acquire("/sse", user("a"), Some(2)).is_none());
+
+        // Other users and routes have their own counts
+        assert!(tracker.acquire("/sse", user("b"), Some(2)).is_some());
+        assert!(tracker.acquire("/ws", user("a"), Some(2)).is_some());
+
+        drop(first);
+        assert!(tracker.acquire("/sse", user("a"), Some(2)).is_some());
+    }
+
+    #[test]
+    fn test_snapshot_drops_closed_connections() {
+        let tracker = Arc::new(ConnectionTracker::new());
+        let key = ConnectionSubject::ApiKey("hash".to_string());
+        let guard = tracker.acquire("/ws", key.clone(), None).unwrap();
+        let _other = tracker.acquire("/ws", key.clone(), None).unwrap();
+        assert!(tracker.acquire("/ws", user("a"), Some(0)).is_none());
+
+        assert_eq!(
+            tracker.snapshot(),
+            vec![ActiveConnections {
+                route: "/ws".to_string(),
+                subject: key,
+                connections: 2,
+            }]
+        );
+        drop(guard);
+        assert_eq!(tracker.total(), 1);
+    }
+
+    #[test]
+    fn test_subject_prefers_user() {
+        assert_eq!(
+            ConnectionSubject::from_identity(Some("u1"), Some("hash")),
+            Some(user("u1"))
+        );
+        assert_eq!(
+            ConnectionSubject::from_identity(None, Some("hash")),
+            Some(ConnectionSubject::ApiKey("hash".to_string()))
+        );
+        assert_eq!(ConnectionSubject::from_identity(None, None), None);
+    }
+
+    #[test]
+    fn test_anonymous_connections_count_by_ip() {
+        assert_eq!(
+            ConnectionSubject::for_connection(None, None, Some("10.0.0.1")),
+            Some(ConnectionSubject::Ip("10.0.0.1".to_string()))
+        );
+        assert_eq!(
+            ConnectionSubject::for_connection(None, Some("hash"), Some("10.0.0.1")),
+            Some(ConnectionSubject::ApiKey("hash".to_string()))
+        );
+    }
+}
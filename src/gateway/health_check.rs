@@ -0,0 +1,91 @@
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+/// Time allowed to connect to a target and read its status line.
+pub const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Sends `GET path` to `address` over plain HTTP/1.1. A target is healthy
+/// when it answers with a 2xx or 3xx status in time.
+pub async fn check_target(address: &str, path: &str) -> bool {
+    tokio::time::timeout(HEALTH_CHECK_TIMEOUT, probe(address, path))
+        .await
+        .unwrap_or(false)
+}
+
+/// Whether `path` can be sent as the request target of a health check: an
+/// absolute path, with an optional query, and no whitespace or control
+/// characters that would break the request line.
+pub fn is_valid_path(path: &str) -> bool {
+    path.starts_with('/')
+        && !path
+            .bytes()
+            .any(|b| b.is_ascii_whitespace() || b.is_ascii_control())
+        && path.parse::<http::uri::PathAndQuery>().is_ok()
+}
+
+async fn probe(address: &str, path: &str) -> bool {
+    if !is_valid_path(path) {
+        return false;
+    }
+    let mut stream = match TcpStream::connect(address).await {
+        Ok(s) => s,
+        Err(_) => return false,
+    };
+    let request = format!(
+        "GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: arc-auth-health-check\r\nConnection: close\r\n\r\n",
+        path, address
+    );
+    if stream.write_all(request.as_bytes()).await.is_err() {
+        return false;
+    }
+
+    let mut buf = [0u8; 64];
+    let mut len = 0;
+    while len < buf.len() {
+        match stream.read(&mut buf[len..]).await {
+            Ok(0) | Err(_) => break,
+            Ok(n) => len += n,
+        }
+        if buf[..len].contains(&b'\n') {
+            break;
+        }
+    }
+    is_healthy_status_line(&String::from_utf8_lossy(&buf[..len]))
+}
+
+fn is_healthy_status_line(response: &str) -> bool {
+    let mut parts = response.split_whitespace();
+    matches!(parts.next(), Some(v) if v.starts_with("HTTP/1."))
+        && matches!(
+            parts.next().and_then(|s| s.parse::<u16>().ok()),
+            Some(200..=399)
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_healthy_status_line() {
+        assert!(is_healthy_status_line("HTTP/1.1 200 OK\r\n"));
+        assert!(is_healthy_status_line("HTTP/1.0 204 No Content"));
+        assert!(is_healthy_status_line("HTTP/1.1 301 Moved"));
+        assert!(!is_healthy_status_line("HTTP/1.1 503 Service Unavailable"));
+        assert!(!is_healthy_status_line("SSH-2.0-OpenSSH"));
+        assert!(!is_healthy_status_line(""));
+    }
+
+    #[test]
+    fn test_is_valid_path() {
+        assert!(is_valid_path("/health"));
+        assert!(is_valid_path("/status?full=1"));
+        assert!(!is_valid_path("health"));
+        assert!(!is_valid_path("/health HTTP/1.1\r\nX-Injected: 1"));
+        assert!(!is_valid_path("/health\n"));
+        assert!(!is_valid_path("/a b"));
+        assert!(!is_valid_path("/health\u{7f}"));
+    }
+}
@@ -0,0 +1,252 @@
+use std::collections::BTreeMap;
+
+use http::header::{HeaderMap, HeaderName};
+
+use crate::models::{default_identity_headers, AccessTokenClaims};
+
+/// Headers clients may never send, whatever the route forwards.
+const RESERVED_HEADERS: &[&str] = &["x-user-id", "x-request-id"];
+
+/// `X-` headers the gateway sets or reads itself, which a route cannot map
+/// a claim to. Claims only go to other `X-` headers.
+const GATEWAY_HEADERS: &[&str] = &[
+    "x-api-key",
+    "x-arc-identity",
+    "x-forwarded-for",
+    "x-forwarded-host",
+    "x-forwarded-proto",
+    "x-real-ip",
+    "x-request-id",
+    "x-token-refresh",
+];
+
+/// How the gateway authenticated a request, reported by the `auth_method`
+/// claim.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthMethod {
+    Jwt,
+    ApiKey,
+}
+
+impl AuthMethod {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Jwt => "jwt",
+            Self::ApiKey => "api_key",
+        }
+    }
+}
+
+/// Token claims that can be forwarded to upstreams.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IdentityClaim {
+    Sub,
+    Email,
+    Roles,
+    Exp,
+    AuthTime,
+    Amr,
+    AuthMethod,
+}
+
+impl IdentityClaim {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "sub" => Some(Self::Sub),
+            "email" => Some(Self::Email),
+            "roles" => Some(Self::Roles),
+            "exp" => Some(Self::Exp),
+            "auth_time" => Some(Self::AuthTime),
+            "amr" => Some(Self::Amr),
+            "auth_method" => Some(Self::AuthMethod),
+            _ => None,
+        }
+    }
+
+    /// Header value; lists are comma-separated and times are unix seconds.
+    /// Token claims have no value on requests authenticated without a token.
+    fn value(self, claims: Option<&AccessTokenClaims>, method: AuthMethod) -> Option<String> {
+        Some(match self {
+            Self::Sub => claims?.sub.to_string(),
+            Self::Email => claims?.email.clone(),
+            Self::Roles => claims?.roles.join(","),
+            Self::Exp => claims?.exp.to_string(),
+            Self::AuthTime => claims?.auth_time.to_string(),
+            Self::Amr => claims?.amr.join(","),
+            Self::AuthMethod => method.as_str().to_string(),
+        })
+    }
+}
+
+/// Per-route mapping of token claims to the headers sent upstream.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IdentityHeaders {
+    headers: Vec<(IdentityClaim, HeaderName)>,
+}
+
+impl Default for IdentityHeaders {
+    fn default() -> Self {
+        Self::from_map(&default_identity_headers()).expect("default identity headers are valid")
+    }
+}
+
+impl IdentityHeaders {
+    /// Parses a `claim -> header name` map, rejecting unknown claims,
+    /// invalid header names, names not starting with `X-` or used by the
+    /// gateway, and headers used twice.
+    pub fn from_map(map: &BTreeMap<String, String>) -> Result<Self, String> {
+        let mut headers: Vec<(IdentityClaim, HeaderName)> = Vec::with_capacity(map.len());
+        for (claim, header) in map {
+            let claim = IdentityClaim::parse(claim)
+                .ok_or_else(|| format!("Unknown identity claim '{}'", claim))?;
+            let name = HeaderName::from_bytes(header.trim().as_bytes())
+                .map_err(|_| format!("Invalid header name '{}'", header))?;
+            if !name.as_str().starts_with("x-") || GATEWAY_HEADERS.contains(&name.as_str()) {
+                return Err(format!("Header '{}' cannot carry identity claims", header));
+            }
+            if headers.iter().any(|(_, existing)| existing == name) {
+                return Err(format!("Header '{}' is mapped more than once", header));
+            }
+            headers.push((claim, name));
+        }
+        Ok(Self { headers })
+    }
+
+    /// Parses the JSON object stored with a route.
+    pub fn from_value(value: &serde_json::Value) -> Result<Self, String> {
+        let map: BTreeMap<String, String> = serde_json::from_value(value.clone())
+            .map_err(|_| "identity_headers must map claim names to header names".to_string())?;
+        Self::from_map(&map)
+    }
+
+    /// Whether the client sent a header the gateway reserves for identity,
+    /// either globally or through this route's mapping.
+    pub fn is_spoofed(&self, headers: &HeaderMap) -> bool {
+        RESERVED_HEADERS.iter().any(|h| headers.contains_key(*h))
+            || self.headers.iter().any(|(_, h)| headers.contains_key(h))
+    }
+
+    /// Header names and values to send upstream for an authenticated
+    /// request; `claims` is `None` when it was authenticated by API key.
+    pub fn values<'a>(
+        &'a self,
+        claims: Option<&'a AccessTokenClaims>,
+        method: AuthMethod,
+    ) -> impl Iterator<Item = (&'a HeaderName, String)> + 'a {
+        self.headers
+            .iter()
+            .filter_map(move |(claim, name)| Some((name, claim.value(claims, method)?)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    fn map(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_default_forwards_user_id() {
+        let claims = AccessTokenClaims {
+            sub: Uuid::new_v4(),
+            email: "user@example.com".to_string(),
+            exp: 2_000,
+            iat: 1_000,
+            jti: Uuid::new_v4(),
+            amr: vec!["pwd".to_string()],
+            auth_time: 1_000,
+            roles: vec!["admin".to_string()],
+        };
+        let headers = IdentityHeaders::default();
+        let values: Vec<_> = headers.values(Some(&claims), AuthMethod::Jwt).collect();
+        assert_eq!(values.len(), 1);
+        assert_eq!(values[0].0.as_str(), "x-user-id");
+        assert_eq!(values[0].1, claims.sub.to_string());
+    }
+
+    #[test]
+    fn test_api_key_requests_only_forward_auth_method() {
+        let headers = IdentityHeaders::from_map(&map(&[
+            ("sub", "X-User-Id"),
+            ("auth_method", "X-Auth-Method"),
+        ]))
+        .unwrap();
+        let values: Vec<_> = headers.values(None, AuthMethod::ApiKey).collect();
+        assert_eq!(values.len(), 1);
+        assert_eq!(values[0].0.as_str(), "x-auth-method");
+        assert_eq!(values[0].1, "api_key");
+    }
+
+    #[test]
+    fn test_from_map_validation() {
+        let headers = IdentityHeaders::from_map(&map(&[
+            ("sub", "X-User-Id"),
+            ("roles", "X-User-Roles"),
+            ("auth_method", "X-Auth-Method"),
+        ]))
+        .unwrap();
+        assert_eq!(headers.headers.len(), 3);
+
+        assert!(IdentityHeaders::from_map(&map(&[("password", "X-Password")])).is_err());
+        assert!(IdentityHeaders::from_map(&map(&[("sub", "Bad Header")])).is_err());
+        assert!(IdentityHeaders::from_map(&map(&[("sub", "X-Id"), ("email", "x-id")])).is_err());
+    }
+
+    #[test]
+    fn test_rejected_headers() {
+        for header in [
+            "Authorization",
+            "Proxy-Authorization",
+            "Connection",
+            "Keep-Alive",
+            "TE",
+            "Trailer",
+            "Transfer-Encoding",
+            "Upgrade",
+            "Content-Length",
+            "Content-Type",
+            "Content-Encoding",
+            "Accept",
+            "Origin",
+            "Cookie",
+            "Host",
+            "Sec-WebSocket-Key",
+            "Sec-WebSocket-Protocol",
+            "X-API-Key",
+            "X-Arc-Identity",
+            "X-Forwarded-For",
+            "X-Forwarded-Host",
+            "X-Forwarded-Proto",
+            "X-Real-IP",
+            "X-Request-Id",
+            "X-Token-Refresh",
+        ] {
+            assert!(
+                IdentityHeaders::from_map(&map(&[("sub", header)])).is_err(),
+                "{} was accepted",
+                header
+            );
+        }
+    }
+
+    #[test]
+    fn test_is_spoofed() {
+        let headers = IdentityHeaders::from_map(&map(&[("email", "X-User-Email")])).unwrap();
+
+        let mut request = HeaderMap::new();
+        assert!(!headers.is_spoofed(&request));
+        request.insert("x-user-email", "admin@example.com".parse().unwrap());
+        assert!(headers.is_spoofed(&request));
+
+        // X-User-Id stays reserved even when the route does not forward it
+        let mut request = HeaderMap::new();
+        request.insert("x-user-id", "someone".parse().unwrap());
+        assert!(headers.is_spoofed(&request));
+    }
+}
@@ -1,36 +1,66 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicI64, Ordering};
 use std::sync::Arc;
 
-use jsonwebtoken::{decode, Algorithm, DecodingKey, Validation};
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
 use tokio::sync::RwLock;
 
-use crate::models::AccessTokenClaims;
-use crate::services::SystemConfigService;
+use crate::models::{AccessTokenClaims, JwtKey, AMR_MFA, JWT_LEGACY_KID};
+use crate::services::{signing_key, JwtConfigService, SystemConfigService};
+
+/// Key that verifies access tokens signed under one `kid`, with the one
+/// algorithm the key signs access tokens with.
+struct VerificationKey {
+    algorithm: Algorithm,
+    key: DecodingKey,
+    validation: Validation,
+}
 
 pub struct JwtValidator {
     system_config: Arc<SystemConfigService>,
-    validation: Validation,
-    auto_refresh_threshold: i64,
-    cached_secret: Arc<RwLock<String>>,
+    jwt_config: Arc<JwtConfigService>,
+    auto_refresh_threshold: AtomicI64,
+    /// Verification keys by `kid`: the active key plus rotated-out keys
+    /// still inside their grace period.
+    cached_keys: Arc<RwLock<HashMap<String, VerificationKey>>>,
 }
 
 impl JwtValidator {
-    pub fn new(system_config: Arc<SystemConfigService>, auto_refresh_threshold: i64) -> Self {
-        let mut validation = Validation::new(Algorithm::HS256);
-        validation.set_required_spec_claims(&["exp", "sub", "iat"]);
-
+    pub fn new(system_config: Arc<SystemConfigService>, jwt_config: Arc<JwtConfigService>) -> Self {
         Self {
             system_config,
-            validation,
-            auto_refresh_threshold,
-            cached_secret: Arc::new(RwLock::new(String::new())),
+            jwt_config,
+            auto_refresh_threshold: AtomicI64::new(0),
+            cached_keys: Arc::new(RwLock::new(HashMap::new())),
         }
     }
 
+    fn validation_for(alg: Algorithm) -> Validation {
+        let mut validation = Validation::new(alg);
+        validation.set_required_spec_claims(&["exp", "sub", "iat"]);
+        validation
+    }
+
+    fn verification_key(key: &JwtKey) -> Option<VerificationKey> {
+        let algorithm = signing_key::parse_algorithm(&key.algorithm)?;
+        let decoding_key = signing_key::access_token_decoding_key(key, algorithm)?;
+        Some(VerificationKey {
+            algorithm,
+            key: decoding_key,
+            validation: Self::validation_for(algorithm),
+        })
+    }
+
     pub async fn init(&self) -> anyhow::Result<()> {
-        let secret = self.system_config.get_jwt_secret().await?;
-        let mut cached = self.cached_secret.write().await;
-        *cached = secret;
-        Ok(())
+        let keys = self.system_config.get_jwt_keys().await?;
+        let decoding_keys = keys
+            .iter()
+            .filter_map(|k| Some((k.kid.clone(), Self::verification_key(k)?)))
+            .collect();
+        let mut cached = self.cached_keys.write().await;
+        *cached = decoding_keys;
+        drop(cached);
+        self.load_jwt_config().await
     }
 
     pub async fn refresh_secret(&self) -> anyhow::Result<()> {
@@ -38,11 +68,31 @@ impl JwtValidator {
         self.init().await
     }
 
+    pub async fn refresh_jwt_config(&self) -> anyhow::Result<()> {
+        self.jwt_config.invalidate_cache().await;
+        self.load_jwt_config().await
+    }
+
+    async fn load_jwt_config(&self) -> anyhow::Result<()> {
+        let config = self.jwt_config.get_config().await?;
+        self.auto_refresh_threshold
+            .store(config.auto_refresh_threshold_secs as i64, Ordering::Relaxed);
+        Ok(())
+    }
+
     pub async fn validate(&self, token: &str) -> Result<AccessTokenClaims, JwtError> {
-        let secret = self.cached_secret.read().await;
-        let decoding_key = DecodingKey::from_secret(secret.as_bytes());
+        let header = decode_header(token).map_err(|_| JwtError::Invalid)?;
+        let kid = header.kid.as_deref().unwrap_or(JWT_LEGACY_KID);
+
+        let keys = self.cached_keys.read().await;
+        let entry = keys.get(kid).ok_or(JwtError::Invalid)?;
+        // An EdDSA/RS256 key's shared secret only signs refresh and admin
+        // tokens, so an HS256 access token under its kid is forged
+        if header.alg != entry.algorithm {
+            return Err(JwtError::Invalid);
+        }
 
-        let token_data = decode::<AccessTokenClaims>(token, &decoding_key, &self.validation)
+        let token_data = decode::<AccessTokenClaims>(token, &entry.key, &entry.validation)
             .map_err(|e| match e.kind() {
                 jsonwebtoken::errors::ErrorKind::ExpiredSignature => JwtError::Expired,
                 _ => JwtError::Invalid,
@@ -52,7 +102,43 @@ impl JwtValidator {
 
     pub fn should_refresh(&self, claims: &AccessTokenClaims) -> bool {
         let now = chrono::Utc::now().timestamp();
-        claims.exp - now < self.auto_refresh_threshold
+        claims.exp - now < self.auto_refresh_threshold.load(Ordering::Relaxed)
+    }
+
+    /// Whether the token's login (`auth_time`, `amr`) is recent and strong
+    /// enough for a route's step-up policy.
+    pub fn meets_step_up(
+        claims: &AccessTokenClaims,
+        max_auth_age_secs: Option<i64>,
+        require_mfa: bool,
+    ) -> bool {
+        Self::meets_step_up_at(
+            claims,
+            max_auth_age_secs,
+            require_mfa,
+            chrono::Utc::now().timestamp(),
+        )
+    }
+
+    fn meets_step_up_at(
+        claims: &AccessTokenClaims,
+        max_auth_age_secs: Option<i64>,
+        require_mfa: bool,
+        now: i64,
+    ) -> bool {
+        if require_mfa && !claims.amr.iter().any(|m| m == AMR_MFA) {
+            return false;
+        }
+        match max_auth_age_secs {
+            Some(max_age) => claims.auth_time > 0 && now - claims.auth_time <= max_age,
+            None => true,
+        }
+    }
+
+    /// Whether the token carries one of a route's required roles. An empty
+    /// list allows every authenticated user.
+    pub fn has_required_role(claims: &AccessTokenClaims, required_roles: &[String]) -> bool {
+        required_roles.is_empty() || required_roles.iter().any(|r| claims.roles.contains(r))
     }
 }
 
@@ -61,3 +147,75 @@ pub enum JwtError {
     Invalid,
     Expired,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    fn claims(amr: &[&str], auth_time: i64) -> AccessTokenClaims {
+        AccessTokenClaims {
+            sub: Uuid::new_v4(),
+            email: "user@example.com".to_string(),
+            exp: auth_time + 3600,
+            iat: auth_time,
+            jti: Uuid::new_v4(),
+            amr: amr.iter().map(|m| m.to_string()).collect(),
+            auth_time,
+            roles: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_step_up_without_policy() {
+        let c = claims(&["pwd"], 0);
+        assert!(JwtValidator::meets_step_up_at(&c, None, false, 1_000));
+    }
+
+    #[test]
+    fn test_step_up_requires_mfa() {
+        let now = 1_000_000;
+        assert!(!JwtValidator::meets_step_up_at(
+            &claims(&["pwd"], now),
+            None,
+            true,
+            now
+        ));
+        assert!(JwtValidator::meets_step_up_at(
+            &claims(&["pwd", "otp", "mfa"], now),
+            None,
+            true,
+            now
+        ));
+    }
+
+    #[test]
+    fn test_step_up_max_auth_age() {
+        let now = 1_000_000;
+        let c = claims(&["pwd"], now - 600);
+        assert!(JwtValidator::meets_step_up_at(&c, Some(600), false, now));
+        assert!(!JwtValidator::meets_step_up_at(&c, Some(599), false, now));
+        // Tokens issued before auth_time existed never satisfy a max age
+        assert!(!JwtValidator::meets_step_up_at(
+            &claims(&["pwd"], 0),
+            Some(600),
+            false,
+            now
+        ));
+    }
+
+    #[test]
+    fn test_required_roles() {
+        let mut c = claims(&["pwd"], 0);
+        let required = vec!["admin".to_string(), "billing".to_string()];
+        assert!(JwtValidator::has_required_role(&c, &[]));
+        assert!(!JwtValidator::has_required_role(&c, &required));
+
+        c.roles = vec!["billing".to_string()];
+        assert!(JwtValidator::has_required_role(&c, &required));
+        assert!(!JwtValidator::has_required_role(
+            &c,
+            &["Billing".to_string()]
+        ));
+    }
+}
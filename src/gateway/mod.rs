@@ -1,6 +1,27 @@
+pub mod api_keys;
+pub mod circuit_breaker;
+pub mod concurrency;
 pub mod config_cache;
+pub mod connections;
+pub mod health_check;
+pub mod identity;
 pub mod jwt;
 pub mod proxy;
+pub mod rate_limit;
+pub mod revocation;
+pub mod route_matcher;
+pub mod token_source;
+pub mod upstream_options;
+pub mod upstream_pool;
+pub mod usage;
+pub mod virtual_host;
 
+pub use api_keys::ApiKeyCache;
+pub use circuit_breaker::CircuitBreaker;
+pub use concurrency::ConcurrencyLimiter;
 pub use config_cache::ProxyConfigCache;
+pub use connections::ConnectionTracker;
 pub use jwt::JwtValidator;
+pub use rate_limit::RateLimitCache;
+pub use revocation::RevocationCache;
+pub use usage::UsageMeter;
@@ -1,25 +1,145 @@
 use async_trait::async_trait;
-use http::header::HeaderValue;
 use pingora::http::{RequestHeader, ResponseHeader};
 use pingora::prelude::HttpPeer;
-use pingora::proxy::{ProxyHttp, Session};
-use std::net::SocketAddr;
+use pingora::proxy::{FailToProxy, ProxyHttp, Session};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tracing::{debug, info, warn};
 use uuid::Uuid;
 
-use super::config_cache::{MatchedRoute, ProxyConfigCache};
+use super::api_keys::ApiKeyCache;
+use super::circuit_breaker::{Admission, CircuitBreaker};
+use super::concurrency::{ConcurrencyLimiter, SlotGuard};
+use super::config_cache::{AuthMode, MatchedRoute, ProxyConfigCache, RouteLookup};
+use super::connections::{ConnectionGuard, ConnectionSubject, ConnectionTracker};
+use super::identity::AuthMethod;
 use super::jwt::{JwtError, JwtValidator};
+use super::rate_limit::{RateLimitCache, RateLimitSubject};
+use super::revocation::RevocationCache;
+use super::token_source::{
+    offers_bearer_protocol, strip_query_param, strip_subprotocol_token, TokenSource,
+    WS_BEARER_PROTOCOL,
+};
+use super::upstream_pool::TargetGuard;
+use super::usage::{QuotaPeriod, UsageKey, UsageMeter};
+use super::virtual_host::normalize_host;
+use crate::identity_assertion::{path_and_query, IdentityAssertion, IDENTITY_ASSERTION_HEADER};
+use crate::models::AccessTokenClaims;
 
 type Result<T> = pingora::Result<T>;
 
 impl AuthGateway {
     async fn send_error(&self, session: &mut Session, status: u16, msg: &str) -> Result<bool> {
-        let body = format!(r#"{{"error":{{"code":"{}","message":"{}"}}}}"#, status, msg);
+        self.send_error_code(session, status, &status.to_string(), msg, None)
+            .await
+    }
+
+    async fn send_error_code(
+        &self,
+        session: &mut Session,
+        status: u16,
+        code: &str,
+        msg: &str,
+        www_authenticate: Option<String>,
+    ) -> Result<bool> {
+        let body = format!(r#"{{"error":{{"code":"{}","message":"{}"}}}}"#, code, msg);
         let mut header = ResponseHeader::build(status, None)?;
         header.insert_header("Content-Type", "application/json")?;
         header.insert_header("Content-Length", body.len().to_string())?;
         header.insert_header("Access-Control-Allow-Origin", "*")?;
+        if let Some(challenge) = www_authenticate {
+            header.insert_header("WWW-Authenticate", challenge)?;
+            header.insert_header("Access-Control-Expose-Headers", "WWW-Authenticate")?;
+        }
+        session
+            .write_response_header(Box::new(header), true)
+            .await?;
+        session.write_response_body(Some(body.into()), true).await?;
+        Ok(true)
+    }
+
+    /// 401 asking the client to log in again (RFC 9470 step-up challenge).
+    async fn send_step_up_required(
+        &self,
+        session: &mut Session,
+        matched: &MatchedRoute,
+    ) -> Result<bool> {
+        let mut challenge = String::from(r#"Bearer error="insufficient_user_authentication""#);
+        if let Some(max_age) = matched.max_auth_age_secs {
+            challenge.push_str(&format!(", max_age={}", max_age));
+        }
+        if matched.require_mfa {
+            challenge.push_str(r#", acr_values="mfa""#);
+        }
+        self.send_error_code(
+            session,
+            401,
+            "STEP_UP_REQUIRED",
+            "Recent or multi-factor login required",
+            Some(challenge),
+        )
+        .await
+    }
+
+    /// 429 with the seconds until the client may try again.
+    async fn send_too_many_requests(
+        &self,
+        session: &mut Session,
+        code: &str,
+        msg: &str,
+        retry_after_secs: u64,
+    ) -> Result<bool> {
+        let body = format!(r#"{{"error":{{"code":"{}","message":"{}"}}}}"#, code, msg);
+        let mut header = ResponseHeader::build(429, None)?;
+        header.insert_header("Content-Type", "application/json")?;
+        header.insert_header("Content-Length", body.len().to_string())?;
+        header.insert_header("Retry-After", retry_after_secs.to_string())?;
+        header.insert_header("Access-Control-Allow-Origin", "*")?;
+        header.insert_header("Access-Control-Expose-Headers", "Retry-After")?;
+        session
+            .write_response_header(Box::new(header), true)
+            .await?;
+        session.write_response_body(Some(body.into()), true).await?;
+        Ok(true)
+    }
+
+    /// 503 for an upstream that cannot take the request right now.
+    async fn send_unavailable(
+        &self,
+        session: &mut Session,
+        code: &str,
+        msg: &str,
+        retry_after: Duration,
+    ) -> Result<()> {
+        let body = format!(r#"{{"error":{{"code":"{}","message":"{}"}}}}"#, code, msg);
+        let mut header = ResponseHeader::build(503, None)?;
+        header.insert_header("Content-Type", "application/json")?;
+        header.insert_header("Content-Length", body.len().to_string())?;
+        // Round up so clients do not come back too early
+        header.insert_header(
+            "Retry-After",
+            (retry_after.as_secs_f64().ceil() as u64).max(1).to_string(),
+        )?;
+        header.insert_header("Access-Control-Allow-Origin", "*")?;
+        header.insert_header("Access-Control-Expose-Headers", "Retry-After")?;
+        session
+            .write_response_header(Box::new(header), true)
+            .await?;
+        session.write_response_body(Some(body.into()), true).await?;
+        Ok(())
+    }
+
+    async fn send_method_not_allowed(
+        &self,
+        session: &mut Session,
+        allowed: &[String],
+    ) -> Result<bool> {
+        let body = r#"{"error":{"code":"METHOD_NOT_ALLOWED","message":"Method not allowed"}}"#;
+        let mut header = ResponseHeader::build(405, None)?;
+        header.insert_header("Content-Type", "application/json")?;
+        header.insert_header("Content-Length", body.len().to_string())?;
+        header.insert_header("Allow", allowed.join(", "))?;
+        header.insert_header("Access-Control-Allow-Origin", "*")?;
         session
             .write_response_header(Box::new(header), true)
             .await?;
@@ -49,6 +169,13 @@ impl AuthGateway {
 pub struct AuthGateway {
     jwt_validator: Arc<JwtValidator>,
     config_cache: Arc<ProxyConfigCache>,
+    rate_limit_cache: Arc<RateLimitCache>,
+    revocation_cache: Arc<RevocationCache>,
+    api_key_cache: Arc<ApiKeyCache>,
+    connection_tracker: Arc<ConnectionTracker>,
+    circuit_breaker: Arc<CircuitBreaker>,
+    concurrency_limiter: Arc<ConcurrencyLimiter>,
+    usage_meter: Arc<UsageMeter>,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -58,46 +185,188 @@ pub enum ConnectionType {
     Sse,
 }
 
+/// Why a bearer token was not accepted.
+enum AuthFailure {
+    Missing,
+    Expired,
+    Invalid,
+    UserRevoked,
+    TokenRevoked,
+}
+
+impl AuthFailure {
+    fn reason(&self) -> &'static str {
+        match self {
+            Self::Missing => "missing token",
+            Self::Expired => "token expired",
+            Self::Invalid => "invalid token",
+            Self::UserRevoked => "user deactivated or deleted",
+            Self::TokenRevoked => "token revoked by logout",
+        }
+    }
+
+    /// Message of the 401 response.
+    fn message(&self) -> &'static str {
+        match self {
+            Self::Missing => "Missing token",
+            Self::Expired => "Token expired",
+            Self::Invalid => "Invalid token",
+            Self::UserRevoked | Self::TokenRevoked => "Token revoked",
+        }
+    }
+}
+
 pub struct RequestCtx {
     pub user_id: Option<String>,
+    /// Hash of the request's `X-API-Key`, set only when the key exists
+    pub api_key_hash: Option<String>,
+    /// Claims of the validated access token, forwarded as identity headers
+    pub claims: Option<AccessTokenClaims>,
     pub request_id: String,
     pub should_refresh: bool,
     pub matched_route: Option<MatchedRoute>,
     pub connection_type: ConnectionType,
     pub origin: Option<String>,
+    /// Answer the WebSocket handshake with the `bearer` subprotocol
+    pub ws_bearer_protocol: bool,
+    /// When a WebSocket or SSE connection must be closed
+    pub connection_deadline: Option<Instant>,
+    /// Counts this WebSocket or SSE connection until the context is dropped
+    pub connection_guard: Option<ConnectionGuard>,
+    /// Pool target chosen in `upstream_peer`
+    pub upstream_target: Option<TargetGuard>,
+    /// In-flight slot on a route with `max_in_flight`, freed with the context
+    pub concurrency_slot: Option<SlotGuard>,
+    /// Failed upstream connects retried so far
+    pub retries: usize,
+    /// Upstream whose outcome is not yet reported to the circuit breaker
+    pub upstream_address: Option<String>,
+    /// Set when the upstream's circuit is open, with the time until a probe
+    pub circuit_retry_after: Option<Duration>,
+    /// Subject and route the request is metered under
+    pub usage_key: Option<UsageKey>,
+    /// Response body bytes sent to the client
+    pub response_bytes: u64,
+    pub request_start: Instant,
 }
 
 impl AuthGateway {
-    pub fn new(jwt_validator: Arc<JwtValidator>, config_cache: Arc<ProxyConfigCache>) -> Self {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        jwt_validator: Arc<JwtValidator>,
+        config_cache: Arc<ProxyConfigCache>,
+        rate_limit_cache: Arc<RateLimitCache>,
+        revocation_cache: Arc<RevocationCache>,
+        api_key_cache: Arc<ApiKeyCache>,
+        connection_tracker: Arc<ConnectionTracker>,
+        concurrency_limiter: Arc<ConcurrencyLimiter>,
+        circuit_breaker: Arc<CircuitBreaker>,
+        usage_meter: Arc<UsageMeter>,
+    ) -> Self {
         Self {
             jwt_validator,
             config_cache,
+            rate_limit_cache,
+            revocation_cache,
+            api_key_cache,
+            connection_tracker,
+            circuit_breaker,
+            concurrency_limiter,
+            usage_meter,
+        }
+    }
+
+    /// Validates a bearer token and checks it against the revocation lists.
+    async fn authenticate(
+        &self,
+        token: Option<(&str, TokenSource)>,
+    ) -> std::result::Result<AccessTokenClaims, AuthFailure> {
+        let (token, source) = token.ok_or(AuthFailure::Missing)?;
+        debug!(source = ?source, "Bearer token found");
+        let claims = self
+            .jwt_validator
+            .validate(token)
+            .await
+            .map_err(|e| match e {
+                JwtError::Expired => AuthFailure::Expired,
+                JwtError::Invalid => AuthFailure::Invalid,
+            })?;
+        if self.revocation_cache.is_user_revoked(&claims.sub) {
+            return Err(AuthFailure::UserRevoked);
+        }
+        if self.revocation_cache.is_token_revoked(&claims.jti) {
+            return Err(AuthFailure::TokenRevoked);
         }
+        Ok(claims)
     }
 
-    fn extract_bearer_token(req: &RequestHeader) -> Option<&str> {
-        req.headers
-            .get("authorization")
-            .and_then(|v: &HeaderValue| v.to_str().ok())
-            .and_then(|s: &str| s.strip_prefix("Bearer "))
+    fn set_identity(&self, ctx: &mut RequestCtx, claims: AccessTokenClaims) {
+        ctx.user_id = Some(claims.sub.to_string());
+        ctx.should_refresh = self.jwt_validator.should_refresh(&claims);
+        ctx.claims = Some(claims);
     }
 
-    fn parse_upstream(addr: &str) -> (String, u16) {
-        let parts: Vec<&str> = addr.split(':').collect();
-        let host = parts[0].to_string();
-        let port = parts.get(1).and_then(|p| p.parse().ok()).unwrap_or(80);
-        (host, port)
+    /// Why a long-lived connection must be closed now, if it must: the
+    /// route's lifetime has passed, or its user or token was revoked.
+    fn connection_expired(&self, ctx: &RequestCtx) -> Option<&'static str> {
+        if ctx.connection_deadline.is_some_and(|d| Instant::now() >= d) {
+            return Some("connection lifetime exceeded");
+        }
+        let claims = ctx.claims.as_ref()?;
+        if self.revocation_cache.is_user_revoked(&claims.sub) {
+            Some(AuthFailure::UserRevoked.reason())
+        } else if self.revocation_cache.is_token_revoked(&claims.jti) {
+            Some(AuthFailure::TokenRevoked.reason())
+        } else {
+            None
+        }
+    }
+
+    /// Fails the body filter of an expired connection, which makes Pingora
+    /// tear down the SSE stream or WebSocket tunnel.
+    fn enforce_connection_deadline(&self, ctx: &RequestCtx) -> Result<()> {
+        if ctx.connection_type == ConnectionType::Http {
+            return Ok(());
+        }
+        match self.connection_expired(ctx) {
+            Some(reason) => {
+                warn!(
+                    req_id = %ctx.request_id,
+                    user_id = ?ctx.user_id,
+                    conn_type = ?ctx.connection_type,
+                    "Closing connection: {}",
+                    reason
+                );
+                Err(pingora::Error::explain(
+                    pingora::ErrorType::Custom("ConnectionExpired"),
+                    reason,
+                ))
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Gives back the quota counted in `request_filter` for a request that
+    /// is refused before it reaches an upstream.
+    fn refund_usage(&self, ctx: &mut RequestCtx) {
+        if let Some(key) = ctx.usage_key.take() {
+            self.usage_meter.refund(&key, chrono::Utc::now());
+        }
+    }
+
+    fn client_ip(session: &Session) -> Option<String> {
+        session
+            .client_addr()
+            .and_then(|addr| addr.as_inet())
+            .map(|addr| addr.ip().to_string())
     }
 
-    fn fallback_resolve(addr: &str) -> SocketAddr {
-        use std::net::ToSocketAddrs;
-        addr.to_socket_addrs()
-            .ok()
-            .and_then(|mut addrs| addrs.next())
-            .unwrap_or_else(|| {
-                let (host, port) = Self::parse_upstream(addr);
-                SocketAddr::new(host.parse().unwrap_or([127, 0, 0, 1].into()), port)
-            })
+    /// Host the request is for: the HTTP/2 `:authority`, else the `Host` header.
+    fn request_host(req: &RequestHeader) -> Option<String> {
+        req.uri
+            .host()
+            .or_else(|| req.headers.get("host").and_then(|v| v.to_str().ok()))
+            .and_then(normalize_host)
     }
 
     fn detect_connection_type(req: &RequestHeader) -> ConnectionType {
@@ -137,11 +406,24 @@ impl ProxyHttp for AuthGateway {
     fn new_ctx(&self) -> Self::CTX {
         RequestCtx {
             user_id: None,
+            api_key_hash: None,
+            claims: None,
             request_id: Uuid::new_v4().to_string(),
             should_refresh: false,
             matched_route: None,
             connection_type: ConnectionType::Http,
             origin: None,
+            ws_bearer_protocol: false,
+            connection_deadline: None,
+            connection_guard: None,
+            upstream_target: None,
+            concurrency_slot: None,
+            retries: 0,
+            upstream_address: None,
+            circuit_retry_after: None,
+            usage_key: None,
+            response_bytes: 0,
+            request_start: Instant::now(),
         }
     }
 
@@ -164,35 +446,34 @@ impl ProxyHttp for AuthGateway {
             return self.send_cors_preflight(session).await;
         }
 
-        let headers = &session.req_header().headers;
-        if headers.contains_key("x-user-id") || headers.contains_key("x-request-id") {
-            warn!(
-                req_id = %ctx.request_id,
-                method = %method,
-                path = %path,
-                "Rejected: reserved header detected"
-            );
-            return self
-                .send_error(session, 400, "Reserved header detected")
-                .await;
-        }
-
         ctx.connection_type = Self::detect_connection_type(session.req_header());
 
         if ctx.connection_type != ConnectionType::Http {
             debug!(
                 path = %path,
                 conn_type = ?ctx.connection_type,
-                "Long-lived connection detected, auth will be performed once"
+                "Long-lived connection detected"
             );
         }
 
-        let matched = match self.config_cache.match_route(path) {
-            Some(r) => r,
-            None => {
+        let host = Self::request_host(session.req_header());
+        let matched = match self.config_cache.match_route(host.as_deref(), method, path) {
+            RouteLookup::Matched(r) => *r,
+            RouteLookup::MethodNotAllowed(allowed) => {
+                warn!(
+                    req_id = %ctx.request_id,
+                    method = %method,
+                    host = ?host,
+                    path = %path,
+                    "Method not allowed on route"
+                );
+                return self.send_method_not_allowed(session, &allowed).await;
+            }
+            RouteLookup::NotFound => {
                 warn!(
                     req_id = %ctx.request_id,
                     method = %method,
+                    host = ?host,
                     path = %path,
                     "No route matched"
                 );
@@ -200,54 +481,249 @@ impl ProxyHttp for AuthGateway {
             }
         };
 
+        if matched
+            .identity_headers
+            .is_spoofed(&session.req_header().headers)
+        {
+            warn!(
+                req_id = %ctx.request_id,
+                method = %method,
+                path = %path,
+                "Rejected: reserved header detected"
+            );
+            return self
+                .send_error(session, 400, "Reserved header detected")
+                .await;
+        }
+
+        let query = match matched.token_sources.query_param {
+            Some(ref param) => strip_query_param(query, param).unwrap_or_default(),
+            None => query.to_string(),
+        };
         info!(
             req_id = %ctx.request_id,
             method = %method,
             path = %path,
             query = %query,
             upstream = %matched.upstream_address,
-            auth = %matched.require_auth,
+            auth = matched.auth_mode.as_str(),
             "Request received"
         );
 
-        if matched.require_auth {
-            let token = match Self::extract_bearer_token(session.req_header()) {
-                Some(t) => t,
-                None => {
+        let long_lived = ctx.connection_type != ConnectionType::Http;
+        match matched.auth_mode {
+            AuthMode::None => {}
+            AuthMode::Optional => {
+                let req = session.req_header();
+                let token = matched
+                    .token_sources
+                    .extract(&req.headers, &req.uri, long_lived);
+                match self.authenticate(token).await {
+                    Ok(claims) => self.set_identity(ctx, claims),
+                    Err(AuthFailure::Missing) => {}
+                    Err(failure) => debug!(
+                        req_id = %ctx.request_id,
+                        path = %path,
+                        reason = failure.reason(),
+                        "Optional auth: continuing anonymously"
+                    ),
+                }
+            }
+            AuthMode::Required => {
+                let req = session.req_header();
+                let token = matched
+                    .token_sources
+                    .extract(&req.headers, &req.uri, long_lived);
+                let claims = match self.authenticate(token).await {
+                    Ok(c) => c,
+                    Err(failure) => {
+                        warn!(
+                            req_id = %ctx.request_id,
+                            method = %method,
+                            path = %path,
+                            "Auth failed: {}",
+                            failure.reason()
+                        );
+                        return self.send_error(session, 401, failure.message()).await;
+                    }
+                };
+
+                if !JwtValidator::meets_step_up(
+                    &claims,
+                    matched.max_auth_age_secs,
+                    matched.require_mfa,
+                ) {
                     warn!(
                         req_id = %ctx.request_id,
                         method = %method,
                         path = %path,
-                        "Auth failed: missing token"
+                        user_id = %claims.sub,
+                        auth_time = claims.auth_time,
+                        amr = ?claims.amr,
+                        "Auth failed: step-up authentication required"
                     );
-                    return self.send_error(session, 401, "Missing token").await;
+                    return self.send_step_up_required(session, &matched).await;
                 }
-            };
 
-            let claims = match self.jwt_validator.validate(token).await {
-                Ok(c) => c,
-                Err(JwtError::Expired) => {
+                if !JwtValidator::has_required_role(&claims, &matched.required_roles) {
                     warn!(
                         req_id = %ctx.request_id,
                         method = %method,
                         path = %path,
-                        "Auth failed: token expired"
+                        user_id = %claims.sub,
+                        roles = ?claims.roles,
+                        "Auth failed: missing required role"
                     );
-                    return self.send_error(session, 401, "Token expired").await;
+                    return self
+                        .send_error_code(
+                            session,
+                            403,
+                            "INSUFFICIENT_ROLE",
+                            "Missing required role",
+                            None,
+                        )
+                        .await;
+                }
+
+                self.set_identity(ctx, claims);
+            }
+        }
+
+        let client_ip = Self::client_ip(session);
+        let api_key = session
+            .req_header()
+            .headers
+            .get("x-api-key")
+            .and_then(|v| v.to_str().ok());
+        // Unknown keys are limited like requests without a key
+        ctx.api_key_hash = api_key.and_then(|key| self.api_key_cache.verify(key));
+        let subject = RateLimitSubject {
+            ip: client_ip.as_deref(),
+            user_id: ctx.user_id.as_deref(),
+            api_key_hash: ctx.api_key_hash.as_deref(),
+        };
+        if let Some(retry_after) = self.rate_limit_cache.check(path, &subject) {
+            warn!(
+                req_id = %ctx.request_id,
+                method = %method,
+                path = %path,
+                client_ip = ?client_ip,
+                user_id = ?ctx.user_id,
+                retry_after = ?retry_after,
+                "Rejected: rate limit exceeded"
+            );
+            return self
+                .send_too_many_requests(
+                    session,
+                    "RATE_LIMITED",
+                    "Rate limit exceeded",
+                    retry_after.as_secs_f64().ceil() as u64,
+                )
+                .await;
+        }
+
+        if ctx.connection_type != ConnectionType::Http {
+            let subject = ConnectionSubject::for_connection(
+                ctx.user_id.as_deref(),
+                ctx.api_key_hash.as_deref(),
+                client_ip.as_deref(),
+            );
+            if let Some(subject) = subject {
+                match self.connection_tracker.acquire(
+                    &matched.route_key(),
+                    subject,
+                    matched.max_connections_per_user,
+                ) {
+                    Some(guard) => ctx.connection_guard = Some(guard),
+                    None => {
+                        warn!(
+                            req_id = %ctx.request_id,
+                            path = %path,
+                            user_id = ?ctx.user_id,
+                            limit = ?matched.max_connections_per_user,
+                            "Rejected: too many open connections"
+                        );
+                        return self
+                            .send_error_code(
+                                session,
+                                429,
+                                "TOO_MANY_CONNECTIONS",
+                                "Too many open connections",
+                                None,
+                            )
+                            .await;
+                    }
                 }
-                Err(JwtError::Invalid) => {
+            }
+
+            let token_exp = ctx.claims.as_ref().map(|c| c.exp);
+            if let Some(lifetime) =
+                matched.connection_lifetime(token_exp, chrono::Utc::now().timestamp())
+            {
+                ctx.connection_deadline = Some(Instant::now() + lifetime);
+            }
+        }
+
+        if let Some(ref limit) = matched.concurrency_limit {
+            match self
+                .concurrency_limiter
+                .acquire(&matched.route_key(), ctx.user_id.as_deref(), limit)
+                .await
+            {
+                Some(slot) => ctx.concurrency_slot = Some(slot),
+                None => {
                     warn!(
                         req_id = %ctx.request_id,
-                        method = %method,
                         path = %path,
-                        "Auth failed: invalid token"
+                        user_id = ?ctx.user_id,
+                        max_in_flight = limit.max_in_flight,
+                        "Rejected: upstream busy"
                     );
-                    return self.send_error(session, 401, "Invalid token").await;
+                    self.send_unavailable(
+                        session,
+                        "UPSTREAM_BUSY",
+                        "Upstream is at capacity, try again later",
+                        limit.queue_timeout,
+                    )
+                    .await?;
+                    return Ok(true);
                 }
-            };
+            }
+        }
 
-            ctx.user_id = Some(claims.sub.to_string());
-            ctx.should_refresh = self.jwt_validator.should_refresh(&claims);
+        // Counted last, so requests rejected above do not use up the quota
+        if let Some(subject) =
+            ConnectionSubject::from_identity(ctx.user_id.as_deref(), ctx.api_key_hash.as_deref())
+        {
+            let key = UsageKey {
+                subject,
+                route: matched.route_key(),
+            };
+            if let Err(exceeded) = self
+                .usage_meter
+                .admit(&key, &matched.quota, chrono::Utc::now())
+            {
+                warn!(
+                    req_id = %ctx.request_id,
+                    path = %path,
+                    user_id = ?ctx.user_id,
+                    period = ?exceeded.period,
+                    "Rejected: quota exceeded"
+                );
+                let msg = match exceeded.period {
+                    QuotaPeriod::Daily => "Daily quota exceeded",
+                    QuotaPeriod::Monthly => "Monthly quota exceeded",
+                };
+                return self
+                    .send_too_many_requests(
+                        session,
+                        "QUOTA_EXCEEDED",
+                        msg,
+                        exceeded.retry_after.as_secs_f64().ceil() as u64,
+                    )
+                    .await;
+            }
+            ctx.usage_key = Some(key);
         }
 
         ctx.matched_route = Some(matched);
@@ -261,16 +737,78 @@ impl ProxyHttp for AuthGateway {
         ctx: &mut Self::CTX,
     ) -> Result<Box<HttpPeer>> {
         let start = std::time::Instant::now();
-        let addr = ctx
+        let mut addr = ctx
+            .matched_route
+            .as_ref()
+            .map(|r| r.upstream_address.clone())
+            .unwrap_or_else(|| self.config_cache.auth_upstream().to_string());
+
+        let pool_name = ctx
             .matched_route
             .as_ref()
-            .map(|r| r.upstream_address.as_str())
-            .unwrap_or(self.config_cache.auth_upstream());
+            .and_then(|r| r.upstream_pool.as_deref());
+        if let Some(name) = pool_name {
+            match self.config_cache.upstream_pool(name) {
+                // Prefer targets with closed circuits; if there are none, the
+                // pick below is rejected or becomes the half-open probe
+                Some(pool) => match pool
+                    .select(ctx.user_id.as_deref(), |address| {
+                        self.circuit_breaker.is_open(address, Instant::now())
+                    })
+                    .or_else(|| pool.select(ctx.user_id.as_deref(), |_| false))
+                {
+                    Some(target) => {
+                        addr = target.address().to_string();
+                        ctx.upstream_target = Some(target);
+                    }
+                    None => {
+                        warn!(
+                            req_id = %ctx.request_id,
+                            pool = %name,
+                            "No healthy upstream target"
+                        );
+                        self.refund_usage(ctx);
+                        return Err(pingora::Error::explain(
+                            pingora::ErrorType::HTTPStatus(503),
+                            "no healthy upstream target",
+                        ));
+                    }
+                },
+                None => warn!(
+                    req_id = %ctx.request_id,
+                    pool = %name,
+                    "Unknown upstream pool, using upstream_address"
+                ),
+            }
+        }
+        if let Admission::Reject(retry_after) = self.circuit_breaker.admit(&addr, Instant::now()) {
+            debug!(req_id = %ctx.request_id, upstream = %addr, "Upstream circuit open");
+            ctx.circuit_retry_after = Some(retry_after);
+            self.refund_usage(ctx);
+            return Err(pingora::Error::explain(
+                pingora::ErrorType::HTTPStatus(503),
+                "upstream circuit open",
+            ));
+        }
+        ctx.upstream_address = Some(addr.clone());
+        let addr = addr.as_str();
 
-        let socket_addr = self
-            .config_cache
-            .get_resolved_addr(addr)
-            .unwrap_or_else(|| Self::fallback_resolve(addr));
+        // Cache misses only happen until the background resolver catches up
+        // with a route change; resolve without blocking the worker thread.
+        let socket_addr = match self.config_cache.get_resolved_addr(addr) {
+            Some(resolved) => resolved,
+            None => match self.config_cache.resolve(addr).await {
+                Some(resolved) => resolved,
+                None => {
+                    warn!(req_id = %ctx.request_id, upstream = %addr, "Upstream did not resolve");
+                    self.refund_usage(ctx);
+                    return Err(pingora::Error::explain(
+                        pingora::ErrorType::HTTPStatus(502),
+                        "upstream address did not resolve",
+                    ));
+                }
+            },
+        };
 
         info!(
             req_id = %ctx.request_id,
@@ -280,36 +818,86 @@ impl ProxyHttp for AuthGateway {
             "upstream_peer resolved"
         );
 
-        let peer = HttpPeer::new(socket_addr, false, String::new());
+        let mut peer = HttpPeer::new(socket_addr, false, String::new());
+        if let Some(ref matched) = ctx.matched_route {
+            let options = &matched.upstream_options;
+            peer.options.connection_timeout = options.connect_timeout;
+            peer.options.read_timeout = options.read_timeout;
+            peer.options.write_timeout = options.write_timeout;
+            peer.options.idle_timeout = options.idle_timeout;
+        }
+        // Idle tunnels see no body chunks to check the deadline on. The read
+        // timeout counts from the last upstream read, so it bounds how long an
+        // idle connection outlives its deadline rather than closing it there.
+        if let Some(deadline) = ctx.connection_deadline {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            peer.options.read_timeout = Some(
+                peer.options
+                    .read_timeout
+                    .map_or(remaining, |timeout| timeout.min(remaining)),
+            );
+        }
         Ok(Box::new(peer))
     }
 
+    fn fail_to_connect(
+        &self,
+        session: &mut Session,
+        peer: &HttpPeer,
+        ctx: &mut Self::CTX,
+        mut e: Box<pingora::Error>,
+    ) -> Box<pingora::Error> {
+        if let Some(upstream) = ctx.upstream_address.take() {
+            if self
+                .circuit_breaker
+                .record_failure(&upstream, Instant::now())
+            {
+                warn!(upstream = %upstream, "Upstream circuit opened");
+            }
+        }
+        let allows_retry = ctx.matched_route.as_ref().is_some_and(|r| {
+            r.upstream_options
+                .allows_retry(&session.req_header().method, ctx.retries)
+        });
+        if allows_retry {
+            ctx.retries += 1;
+            warn!(
+                req_id = %ctx.request_id,
+                upstream = %peer,
+                attempt = ctx.retries,
+                error = %e,
+                "Upstream connect failed, retrying"
+            );
+            e.set_retry(true);
+        }
+        e
+    }
+
     async fn upstream_request_filter(
         &self,
-        _session: &mut Session,
+        session: &mut Session,
         upstream_request: &mut RequestHeader,
         ctx: &mut Self::CTX,
     ) -> Result<()> {
         if let Some(ref matched) = ctx.matched_route {
-            if let Some(ref prefix) = matched.strip_prefix {
-                let original_uri = upstream_request.uri.clone();
-                let path = original_uri.path();
-                let stripped = path.strip_prefix(prefix.as_str()).unwrap_or(path);
-
-                let new_path = if stripped.is_empty() || !stripped.starts_with('/') {
-                    format!("/{}", stripped.trim_start_matches('/'))
-                } else {
-                    stripped.to_string()
-                };
-                let new_path = if new_path.is_empty() {
-                    "/".to_string()
-                } else {
-                    new_path
-                };
-
+            let original_uri = upstream_request.uri.clone();
+            let path = original_uri.path();
+            let new_path = match (&matched.upstream_path, &matched.strip_prefix) {
+                (Some(rewritten), _) => Some(rewritten.clone()),
+                (None, Some(prefix)) => {
+                    let stripped = path.strip_prefix(prefix.as_str()).unwrap_or(path);
+                    Some(if stripped.is_empty() || !stripped.starts_with('/') {
+                        format!("/{}", stripped.trim_start_matches('/'))
+                    } else {
+                        stripped.to_string()
+                    })
+                }
+                (None, None) => None,
+            };
+            if let Some(new_path) = new_path {
                 let path_and_query = match original_uri.query() {
                     Some(q) => format!("{}?{}", new_path, q),
-                    None => new_path,
+                    None => new_path.clone(),
                 };
 
                 match http::Uri::builder()
@@ -321,7 +909,7 @@ impl ProxyHttp for AuthGateway {
                         warn!(
                             req_id = %ctx.request_id,
                             original_path = %path,
-                            attempted = %path_and_query,
+                            attempted = %new_path,
                             error = %e,
                             "Failed to build URI, using root"
                         );
@@ -333,13 +921,103 @@ impl ProxyHttp for AuthGateway {
             }
         }
 
+        if let Some(ref matched) = ctx.matched_route {
+            // Tokens from the query string or subprotocol never reach upstreams
+            if let Some(ref param) = matched.token_sources.query_param {
+                let uri = upstream_request.uri.clone();
+                if let Some(query) = uri.query() {
+                    let path_and_query = match strip_query_param(query, param) {
+                        Some(q) => format!("{}?{}", uri.path(), q),
+                        None => uri.path().to_string(),
+                    };
+                    if let Ok(uri) = http::Uri::builder()
+                        .path_and_query(path_and_query.as_str())
+                        .build()
+                    {
+                        upstream_request.set_uri(uri);
+                    }
+                }
+            }
+            if matched.token_sources.subprotocol
+                && offers_bearer_protocol(&upstream_request.headers)
+            {
+                ctx.ws_bearer_protocol = true;
+                match strip_subprotocol_token(&upstream_request.headers) {
+                    Some(protocols) => {
+                        upstream_request.insert_header("Sec-WebSocket-Protocol", protocols)?
+                    }
+                    None => {
+                        upstream_request.remove_header("Sec-WebSocket-Protocol");
+                    }
+                }
+            }
+        }
+
+        if ctx.connection_type == ConnectionType::Http
+            && ctx
+                .matched_route
+                .as_ref()
+                .is_some_and(|r| !r.upstream_options.keepalive)
+        {
+            upstream_request.insert_header("Connection", "close")?;
+        }
+
         upstream_request.insert_header("X-Request-Id", &ctx.request_id)?;
-        if let Some(user_id) = &ctx.user_id {
-            upstream_request.insert_header("X-User-Id", user_id)?;
+        upstream_request.remove_header(IDENTITY_ASSERTION_HEADER);
+        // Overwrites any client-supplied value; upstreams behind the gateway
+        // trust this header for the caller's address.
+        if let Some(ip) = Self::client_ip(session) {
+            upstream_request.insert_header("X-Real-IP", ip)?;
+        }
+        let auth_method = match (&ctx.claims, &ctx.api_key_hash) {
+            (Some(_), _) => Some(AuthMethod::Jwt),
+            (None, Some(_)) => Some(AuthMethod::ApiKey),
+            (None, None) => None,
+        };
+        if let (Some(matched), Some(method)) = (&ctx.matched_route, auth_method) {
+            for (name, value) in matched.identity_headers.values(ctx.claims.as_ref(), method) {
+                upstream_request.insert_header(name.clone(), value)?;
+            }
+        }
+        if let Some(ref matched) = ctx.matched_route {
+            let upstream = ctx
+                .upstream_target
+                .as_ref()
+                .map(|t| t.address())
+                .unwrap_or(&matched.upstream_address);
+            if let Some(signer) = self.config_cache.identity_signer(upstream) {
+                let assertion = IdentityAssertion::new(
+                    ctx.claims.as_ref().map(|c| c.sub),
+                    &ctx.request_id,
+                    upstream_request.method.as_str(),
+                    path_and_query(&upstream_request.uri),
+                    chrono::Utc::now().timestamp(),
+                );
+                match signer.sign(&assertion) {
+                    Ok(token) => {
+                        upstream_request.insert_header(IDENTITY_ASSERTION_HEADER, token)?
+                    }
+                    Err(e) => warn!(
+                        req_id = %ctx.request_id,
+                        error = %e,
+                        "Failed to sign identity assertion"
+                    ),
+                }
+            }
         }
         Ok(())
     }
 
+    async fn request_body_filter(
+        &self,
+        _session: &mut Session,
+        _body: &mut Option<bytes::Bytes>,
+        _end_of_stream: bool,
+        ctx: &mut Self::CTX,
+    ) -> Result<()> {
+        self.enforce_connection_deadline(ctx)
+    }
+
     async fn response_filter(
         &self,
         session: &mut Session,
@@ -347,6 +1025,18 @@ impl ProxyHttp for AuthGateway {
         ctx: &mut Self::CTX,
     ) -> Result<()> {
         let status = upstream_response.status.as_u16();
+        if let Some(upstream) = ctx.upstream_address.take() {
+            if status >= 500 {
+                if self
+                    .circuit_breaker
+                    .record_failure(&upstream, Instant::now())
+                {
+                    warn!(upstream = %upstream, status = status, "Upstream circuit opened");
+                }
+            } else {
+                self.circuit_breaker.record_success(&upstream);
+            }
+        }
         let content_type = upstream_response
             .headers
             .get("content-type")
@@ -368,6 +1058,15 @@ impl ProxyHttp for AuthGateway {
             upstream_response.insert_header("Cache-Control", "no-cache")?;
         }
 
+        if ctx.ws_bearer_protocol
+            && status == 101
+            && !upstream_response
+                .headers
+                .contains_key("sec-websocket-protocol")
+        {
+            upstream_response.insert_header("Sec-WebSocket-Protocol", WS_BEARER_PROTOCOL)?;
+        }
+
         if ctx.should_refresh {
             upstream_response.insert_header("X-Token-Refresh", "true")?;
         }
@@ -385,8 +1084,9 @@ impl ProxyHttp for AuthGateway {
         end_of_stream: bool,
         ctx: &mut Self::CTX,
     ) -> Result<Option<std::time::Duration>> {
+        let size = body.as_ref().map(|b| b.len()).unwrap_or(0);
+        ctx.response_bytes += size as u64;
         if ctx.connection_type == ConnectionType::Sse {
-            let size = body.as_ref().map(|b| b.len()).unwrap_or(0);
             debug!(
                 req_id = %ctx.request_id,
                 size = size,
@@ -394,6 +1094,83 @@ impl ProxyHttp for AuthGateway {
                 "SSE body chunk"
             );
         }
+        self.enforce_connection_deadline(ctx)?;
         Ok(None)
     }
+
+    async fn fail_to_proxy(
+        &self,
+        session: &mut Session,
+        e: &pingora::Error,
+        ctx: &mut Self::CTX,
+    ) -> FailToProxy {
+        if let Some(retry_after) = ctx.circuit_retry_after.take() {
+            if let Err(err) = self
+                .send_unavailable(
+                    session,
+                    "UPSTREAM_UNAVAILABLE",
+                    "Upstream is failing, try again later",
+                    retry_after,
+                )
+                .await
+            {
+                warn!(req_id = %ctx.request_id, error = %err, "Failed to send error response");
+            }
+            return FailToProxy {
+                error_code: 503,
+                can_reuse_downstream: false,
+            };
+        }
+
+        // Same as the default implementation
+        let code = match e.etype() {
+            pingora::ErrorType::HTTPStatus(code) => *code,
+            _ => match e.esource() {
+                pingora::ErrorSource::Upstream => 502,
+                pingora::ErrorSource::Downstream => match e.etype() {
+                    pingora::ErrorType::WriteError
+                    | pingora::ErrorType::ReadError
+                    | pingora::ErrorType::ConnectionClosed => 0,
+                    _ => 400,
+                },
+                pingora::ErrorSource::Internal | pingora::ErrorSource::Unset => 500,
+            },
+        };
+        if code > 0 {
+            if let Err(err) = session.respond_error(code).await {
+                warn!(req_id = %ctx.request_id, error = %err, "Failed to send error response");
+            }
+        }
+        FailToProxy {
+            error_code: code,
+            can_reuse_downstream: false,
+        }
+    }
+
+    async fn logging(
+        &self,
+        _session: &mut Session,
+        e: Option<&pingora::Error>,
+        ctx: &mut Self::CTX,
+    ) {
+        if let Some(key) = ctx.usage_key.take() {
+            self.usage_meter.record_response(
+                &key,
+                ctx.response_bytes,
+                ctx.request_start.elapsed(),
+                chrono::Utc::now(),
+            );
+        }
+
+        // Upstream errors after the connect, such as read timeouts or resets
+        if let (Some(upstream), Some(e)) = (ctx.upstream_address.take(), e) {
+            if matches!(e.esource(), pingora::ErrorSource::Upstream)
+                && self
+                    .circuit_breaker
+                    .record_failure(&upstream, Instant::now())
+            {
+                warn!(upstream = %upstream, error = %e, "Upstream circuit opened");
+            }
+        }
+    }
 }
@@ -0,0 +1,372 @@
+use std::collections::HashMap;
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use uuid::Uuid;
+
+use crate::models::RateLimitRule;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LimitBy {
+    Ip,
+    User,
+    ApiKey,
+}
+
+impl LimitBy {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "ip" => Some(Self::Ip),
+            "user" => Some(Self::User),
+            "api_key" => Some(Self::ApiKey),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CachedRateLimitRule {
+    pub id: Uuid,
+    pub path_pattern: String,
+    pub limit_by: LimitBy,
+    pub max_requests: usize,
+    pub window: Duration,
+}
+
+impl CachedRateLimitRule {
+    /// Converts a database rule, skipping disabled rules and rules with an
+    /// unknown `limit_by` or non-positive limits.
+    pub fn from_rule(rule: RateLimitRule) -> Option<Self> {
+        if !rule.enabled || rule.max_requests <= 0 || rule.window_secs <= 0 {
+            return None;
+        }
+        let limit_by = match LimitBy::parse(&rule.limit_by) {
+            Some(l) => l,
+            None => {
+                tracing::warn!(
+                    rule = %rule.name,
+                    limit_by = %rule.limit_by,
+                    "Ignoring rate limit rule with unknown limit_by"
+                );
+                return None;
+            }
+        };
+        Some(Self {
+            id: rule.id,
+            path_pattern: rule.path_pattern,
+            limit_by,
+            max_requests: rule.max_requests as usize,
+            window: Duration::from_secs(rule.window_secs as u64),
+        })
+    }
+}
+
+/// Identity of the caller used to pick the counter key of a rule.
+/// Rules limited by user or API key fall back to the client IP when the
+/// caller did not present that identity, so omitting it cannot bypass the rule.
+/// `api_key_hash` must only be set for a key that exists; otherwise a client
+/// could send a new made-up key with every request.
+#[derive(Debug, Default)]
+pub struct RateLimitSubject<'a> {
+    pub ip: Option<&'a str>,
+    pub user_id: Option<&'a str>,
+    pub api_key_hash: Option<&'a str>,
+}
+
+impl RateLimitSubject<'_> {
+    fn key_for(&self, limit_by: LimitBy) -> Option<String> {
+        let by_ip = || self.ip.map(|ip| format!("ip:{}", ip));
+        match limit_by {
+            LimitBy::Ip => by_ip(),
+            LimitBy::User => self.user_id.map(|id| format!("user:{}", id)).or_else(by_ip),
+            LimitBy::ApiKey => self
+                .api_key_hash
+                .map(|hash| format!("apikey:{}", hash))
+                .or_else(by_ip),
+        }
+    }
+}
+
+pub struct RateLimitCache {
+    rules: RwLock<Vec<CachedRateLimitRule>>,
+    /// Sliding windows keyed by "rule_id:subject"
+    windows: Mutex<HashMap<String, Vec<Instant>>>,
+    last_cleanup: Mutex<Instant>,
+}
+
+impl Default for RateLimitCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RateLimitCache {
+    pub fn new() -> Self {
+        Self {
+            rules: RwLock::new(Vec::new()),
+            windows: Mutex::new(HashMap::new()),
+            last_cleanup: Mutex::new(Instant::now()),
+        }
+    }
+
+    pub fn update_rules(&self, rules: Vec<CachedRateLimitRule>) {
+        let ids: Vec<String> = rules.iter().map(|r| format!("{}:", r.id)).collect();
+
+        if let Ok(mut current) = self.rules.write() {
+            *current = rules;
+        }
+
+        let mut windows = match self.windows.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        windows.retain(|key, _| ids.iter().any(|prefix| key.starts_with(prefix)));
+    }
+
+    /// Records a request against every matching rule, unless one of them is
+    /// exceeded: then nothing is recorded and the longest time until the
+    /// caller may retry is returned.
+    pub fn check(&self, path: &str, subject: &RateLimitSubject<'_>) -> Option<Duration> {
+        let rules = match self.rules.read() {
+            Ok(guard) => guard,
+            Err(e) => {
+                tracing::warn!("Failed to acquire rate limit rules lock: {}", e);
+                return None;
+            }
+        };
+        if rules.is_empty() {
+            return None;
+        }
+
+        let now = Instant::now();
+        self.maybe_cleanup(now, &rules);
+
+        let mut windows = match self.windows.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+
+        let mut matched = Vec::new();
+        let mut retry_after: Option<Duration> = None;
+        for rule in rules.iter() {
+            if !path_matches(&rule.path_pattern, path) {
+                continue;
+            }
+            let subject_key = match subject.key_for(rule.limit_by) {
+                Some(k) => k,
+                None => continue,
+            };
+
+            let key = format!("{}:{}", rule.id, subject_key);
+            let timestamps = windows.entry(key.clone()).or_default();
+            timestamps.retain(|t| now.duration_since(*t) < rule.window);
+
+            if timestamps.len() >= rule.max_requests {
+                let oldest = timestamps.first().copied().unwrap_or(now);
+                let wait = rule
+                    .window
+                    .saturating_sub(now.duration_since(oldest))
+                    .max(Duration::from_secs(1));
+                retry_after = Some(retry_after.map_or(wait, |r| r.max(wait)));
+            }
+            matched.push(key);
+        }
+
+        // Rejected requests do not use up the other rules' windows
+        if retry_after.is_none() {
+            for key in matched {
+                windows.entry(key).or_default().push(now);
+            }
+        }
+        retry_after
+    }
+
+    fn maybe_cleanup(&self, now: Instant, rules: &[CachedRateLimitRule]) {
+        let cleanup_interval = Duration::from_secs(60);
+
+        let mut last = match self.last_cleanup.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        if now.duration_since(*last) <= cleanup_interval {
+            return;
+        }
+        *last = now;
+        drop(last);
+
+        let max_window = rules
+            .iter()
+            .map(|r| r.window)
+            .max()
+            .unwrap_or(Duration::ZERO);
+
+        let mut windows = match self.windows.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        windows.retain(|_, timestamps| {
+            timestamps.retain(|t| now.duration_since(*t) < max_window);
+            !timestamps.is_empty()
+        });
+    }
+}
+
+/// Matches a path against a rule pattern where `*` matches any sequence of
+/// characters, e.g. `/api/*` or `/api/*/predict`. Patterns without `*` must
+/// match the whole path.
+pub fn path_matches(pattern: &str, path: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or("");
+    let Some(mut rest) = path.strip_prefix(first) else {
+        return false;
+    };
+
+    let remaining: Vec<&str> = parts.collect();
+    if remaining.is_empty() {
+        return rest.is_empty();
+    }
+
+    let last_index = remaining.len() - 1;
+    for (i, part) in remaining.iter().enumerate() {
+        if i == last_index {
+            return rest.ends_with(part);
+        }
+        match rest.find(part) {
+            Some(pos) => rest = &rest[pos + part.len()..],
+            None => return false,
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(pattern: &str, limit_by: LimitBy, max_requests: usize) -> CachedRateLimitRule {
+        CachedRateLimitRule {
+            id: Uuid::new_v4(),
+            path_pattern: pattern.to_string(),
+            limit_by,
+            max_requests,
+            window: Duration::from_secs(60),
+        }
+    }
+
+    #[test]
+    fn test_path_matches_wildcards() {
+        assert!(path_matches("/api/*", "/api/v1/predict"));
+        assert!(path_matches("/api/*", "/api/"));
+        assert!(!path_matches("/api/*", "/apiary"));
+        assert!(path_matches("/api/*/predict", "/api/v1/predict"));
+        assert!(!path_matches("/api/*/predict", "/api/v1/train"));
+        assert!(path_matches("*", "/anything"));
+    }
+
+    #[test]
+    fn test_path_matches_exact() {
+        assert!(path_matches("/auth/login/init", "/auth/login/init"));
+        assert!(!path_matches("/auth/login/init", "/auth/login/init/x"));
+    }
+
+    #[test]
+    fn test_limits_by_ip_and_reports_retry_after() {
+        let cache = RateLimitCache::new();
+        cache.update_rules(vec![rule("/api/*", LimitBy::Ip, 2)]);
+        let subject = RateLimitSubject {
+            ip: Some("1.2.3.4"),
+            ..Default::default()
+        };
+
+        assert!(cache.check("/api/a", &subject).is_none());
+        assert!(cache.check("/api/b", &subject).is_none());
+        let retry_after = cache.check("/api/c", &subject).expect("should be limited");
+        assert!(retry_after >= Duration::from_secs(1));
+        assert!(retry_after <= Duration::from_secs(60));
+
+        assert!(cache.check("/public", &subject).is_none());
+    }
+
+    #[test]
+    fn test_limits_by_user_separately() {
+        let cache = RateLimitCache::new();
+        cache.update_rules(vec![rule("/api/*", LimitBy::User, 1)]);
+        let alice = RateLimitSubject {
+            ip: Some("1.2.3.4"),
+            user_id: Some("alice"),
+            ..Default::default()
+        };
+        let bob = RateLimitSubject {
+            ip: Some("1.2.3.4"),
+            user_id: Some("bob"),
+            ..Default::default()
+        };
+
+        assert!(cache.check("/api/x", &alice).is_none());
+        assert!(cache.check("/api/x", &alice).is_some());
+        assert!(cache.check("/api/x", &bob).is_none());
+    }
+
+    #[test]
+    fn test_api_key_rule_falls_back_to_ip() {
+        let cache = RateLimitCache::new();
+        cache.update_rules(vec![rule("/api/*", LimitBy::ApiKey, 1)]);
+        let anonymous = RateLimitSubject {
+            ip: Some("5.6.7.8"),
+            ..Default::default()
+        };
+
+        assert!(cache.check("/api/x", &anonymous).is_none());
+        assert!(cache.check("/api/x", &anonymous).is_some());
+    }
+
+    #[test]
+    fn test_rejected_request_is_not_counted_by_other_rules() {
+        let cache = RateLimitCache::new();
+        cache.update_rules(vec![
+            rule("/api/*", LimitBy::Ip, 2),
+            rule("/api/*", LimitBy::User, 1),
+        ]);
+        let alice = RateLimitSubject {
+            ip: Some("1.2.3.4"),
+            user_id: Some("alice"),
+            ..Default::default()
+        };
+        let bob = RateLimitSubject {
+            ip: Some("1.2.3.4"),
+            user_id: Some("bob"),
+            ..Default::default()
+        };
+
+        assert!(cache.check("/api/x", &alice).is_none());
+        // Refused by the user rule, so the IP window keeps one free slot
+        assert!(cache.check("/api/x", &alice).is_some());
+        assert!(cache.check("/api/x", &alice).is_some());
+        assert!(cache.check("/api/x", &bob).is_none());
+        assert!(cache.check("/api/x", &bob).is_some());
+    }
+
+    #[test]
+    fn test_update_rules_resets_removed_counters() {
+        let cache = RateLimitCache::new();
+        let r = rule("/api/*", LimitBy::Ip, 1);
+        let subject = RateLimitSubject {
+            ip: Some("1.2.3.4"),
+            ..Default::default()
+        };
+        cache.update_rules(vec![r]);
+        assert!(cache.check("/api/x", &subject).is_none());
+        assert!(cache.check("/api/x", &subject).is_some());
+
+        cache.update_rules(vec![rule("/api/*", LimitBy::Ip, 1)]);
+        assert!(cache.check("/api/x", &subject).is_none());
+    }
+
+    #[test]
+    fn test_limit_by_parse() {
+        assert_eq!(LimitBy::parse("ip"), Some(LimitBy::Ip));
+        assert_eq!(LimitBy::parse("user"), Some(LimitBy::User));
+        assert_eq!(LimitBy::parse("api_key"), Some(LimitBy::ApiKey));
+        assert_eq!(LimitBy::parse("session"), None);
+    }
+}
@@ -0,0 +1,151 @@
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+use uuid::Uuid;
+
+/// Access tokens the gateway must reject before they expire: all tokens of
+/// deactivated or recently deleted users, and single tokens revoked by logout.
+pub struct RevocationCache {
+    /// Revoked user -> until when (unix seconds); `None` for deactivated
+    /// users, who stay revoked until they are reactivated
+    users: RwLock<HashMap<Uuid, Option<i64>>>,
+    /// Deny-listed token `jti` -> token `exp` (unix seconds)
+    tokens: RwLock<HashMap<Uuid, i64>>,
+}
+
+impl Default for RevocationCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RevocationCache {
+    pub fn new() -> Self {
+        Self {
+            users: RwLock::new(HashMap::new()),
+            tokens: RwLock::new(HashMap::new()),
+        }
+    }
+
+    pub fn update_users(&self, revoked: Vec<(Uuid, Option<i64>)>) {
+        if let Ok(mut users) = self.users.write() {
+            *users = revoked.into_iter().collect();
+        }
+    }
+
+    /// Rejects a user's tokens until `until`, or until the user is restored,
+    /// dropping entries that have expired.
+    pub fn revoke_user(&self, user_id: Uuid, until: Option<i64>) {
+        let now = chrono::Utc::now().timestamp();
+        if let Ok(mut users) = self.users.write() {
+            users.retain(|_, user_until| user_until.is_none_or(|t| t > now));
+            users.insert(user_id, until);
+        }
+    }
+
+    pub fn restore_user(&self, user_id: Uuid) {
+        if let Ok(mut users) = self.users.write() {
+            users.remove(&user_id);
+        }
+    }
+
+    pub fn update_tokens(&self, tokens: Vec<(Uuid, i64)>) {
+        if let Ok(mut current) = self.tokens.write() {
+            *current = tokens.into_iter().collect();
+        }
+    }
+
+    /// Deny-lists a token until `exp`, dropping entries that have expired.
+    pub fn revoke_token(&self, jti: Uuid, exp: i64) {
+        let now = chrono::Utc::now().timestamp();
+        if let Ok(mut tokens) = self.tokens.write() {
+            tokens.retain(|_, token_exp| *token_exp > now);
+            tokens.insert(jti, exp);
+        }
+    }
+
+    pub fn is_token_revoked(&self, jti: &Uuid) -> bool {
+        match self.tokens.read() {
+            Ok(tokens) => tokens.contains_key(jti),
+            Err(e) => {
+                tracing::warn!("Failed to acquire revocation lock: {}", e);
+                false
+            }
+        }
+    }
+
+    pub fn is_user_revoked(&self, user_id: &Uuid) -> bool {
+        let now = chrono::Utc::now().timestamp();
+        match self.users.read() {
+            Ok(users) => users
+                .get(user_id)
+                .is_some_and(|until| until.is_none_or(|t| t > now)),
+            Err(e) => {
+                tracing::warn!("Failed to acquire revocation lock: {}", e);
+                false
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_revoke_and_restore_user() {
+        let cache = RevocationCache::new();
+        let user = Uuid::new_v4();
+
+        assert!(!cache.is_user_revoked(&user));
+        cache.revoke_user(user, None);
+        assert!(cache.is_user_revoked(&user));
+        cache.restore_user(user);
+        assert!(!cache.is_user_revoked(&user));
+    }
+
+    #[test]
+    fn test_update_users_replaces_set() {
+        let cache = RevocationCache::new();
+        let old = Uuid::new_v4();
+        let new = Uuid::new_v4();
+
+        cache.revoke_user(old, None);
+        cache.update_users(vec![(new, None)]);
+
+        assert!(!cache.is_user_revoked(&old));
+        assert!(cache.is_user_revoked(&new));
+    }
+
+    #[test]
+    fn test_deleted_user_revocation_expires() {
+        let cache = RevocationCache::new();
+        let now = chrono::Utc::now().timestamp();
+        let expired = Uuid::new_v4();
+        let deleted = Uuid::new_v4();
+        let deactivated = Uuid::new_v4();
+
+        cache.update_users(vec![(expired, Some(now - 10)), (deactivated, None)]);
+        assert!(!cache.is_user_revoked(&expired));
+
+        cache.revoke_user(deleted, Some(now + 60));
+        assert!(cache.is_user_revoked(&deleted));
+        assert!(cache.is_user_revoked(&deactivated));
+        assert!(!cache.users.read().unwrap().contains_key(&expired));
+    }
+
+    #[test]
+    fn test_revoke_token_prunes_expired() {
+        let cache = RevocationCache::new();
+        let now = chrono::Utc::now().timestamp();
+        let expired = Uuid::new_v4();
+        let live = Uuid::new_v4();
+
+        cache.update_tokens(vec![(expired, now - 10)]);
+        assert!(cache.is_token_revoked(&expired));
+
+        cache.revoke_token(live, now + 60);
+        assert!(cache.is_token_revoked(&live));
+        assert!(!cache.is_token_revoked(&expired));
+    }
+}
@@ -0,0 +1,632 @@
+use std::collections::HashMap;
+use std::iter;
+
+use regex::{Regex, RegexSet};
+use tracing::warn;
+
+use super::config_cache::CachedRoute;
+use super::virtual_host::HostPattern;
+use crate::models::{MATCH_EXACT, MATCH_GLOB, MATCH_PREFIX, MATCH_REGEX};
+
+/// How a route's path pattern is compared with the request path.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MatchType {
+    /// Whole path segments: `/api` matches `/api` and `/api/v1`, not `/apiary`
+    Prefix,
+    /// The whole path, character for character
+    Exact,
+    /// A regular expression that must match the whole path
+    Regex,
+    /// `*` matches within one segment, `**` across segments, `?` one character
+    Glob,
+}
+
+impl MatchType {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            MATCH_PREFIX => Some(Self::Prefix),
+            MATCH_EXACT => Some(Self::Exact),
+            MATCH_REGEX => Some(Self::Regex),
+            MATCH_GLOB => Some(Self::Glob),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Prefix => MATCH_PREFIX,
+            Self::Exact => MATCH_EXACT,
+            Self::Regex => MATCH_REGEX,
+            Self::Glob => MATCH_GLOB,
+        }
+    }
+}
+
+/// A compiled path pattern.
+#[derive(Debug)]
+enum PathPattern {
+    /// Path segments of the prefix
+    Prefix(Vec<String>),
+    Exact(String),
+    /// Regex and glob patterns, anchored at both ends
+    Regex(Regex),
+}
+
+impl PathPattern {
+    fn compile(match_type: MatchType, pattern: &str) -> Result<Self, String> {
+        let regex = |source: String| {
+            Regex::new(&source)
+                .map(Self::Regex)
+                .map_err(|e| e.to_string())
+        };
+        match match_type {
+            MatchType::Prefix => Ok(Self::Prefix(
+                segments(pattern).map(str::to_string).collect(),
+            )),
+            MatchType::Exact => Ok(Self::Exact(pattern.to_string())),
+            MatchType::Regex => regex(format!("^(?:{})$", pattern)),
+            MatchType::Glob => regex(glob_to_regex(pattern)),
+        }
+    }
+
+    /// Captures of a path the pattern matched. `{0}` is the whole path; a
+    /// prefix captures the rest of the path after it as `{1}`.
+    fn captures(&self, path: &str) -> PathCaptures {
+        let mut captures = PathCaptures {
+            positional: vec![path.to_string()],
+            named: HashMap::new(),
+        };
+        match self {
+            Self::Prefix(prefix) => {
+                let mut rest = path.trim_start_matches('/');
+                for _ in prefix {
+                    rest = rest
+                        .split_once('/')
+                        .map_or("", |(_, r)| r)
+                        .trim_start_matches('/');
+                }
+                captures.positional.push(rest.to_string());
+            }
+            Self::Exact(_) => {}
+            Self::Regex(regex) => {
+                if let Some(groups) = regex.captures(path) {
+                    captures.positional = groups
+                        .iter()
+                        .map(|g| g.map_or(String::new(), |g| g.as_str().to_string()))
+                        .collect();
+                    for name in regex.capture_names().flatten() {
+                        let value = groups.name(name).map_or("", |g| g.as_str());
+                        captures.named.insert(name.to_string(), value.to_string());
+                    }
+                }
+            }
+        }
+        captures
+    }
+
+    /// Whether a rewrite may use `{key}`.
+    fn has_capture(&self, key: &str) -> bool {
+        match (self, key.parse::<usize>()) {
+            (_, Ok(0)) => true,
+            (Self::Prefix(_), Ok(1)) => true,
+            (Self::Regex(regex), Ok(index)) => index < regex.captures_len(),
+            (Self::Regex(regex), Err(_)) => regex.capture_names().flatten().any(|n| n == key),
+            _ => false,
+        }
+    }
+
+    /// Orders routes matching the same path at equal priority: exact paths
+    /// first, then regex and glob patterns, then the longest prefix.
+    fn specificity(&self) -> (u8, usize) {
+        match self {
+            Self::Exact(path) => (2, path.len()),
+            Self::Regex(_) => (1, 0),
+            Self::Prefix(prefix) => (0, prefix.len()),
+        }
+    }
+}
+
+fn segments(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/').filter(|s| !s.is_empty())
+}
+
+fn glob_to_regex(glob: &str) -> String {
+    let mut source = String::from("^");
+    let mut chars = glob.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                source.push_str("(.*)");
+            }
+            '*' => source.push_str("([^/]*)"),
+            '?' => source.push_str("([^/])"),
+            c => source.push_str(&regex::escape(c.encode_utf8(&mut [0; 4]))),
+        }
+    }
+    source.push('$');
+    source
+}
+
+/// Values a rewrite template can refer to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PathCaptures {
+    positional: Vec<String>,
+    named: HashMap<String, String>,
+}
+
+impl PathCaptures {
+    fn get(&self, key: &str) -> Option<&str> {
+        match key.parse::<usize>() {
+            Ok(index) => self.positional.get(index).map(String::as_str),
+            Err(_) => self.named.get(key).map(String::as_str),
+        }
+    }
+
+    /// Replaces `{1}` or `{name}` in `template` with the captured values.
+    pub fn expand(&self, template: &str) -> String {
+        let mut path = String::with_capacity(template.len());
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            path.push_str(&rest[..start]);
+            match rest[start..].find('}') {
+                Some(end) => {
+                    let key = &rest[start + 1..start + end];
+                    path.push_str(self.get(key).unwrap_or_default());
+                    rest = &rest[start + end + 1..];
+                }
+                None => {
+                    rest = &rest[start..];
+                    break;
+                }
+            }
+        }
+        path.push_str(rest);
+        if !path.starts_with('/') {
+            path.insert(0, '/');
+        }
+        path
+    }
+}
+
+/// Checks that `pattern` compiles and that `rewrite` only refers to
+/// captures the pattern has.
+pub fn check_pattern(
+    match_type: MatchType,
+    pattern: &str,
+    rewrite: Option<&str>,
+) -> Result<(), String> {
+    if match_type != MatchType::Regex && !pattern.starts_with('/') {
+        return Err("path must start with '/'".into());
+    }
+    let compiled = PathPattern::compile(match_type, pattern)?;
+    let mut rest = rewrite.unwrap_or_default();
+    while let Some(start) = rest.find('{') {
+        let end = rest[start..]
+            .find('}')
+            .ok_or("rewrite has an unclosed '{'")?;
+        let key = &rest[start + 1..start + end];
+        if !compiled.has_capture(key) {
+            return Err(format!("rewrite refers to unknown capture {{{}}}", key));
+        }
+        rest = &rest[start + end + 1..];
+    }
+    Ok(())
+}
+
+/// Uppercases and sorts request methods, rejecting anything that is not a
+/// method name.
+pub fn normalize_methods(methods: &[String]) -> Result<Vec<String>, String> {
+    let mut normalized = Vec::with_capacity(methods.len());
+    for method in methods {
+        let method = method.trim().to_ascii_uppercase();
+        if method.is_empty()
+            || method.len() > 20
+            || !method.chars().all(|c| c.is_ascii_alphabetic())
+        {
+            return Err(format!("Invalid method '{}'", method));
+        }
+        if !normalized.contains(&method) {
+            normalized.push(method);
+        }
+    }
+    normalized.sort();
+    Ok(normalized)
+}
+
+/// Whether a route with `methods` serves `method`. No methods allow every
+/// method, and `GET` allows `HEAD`.
+fn allows_method(methods: &[String], method: &str) -> bool {
+    methods.is_empty()
+        || methods.iter().any(|m| m == method)
+        || (method == "HEAD" && methods.iter().any(|m| m == "GET"))
+}
+
+struct Entry {
+    route: CachedRoute,
+    pattern: PathPattern,
+}
+
+impl Entry {
+    fn rank(&self) -> (i32, (u8, usize)) {
+        (self.route.priority, self.pattern.specificity())
+    }
+}
+
+#[derive(Default)]
+struct PrefixNode {
+    children: HashMap<String, PrefixNode>,
+    routes: Vec<usize>,
+}
+
+/// Routes of one host, or of no host.
+#[derive(Default)]
+struct RouteTable {
+    prefixes: PrefixNode,
+    exact: HashMap<String, Vec<usize>>,
+    /// Regex and glob routes
+    patterns: Vec<usize>,
+    /// The patterns in one automaton; `None` when it would be too large
+    pattern_set: Option<RegexSet>,
+}
+
+/// Result of [`RouteMatcher::find`].
+pub enum RouteMatch<'a> {
+    Found(&'a CachedRoute, PathCaptures),
+    /// Routes match the path but not the method; the methods they allow
+    MethodNotAllowed(Vec<String>),
+    NotFound,
+}
+
+/// Static and dynamic routes compiled for matching: a segment tree of
+/// prefixes, a map of exact paths and a regex set of patterns per host.
+/// Rebuilt whenever the routes change.
+#[derive(Default)]
+pub struct RouteMatcher {
+    /// Static routes first; on equal rank the earlier route wins
+    entries: Vec<Entry>,
+    static_count: usize,
+    any_host: RouteTable,
+    exact_hosts: HashMap<String, RouteTable>,
+    /// Most specific wildcard first
+    wildcard_hosts: Vec<(HostPattern, RouteTable)>,
+}
+
+impl RouteMatcher {
+    /// Compiles the routes. Routes whose pattern does not compile are
+    /// skipped.
+    pub fn new(static_routes: Vec<CachedRoute>, dynamic_routes: Vec<CachedRoute>) -> Self {
+        let mut matcher = Self::default();
+        let static_len = static_routes.len();
+        for (i, route) in static_routes.into_iter().chain(dynamic_routes).enumerate() {
+            match PathPattern::compile(route.match_type, &route.path_prefix) {
+                Ok(pattern) => {
+                    if i < static_len {
+                        matcher.static_count += 1;
+                    }
+                    matcher.entries.push(Entry { route, pattern });
+                }
+                Err(e) => warn!(
+                    path = %route.path_prefix,
+                    match_type = route.match_type.as_str(),
+                    error = %e,
+                    "Invalid route pattern, route skipped"
+                ),
+            }
+        }
+
+        for (id, entry) in matcher.entries.iter().enumerate() {
+            let table = match entry.route.host {
+                None => &mut matcher.any_host,
+                Some(HostPattern::Exact(ref host)) => {
+                    matcher.exact_hosts.entry(host.clone()).or_default()
+                }
+                Some(ref wildcard) => {
+                    match matcher
+                        .wildcard_hosts
+                        .iter()
+                        .position(|(pattern, _)| pattern == wildcard)
+                    {
+                        Some(index) => &mut matcher.wildcard_hosts[index].1,
+                        None => {
+                            matcher
+                                .wildcard_hosts
+                                .push((wildcard.clone(), RouteTable::default()));
+                            &mut matcher.wildcard_hosts.last_mut().expect("just pushed").1
+                        }
+                    }
+                }
+            };
+            match entry.pattern {
+                PathPattern::Prefix(ref prefix) => {
+                    let mut node = &mut table.prefixes;
+                    for segment in prefix {
+                        node = node.children.entry(segment.clone()).or_default();
+                    }
+                    node.routes.push(id);
+                }
+                PathPattern::Exact(ref path) => {
+                    table.exact.entry(path.clone()).or_default().push(id)
+                }
+                PathPattern::Regex(_) => table.patterns.push(id),
+            }
+        }
+        matcher
+            .wildcard_hosts
+            .sort_by_key(|(pattern, _)| std::cmp::Reverse(pattern.specificity()));
+
+        let entries = &matcher.entries;
+        let tables = iter::once(&mut matcher.any_host)
+            .chain(matcher.exact_hosts.values_mut())
+            .chain(matcher.wildcard_hosts.iter_mut().map(|(_, table)| table));
+        for table in tables {
+            if table.patterns.is_empty() {
+                continue;
+            }
+            let sources = table.patterns.iter().map(|&id| match entries[id].pattern {
+                PathPattern::Regex(ref regex) => regex.as_str(),
+                _ => unreachable!("only regex patterns are in the pattern list"),
+            });
+            table.pattern_set = RegexSet::new(sources).ok();
+        }
+        matcher
+    }
+
+    pub fn routes(&self) -> impl Iterator<Item = &CachedRoute> {
+        self.entries.iter().map(|e| &e.route)
+    }
+
+    pub fn dynamic_routes(&self) -> impl Iterator<Item = &CachedRoute> {
+        self.entries[self.static_count..].iter().map(|e| &e.route)
+    }
+
+    /// Finds the route for a request. `host` is the normalized request host:
+    /// routes for that exact host are tried first, then wildcard hosts, then
+    /// routes without a host. Within those, the highest priority wins, then
+    /// the most specific pattern.
+    pub fn find(&self, host: Option<&str>, method: &str, path: &str) -> RouteMatch<'_> {
+        let host_tables = host.into_iter().flat_map(|host| {
+            self.exact_hosts.get(host).into_iter().chain(
+                self.wildcard_hosts
+                    .iter()
+                    .filter(move |(pattern, _)| pattern.matches(host))
+                    .map(|(_, table)| table),
+            )
+        });
+        let mut not_allowed = None;
+        for table in host_tables.chain(iter::once(&self.any_host)) {
+            match self.find_in(table, method, path) {
+                RouteMatch::NotFound => {}
+                RouteMatch::MethodNotAllowed(methods) => {
+                    not_allowed.get_or_insert(methods);
+                }
+                found => return found,
+            }
+        }
+        not_allowed.map_or(RouteMatch::NotFound, RouteMatch::MethodNotAllowed)
+    }
+
+    fn find_in(&self, table: &RouteTable, method: &str, path: &str) -> RouteMatch<'_> {
+        let mut candidates: Vec<usize> = Vec::new();
+        if let Some(ids) = table.exact.get(path) {
+            candidates.extend(ids);
+        }
+        let mut node = &table.prefixes;
+        candidates.extend(&node.routes);
+        for segment in segments(path) {
+            match node.children.get(segment) {
+                Some(child) => {
+                    node = child;
+                    candidates.extend(&node.routes);
+                }
+                None => break,
+            }
+        }
+        match table.pattern_set {
+            Some(ref set) => candidates.extend(set.matches(path).iter().map(|i| table.patterns[i])),
+            None => candidates.extend(table.patterns.iter().filter(|&&id| {
+                matches!(self.entries[id].pattern, PathPattern::Regex(ref regex) if regex.is_match(path))
+            })),
+        }
+
+        let best = candidates
+            .iter()
+            .copied()
+            .filter(|&id| allows_method(&self.entries[id].route.methods, method))
+            .max_by(|&a, &b| {
+                self.entries[a]
+                    .rank()
+                    .cmp(&self.entries[b].rank())
+                    .then(b.cmp(&a))
+            });
+        match best {
+            Some(id) => {
+                let entry = &self.entries[id];
+                RouteMatch::Found(&entry.route, entry.pattern.captures(path))
+            }
+            None if candidates.is_empty() => RouteMatch::NotFound,
+            None => {
+                let mut allowed: Vec<String> = candidates
+                    .iter()
+                    .flat_map(|&id| self.entries[id].route.methods.iter().cloned())
+                    .collect();
+                if allowed.iter().any(|m| m == "GET") {
+                    allowed.push("HEAD".to_string());
+                }
+                allowed.sort();
+                allowed.dedup();
+                RouteMatch::MethodNotAllowed(allowed)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gateway::config_cache::tests::route;
+    use crate::gateway::config_cache::AuthMode;
+
+    fn routed(path: &str, match_type: MatchType, priority: i32, upstream: &str) -> CachedRoute {
+        CachedRoute {
+            match_type,
+            priority,
+            upstream_address: upstream.to_string(),
+            ..route(path, AuthMode::None)
+        }
+    }
+
+    fn upstream(matcher: &RouteMatcher, method: &str, path: &str) -> Option<String> {
+        match matcher.find(None, method, path) {
+            RouteMatch::Found(route, _) => Some(route.upstream_address.clone()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_prefix_matches_whole_segments() {
+        let matcher = RouteMatcher::new(
+            vec![routed("/api", MatchType::Prefix, 0, "api")],
+            vec![routed("/api/v2/", MatchType::Prefix, 0, "v2")],
+        );
+        assert_eq!(upstream(&matcher, "GET", "/api").as_deref(), Some("api"));
+        assert_eq!(
+            upstream(&matcher, "GET", "/api/v1/users").as_deref(),
+            Some("api")
+        );
+        // The longer prefix wins regardless of order
+        assert_eq!(upstream(&matcher, "GET", "/api/v2").as_deref(), Some("v2"));
+        assert_eq!(
+            upstream(&matcher, "GET", "/api/v2/users").as_deref(),
+            Some("v2")
+        );
+        assert_eq!(upstream(&matcher, "GET", "/apiary"), None);
+    }
+
+    #[test]
+    fn test_priority_and_pattern_types() {
+        let matcher = RouteMatcher::new(
+            Vec::new(),
+            vec![
+                routed("/", MatchType::Prefix, 0, "root"),
+                routed("/health", MatchType::Exact, 0, "health"),
+                routed("/files/*.png", MatchType::Glob, 0, "images"),
+                routed(r"/users/(?P<id>\d+)", MatchType::Regex, 0, "users"),
+                routed("/users/me", MatchType::Prefix, 10, "me"),
+            ],
+        );
+        assert_eq!(
+            upstream(&matcher, "GET", "/health").as_deref(),
+            Some("health")
+        );
+        assert_eq!(
+            upstream(&matcher, "GET", "/health/db").as_deref(),
+            Some("root")
+        );
+        assert_eq!(
+            upstream(&matcher, "GET", "/files/a.png").as_deref(),
+            Some("images")
+        );
+        assert_eq!(
+            upstream(&matcher, "GET", "/files/a/b.png").as_deref(),
+            Some("root")
+        );
+        assert_eq!(
+            upstream(&matcher, "GET", "/users/42").as_deref(),
+            Some("users")
+        );
+        assert_eq!(
+            upstream(&matcher, "GET", "/users/42/x").as_deref(),
+            Some("root")
+        );
+        assert_eq!(
+            upstream(&matcher, "GET", "/users/me").as_deref(),
+            Some("me")
+        );
+    }
+
+    #[test]
+    fn test_methods() {
+        let matcher = RouteMatcher::new(
+            Vec::new(),
+            vec![
+                CachedRoute {
+                    methods: vec!["GET".to_string()],
+                    ..routed("/items", MatchType::Prefix, 0, "read")
+                },
+                CachedRoute {
+                    methods: vec!["POST".to_string(), "PUT".to_string()],
+                    ..routed("/items", MatchType::Prefix, 0, "write")
+                },
+            ],
+        );
+        assert_eq!(upstream(&matcher, "GET", "/items").as_deref(), Some("read"));
+        assert_eq!(
+            upstream(&matcher, "HEAD", "/items").as_deref(),
+            Some("read")
+        );
+        assert_eq!(
+            upstream(&matcher, "PUT", "/items/1").as_deref(),
+            Some("write")
+        );
+        match matcher.find(None, "DELETE", "/items/1") {
+            RouteMatch::MethodNotAllowed(allowed) => {
+                assert_eq!(allowed, ["GET", "HEAD", "POST", "PUT"])
+            }
+            _ => panic!("expected 405"),
+        }
+        assert!(matches!(
+            matcher.find(None, "DELETE", "/other"),
+            RouteMatch::NotFound
+        ));
+    }
+
+    #[test]
+    fn test_rewrite_captures() {
+        let matcher = RouteMatcher::new(
+            Vec::new(),
+            vec![
+                routed(r"/v(?P<version>\d+)/(.*)", MatchType::Regex, 0, "regex"),
+                routed("/static/**", MatchType::Glob, 0, "glob"),
+                routed("/old", MatchType::Prefix, 0, "prefix"),
+            ],
+        );
+        let captures = |path: &str| match matcher.find(None, "GET", path) {
+            RouteMatch::Found(_, captures) => captures,
+            _ => panic!("no route for {}", path),
+        };
+        assert_eq!(
+            captures("/v2/users/1").expand("/api/{version}/{2}"),
+            "/api/2/users/1"
+        );
+        assert_eq!(
+            captures("/static/css/a.css").expand("assets/{1}"),
+            "/assets/css/a.css"
+        );
+        assert_eq!(captures("/old/a/b").expand("/new/{1}"), "/new/a/b");
+        assert_eq!(captures("/old").expand("/new/{1}"), "/new/");
+        assert_eq!(captures("/old").expand("{0}{missing"), "/old{missing");
+    }
+
+    #[test]
+    fn test_check_pattern() {
+        assert!(check_pattern(
+            MatchType::Regex,
+            r"/v(\d+)/(?P<rest>.*)",
+            Some("/{1}/{rest}")
+        )
+        .is_ok());
+        assert!(check_pattern(MatchType::Regex, r"/v(\d+)", Some("/{2}")).is_err());
+        assert!(check_pattern(MatchType::Regex, "/(", None).is_err());
+        assert!(check_pattern(MatchType::Prefix, "/old", Some("/new/{1}")).is_ok());
+        assert!(check_pattern(MatchType::Exact, "/old", Some("/new/{1}")).is_err());
+        assert!(check_pattern(MatchType::Glob, "files/*", None).is_err());
+        assert!(check_pattern(MatchType::Glob, "/files/*", Some("/{1")).is_err());
+
+        assert_eq!(
+            normalize_methods(&["get".to_string(), "GET".to_string(), "post".to_string()]),
+            Ok(vec!["GET".to_string(), "POST".to_string()])
+        );
+        assert!(normalize_methods(&["GET /".to_string()]).is_err());
+    }
+}
@@ -0,0 +1,228 @@
+use http::header::{HeaderMap, AUTHORIZATION, COOKIE, SEC_WEBSOCKET_PROTOCOL};
+use http::Uri;
+
+/// Subprotocol marking that the next `Sec-WebSocket-Protocol` entry is an
+/// access token: `new WebSocket(url, ["bearer", accessToken])`. The gateway
+/// answers with this protocol when the upstream selects none.
+pub const WS_BEARER_PROTOCOL: &str = "bearer";
+
+/// Where a route's access token was found.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TokenSource {
+    Header,
+    Subprotocol,
+    Cookie,
+    Query,
+}
+
+/// Places besides the `Authorization` header a route accepts tokens from,
+/// for clients such as `WebSocket` and `EventSource` that cannot set headers.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TokenSources {
+    pub query_param: Option<String>,
+    pub cookie: Option<String>,
+    pub subprotocol: bool,
+}
+
+impl TokenSources {
+    /// Finds the request's access token. The `Authorization` header takes
+    /// precedence, then the subprotocol, the cookie and the query parameter.
+    /// The other sources only apply to `long_lived` (WebSocket and SSE)
+    /// requests: browsers attach cookies to cross-site requests and query
+    /// tokens end up in links, so plain HTTP needs the header.
+    pub fn extract<'a>(
+        &self,
+        headers: &'a HeaderMap,
+        uri: &'a Uri,
+        long_lived: bool,
+    ) -> Option<(&'a str, TokenSource)> {
+        if let Some(token) = bearer_token(headers) {
+            return Some((token, TokenSource::Header));
+        }
+        if !long_lived {
+            return None;
+        }
+        if self.subprotocol {
+            if let Some(token) = subprotocol_token(headers) {
+                return Some((token, TokenSource::Subprotocol));
+            }
+        }
+        if let Some(ref name) = self.cookie {
+            if let Some(token) = cookie_value(headers, name) {
+                return Some((token, TokenSource::Cookie));
+            }
+        }
+        if let Some(ref name) = self.query_param {
+            if let Some(token) = query_value(uri.query()?, name) {
+                return Some((token, TokenSource::Query));
+            }
+        }
+        None
+    }
+}
+
+/// Token of an `Authorization: Bearer` header.
+pub fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|s| s.strip_prefix("Bearer "))
+}
+
+fn subprotocols(headers: &HeaderMap) -> impl Iterator<Item = &str> + '_ {
+    headers
+        .get_all(SEC_WEBSOCKET_PROTOCOL)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(str::trim)
+        .filter(|p| !p.is_empty())
+}
+
+fn subprotocol_token(headers: &HeaderMap) -> Option<&str> {
+    let mut protocols = subprotocols(headers);
+    protocols.find(|p| *p == WS_BEARER_PROTOCOL)?;
+    protocols.next()
+}
+
+pub fn offers_bearer_protocol(headers: &HeaderMap) -> bool {
+    subprotocols(headers).any(|p| p == WS_BEARER_PROTOCOL)
+}
+
+/// `Sec-WebSocket-Protocol` to forward once the bearer marker and token are
+/// removed; `None` when no other protocol was offered.
+pub fn strip_subprotocol_token(headers: &HeaderMap) -> Option<String> {
+    let mut remaining = Vec::new();
+    let mut protocols = subprotocols(headers);
+    while let Some(protocol) = protocols.next() {
+        if protocol == WS_BEARER_PROTOCOL {
+            protocols.next();
+        } else {
+            remaining.push(protocol);
+        }
+    }
+    (!remaining.is_empty()).then(|| remaining.join(", "))
+}
+
+fn cookie_value<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+        .filter(|value| !value.is_empty())
+}
+
+fn query_value<'a>(query: &'a str, name: &str) -> Option<&'a str> {
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+        .filter(|value| !value.is_empty())
+}
+
+/// Query string without parameter `name`, or `None` if nothing is left.
+/// Also keeps query tokens out of logs.
+pub fn strip_query_param(query: &str, name: &str) -> Option<String> {
+    let remaining: Vec<&str> = query
+        .split('&')
+        .filter(|pair| !pair.is_empty() && pair.split('=').next() != Some(name))
+        .collect();
+    (!remaining.is_empty()).then(|| remaining.join("&"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sources() -> TokenSources {
+        TokenSources {
+            query_param: Some("access_token".to_string()),
+            cookie: Some("arc_token".to_string()),
+            subprotocol: true,
+        }
+    }
+
+    #[test]
+    fn test_header_takes_precedence() {
+        let mut headers = HeaderMap::new();
+        headers.insert(AUTHORIZATION, "Bearer from-header".parse().unwrap());
+        headers.insert(COOKIE, "arc_token=from-cookie".parse().unwrap());
+        let uri: Uri = "/ws?access_token=from-query".parse().unwrap();
+
+        assert_eq!(
+            sources().extract(&headers, &uri, true),
+            Some(("from-header", TokenSource::Header))
+        );
+        assert_eq!(
+            sources().extract(&headers, &uri, false),
+            Some(("from-header", TokenSource::Header))
+        );
+    }
+
+    #[test]
+    fn test_fallback_sources() {
+        let uri: Uri = "/events?topic=a&access_token=from-query".parse().unwrap();
+        let mut headers = HeaderMap::new();
+        assert_eq!(
+            sources().extract(&headers, &uri, true),
+            Some(("from-query", TokenSource::Query))
+        );
+
+        headers.insert(COOKIE, "theme=dark; arc_token=from-cookie".parse().unwrap());
+        assert_eq!(
+            sources().extract(&headers, &uri, true),
+            Some(("from-cookie", TokenSource::Cookie))
+        );
+
+        headers.insert(
+            SEC_WEBSOCKET_PROTOCOL,
+            "chat, bearer, eyJ.a.b".parse().unwrap(),
+        );
+        assert_eq!(
+            sources().extract(&headers, &uri, true),
+            Some(("eyJ.a.b", TokenSource::Subprotocol))
+        );
+
+        // Sources the route does not enable are ignored
+        assert_eq!(TokenSources::default().extract(&headers, &uri, true), None);
+    }
+
+    #[test]
+    fn test_plain_http_needs_header() {
+        let uri: Uri = "/orders?access_token=from-query".parse().unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert(COOKIE, "arc_token=from-cookie".parse().unwrap());
+        assert_eq!(sources().extract(&headers, &uri, false), None);
+    }
+
+    #[test]
+    fn test_strip_subprotocol_token() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            SEC_WEBSOCKET_PROTOCOL,
+            "chat, bearer, eyJ.a.b".parse().unwrap(),
+        );
+        assert_eq!(strip_subprotocol_token(&headers), Some("chat".to_string()));
+
+        headers.insert(SEC_WEBSOCKET_PROTOCOL, "bearer, eyJ.a.b".parse().unwrap());
+        assert_eq!(strip_subprotocol_token(&headers), None);
+    }
+
+    #[test]
+    fn test_strip_query_param() {
+        assert_eq!(
+            strip_query_param("a=1&access_token=t&b=2", "access_token"),
+            Some("a=1&b=2".to_string())
+        );
+        assert_eq!(strip_query_param("access_token=t", "access_token"), None);
+        assert_eq!(
+            strip_query_param("access_token_x=1", "access_token"),
+            Some("access_token_x=1".to_string())
+        );
+    }
+}
@@ -0,0 +1,100 @@
+use std::time::Duration;
+
+use http::Method;
+
+/// How the gateway connects to a route's upstream. Unset timeouts keep the
+/// pingora defaults.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UpstreamOptions {
+    pub connect_timeout: Option<Duration>,
+    pub read_timeout: Option<Duration>,
+    pub write_timeout: Option<Duration>,
+    /// How long an idle connection stays in the keepalive pool
+    pub idle_timeout: Option<Duration>,
+    /// Retries after a failed connect, for idempotent methods only
+    pub max_retries: usize,
+    pub keepalive: bool,
+}
+
+impl Default for UpstreamOptions {
+    fn default() -> Self {
+        Self {
+            connect_timeout: None,
+            read_timeout: None,
+            write_timeout: None,
+            idle_timeout: None,
+            max_retries: 0,
+            keepalive: true,
+        }
+    }
+}
+
+impl UpstreamOptions {
+    /// Builds options from millisecond values; non-positive values are unset.
+    pub fn from_millis(
+        connect: Option<i64>,
+        read: Option<i64>,
+        write: Option<i64>,
+        idle: Option<i64>,
+        max_retries: i64,
+        keepalive: bool,
+    ) -> Self {
+        let duration = |ms: Option<i64>| {
+            ms.filter(|ms| *ms > 0)
+                .map(|ms| Duration::from_millis(ms as u64))
+        };
+        Self {
+            connect_timeout: duration(connect),
+            read_timeout: duration(read),
+            write_timeout: duration(write),
+            idle_timeout: duration(idle),
+            max_retries: max_retries.max(0) as usize,
+            keepalive,
+        }
+    }
+
+    /// Whether a request that already failed to connect `retries` times may
+    /// be sent again. The request never reached the upstream, but only
+    /// idempotent methods are retried in case it did.
+    pub fn allows_retry(&self, method: &Method, retries: usize) -> bool {
+        retries < self.max_retries && is_idempotent(method)
+    }
+}
+
+fn is_idempotent(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE | Method::PUT | Method::DELETE
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_millis() {
+        let options = UpstreamOptions::from_millis(Some(500), None, Some(0), Some(-1), -2, false);
+        assert_eq!(options.connect_timeout, Some(Duration::from_millis(500)));
+        assert_eq!(options.read_timeout, None);
+        assert_eq!(options.write_timeout, None);
+        assert_eq!(options.idle_timeout, None);
+        assert_eq!(options.max_retries, 0);
+        assert!(!options.keepalive);
+    }
+
+    #[test]
+    fn test_allows_retry() {
+        let options = UpstreamOptions {
+            max_retries: 2,
+            ..UpstreamOptions::default()
+        };
+        assert!(options.allows_retry(&Method::GET, 0));
+        assert!(options.allows_retry(&Method::PUT, 1));
+        assert!(!options.allows_retry(&Method::GET, 2));
+        assert!(!options.allows_retry(&Method::POST, 0));
+        assert!(!options.allows_retry(&Method::PATCH, 0));
+
+        assert!(!UpstreamOptions::default().allows_retry(&Method::GET, 0));
+    }
+}
@@ -0,0 +1,384 @@
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::Serialize;
+use tracing::warn;
+
+use crate::models::{
+    UpstreamPool, UpstreamTargetInput, LB_CONSISTENT_HASH, LB_LEAST_CONNECTIONS, LB_ROUND_ROBIN,
+};
+
+/// Consecutive failed health checks before a target stops receiving traffic.
+const UNHEALTHY_THRESHOLD: u32 = 2;
+
+/// How a pool picks a target for each request.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LoadBalance {
+    RoundRobin,
+    LeastConnections,
+    /// Sticks each user to one target; anonymous requests use round-robin
+    ConsistentHash,
+}
+
+impl LoadBalance {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            LB_ROUND_ROBIN => Some(Self::RoundRobin),
+            LB_LEAST_CONNECTIONS => Some(Self::LeastConnections),
+            LB_CONSISTENT_HASH => Some(Self::ConsistentHash),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::RoundRobin => LB_ROUND_ROBIN,
+            Self::LeastConnections => LB_LEAST_CONNECTIONS,
+            Self::ConsistentHash => LB_CONSISTENT_HASH,
+        }
+    }
+}
+
+/// Health and load of one target, kept across pool reloads.
+#[derive(Debug)]
+pub struct UpstreamTarget {
+    pub address: String,
+    healthy: AtomicBool,
+    failures: AtomicU32,
+    active: AtomicUsize,
+}
+
+impl UpstreamTarget {
+    fn new(address: String) -> Self {
+        Self {
+            address,
+            healthy: AtomicBool::new(true),
+            failures: AtomicU32::new(0),
+            active: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        self.healthy.load(Ordering::Relaxed)
+    }
+
+    /// Records a health check result. Returns true when the target changed
+    /// between healthy and unhealthy.
+    pub fn record_check(&self, ok: bool) -> bool {
+        let healthy = if ok {
+            self.failures.store(0, Ordering::Relaxed);
+            true
+        } else {
+            self.failures.fetch_add(1, Ordering::Relaxed) + 1 < UNHEALTHY_THRESHOLD
+        };
+        self.healthy.swap(healthy, Ordering::Relaxed) != healthy
+    }
+}
+
+/// Counts a request against its target until dropped with the request context.
+pub struct TargetGuard {
+    target: Arc<UpstreamTarget>,
+}
+
+impl TargetGuard {
+    pub fn address(&self) -> &str {
+        &self.target.address
+    }
+}
+
+impl Drop for TargetGuard {
+    fn drop(&mut self) {
+        self.target.active.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct TargetStatus {
+    pub address: String,
+    pub weight: u32,
+    pub healthy: bool,
+    pub active_requests: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PoolStatus {
+    pub name: String,
+    pub strategy: &'static str,
+    pub health_check_path: Option<String>,
+    pub targets: Vec<TargetStatus>,
+}
+
+#[derive(Debug)]
+pub struct CachedUpstreamPool {
+    pub name: String,
+    pub strategy: LoadBalance,
+    pub health_check_path: Option<String>,
+    pub health_check_interval: Duration,
+    targets: Vec<(u32, Arc<UpstreamTarget>)>,
+    next: AtomicUsize,
+}
+
+impl CachedUpstreamPool {
+    /// Converts a database pool. Unknown strategies fall back to round-robin
+    /// and targets without a positive weight are skipped.
+    pub fn from_pool(pool: UpstreamPool) -> Self {
+        let strategy = LoadBalance::parse(&pool.strategy).unwrap_or_else(|| {
+            warn!(
+                pool = %pool.name,
+                strategy = %pool.strategy,
+                "Unknown load balancing strategy, using round_robin"
+            );
+            LoadBalance::RoundRobin
+        });
+        let targets: Vec<UpstreamTargetInput> = serde_json::from_value(pool.targets)
+            .unwrap_or_else(|e| {
+                warn!(pool = %pool.name, error = %e, "Invalid upstream pool targets");
+                Vec::new()
+            });
+        Self {
+            name: pool.name,
+            strategy,
+            health_check_path: pool.health_check_path,
+            health_check_interval: Duration::from_secs(
+                pool.health_check_interval_secs.max(1) as u64
+            ),
+            targets: targets
+                .into_iter()
+                .filter(|t| t.weight > 0)
+                .map(|t| (t.weight as u32, Arc::new(UpstreamTarget::new(t.address))))
+                .collect(),
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    /// Keeps the health and load of targets that were already in the pool.
+    /// Targets are healthy again once health checks are turned off.
+    pub fn inherit_state(&mut self, previous: &CachedUpstreamPool) {
+        for (_, target) in self.targets.iter_mut() {
+            if let Some(existing) = previous.target(&target.address) {
+                if self.health_check_path.is_none() {
+                    existing.record_check(true);
+                }
+                *target = existing.clone();
+            }
+        }
+    }
+
+    fn target(&self, address: &str) -> Option<&Arc<UpstreamTarget>> {
+        self.targets
+            .iter()
+            .map(|(_, t)| t)
+            .find(|t| t.address == address)
+    }
+
+    pub fn targets(&self) -> impl Iterator<Item = &Arc<UpstreamTarget>> {
+        self.targets.iter().map(|(_, t)| t)
+    }
+
+    /// Picks a healthy target, or `None` if every target is down.
+    /// `hash_key` is the user id for consistent hashing; targets for which
+    /// `excluded` returns true (open circuits) are skipped like unhealthy ones.
+    pub fn select(
+        &self,
+        hash_key: Option<&str>,
+        excluded: impl Fn(&str) -> bool,
+    ) -> Option<TargetGuard> {
+        let healthy: Vec<&(u32, Arc<UpstreamTarget>)> = self
+            .targets
+            .iter()
+            .filter(|(_, t)| t.is_healthy() && !excluded(&t.address))
+            .collect();
+        if healthy.is_empty() {
+            return None;
+        }
+
+        let chosen = match (self.strategy, hash_key) {
+            (LoadBalance::LeastConnections, _) => healthy
+                .iter()
+                .min_by(|(wa, a), (wb, b)| {
+                    // Compare active/weight without dividing
+                    let load_a = a.active.load(Ordering::Relaxed) as u64 * *wb as u64;
+                    let load_b = b.active.load(Ordering::Relaxed) as u64 * *wa as u64;
+                    load_a.cmp(&load_b)
+                })
+                .map(|(_, t)| t),
+            (LoadBalance::ConsistentHash, Some(key)) => healthy
+                .iter()
+                .max_by(|(wa, a), (wb, b)| {
+                    rendezvous_score(key, &a.address, *wa)
+                        .total_cmp(&rendezvous_score(key, &b.address, *wb))
+                })
+                .map(|(_, t)| t),
+            _ => {
+                let total: u64 = healthy.iter().map(|(w, _)| *w as u64).sum();
+                let mut n = self.next.fetch_add(1, Ordering::Relaxed) as u64 % total;
+                healthy
+                    .iter()
+                    .find(|(w, _)| {
+                        if n < *w as u64 {
+                            true
+                        } else {
+                            n -= *w as u64;
+                            false
+                        }
+                    })
+                    .map(|(_, t)| t)
+            }
+        }?;
+
+        chosen.active.fetch_add(1, Ordering::Relaxed);
+        Some(TargetGuard {
+            target: chosen.clone(),
+        })
+    }
+
+    pub fn status(&self) -> PoolStatus {
+        PoolStatus {
+            name: self.name.clone(),
+            strategy: self.strategy.as_str(),
+            health_check_path: self.health_check_path.clone(),
+            targets: self
+                .targets
+                .iter()
+                .map(|(weight, t)| TargetStatus {
+                    address: t.address.clone(),
+                    weight: *weight,
+                    healthy: t.is_healthy(),
+                    active_requests: t.active.load(Ordering::Relaxed),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Weighted rendezvous hashing: removing a target only moves the keys that
+/// were on it.
+fn rendezvous_score(key: &str, address: &str, weight: u32) -> f64 {
+    let hash = fnv1a(&[key.as_bytes(), b"\0", address.as_bytes()]);
+    // Uniform in (0, 1)
+    let u = ((hash >> 11) as f64 + 0.5) / (1u64 << 53) as f64;
+    weight as f64 / -u.ln()
+}
+
+/// FNV-1a, stable across builds unlike `DefaultHasher`.
+fn fnv1a(parts: &[&[u8]]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in parts.iter().flat_map(|p| p.iter()) {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    fn pool(strategy: &str, targets: serde_json::Value) -> CachedUpstreamPool {
+        CachedUpstreamPool::from_pool(UpstreamPool {
+            id: Uuid::new_v4(),
+            name: "models".to_string(),
+            strategy: strategy.to_string(),
+            targets,
+            health_check_path: Some("/health".to_string()),
+            health_check_interval_secs: 10,
+            created_at: chrono::Utc::now(),
+            updated_at: chrono::Utc::now(),
+        })
+    }
+
+    fn pick(pool: &CachedUpstreamPool, key: Option<&str>) -> String {
+        pool.select(key, |_| false).unwrap().address().to_string()
+    }
+
+    #[test]
+    fn test_weighted_round_robin() {
+        let pool = pool(
+            "round_robin",
+            serde_json::json!([
+                {"address": "a:80", "weight": 2},
+                {"address": "b:80"},
+            ]),
+        );
+        let picks: Vec<String> = (0..6).map(|_| pick(&pool, None)).collect();
+        assert_eq!(picks.iter().filter(|a| *a == "a:80").count(), 4);
+        assert_eq!(picks.iter().filter(|a| *a == "b:80").count(), 2);
+    }
+
+    #[test]
+    fn test_least_connections() {
+        let pool = pool(
+            "least_connections",
+            serde_json::json!([{"address": "a:80"}, {"address": "b:80"}]),
+        );
+        let first = pool.select(None, |_| false).unwrap();
+        let second = pool.select(None, |_| false).unwrap();
+        assert_ne!(first.address(), second.address());
+
+        let busy = first.address().to_string();
+        drop(second);
+        assert_ne!(pick(&pool, None), busy);
+    }
+
+    #[test]
+    fn test_consistent_hash_sticks_and_skips_unhealthy() {
+        let pool = pool(
+            "consistent_hash",
+            serde_json::json!([{"address": "a:80"}, {"address": "b:80"}, {"address": "c:80"}]),
+        );
+        let target = pick(&pool, Some("user-1"));
+        for _ in 0..5 {
+            assert_eq!(pick(&pool, Some("user-1")), target);
+        }
+
+        let down = pool.target(&target).unwrap();
+        down.record_check(false);
+        assert!(down.is_healthy());
+        assert!(down.record_check(false));
+        assert_ne!(pick(&pool, Some("user-1")), target);
+
+        assert!(down.record_check(true));
+        assert_eq!(pick(&pool, Some("user-1")), target);
+    }
+
+    #[test]
+    fn test_no_healthy_target() {
+        let pool = pool("round_robin", serde_json::json!([{"address": "a:80"}]));
+        let target = pool.target("a:80").unwrap();
+        target.record_check(false);
+        target.record_check(false);
+        assert!(pool.select(None, |_| false).is_none());
+    }
+
+    #[test]
+    fn test_select_skips_excluded() {
+        let pool = pool(
+            "consistent_hash",
+            serde_json::json!([{"address": "a:80"}, {"address": "b:80"}]),
+        );
+        let target = pick(&pool, Some("user-1"));
+        let other = pool
+            .select(Some("user-1"), |address| address == target)
+            .unwrap();
+        assert_ne!(other.address(), target);
+        assert!(pool.select(None, |_| true).is_none());
+    }
+
+    #[test]
+    fn test_inherit_state() {
+        let old = pool("round_robin", serde_json::json!([{"address": "a:80"}]));
+        let target = old.target("a:80").unwrap();
+        target.record_check(false);
+        target.record_check(false);
+
+        let mut new = pool(
+            "round_robin",
+            serde_json::json!([{"address": "a:80"}, {"address": "b:80"}]),
+        );
+        new.inherit_state(&old);
+        assert_eq!(pick(&new, None), "b:80");
+        assert_eq!(pick(&new, None), "b:80");
+    }
+}
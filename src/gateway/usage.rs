@@ -0,0 +1,412 @@
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+
+use chrono::{DateTime, Datelike, Duration as ChronoDuration, NaiveDate, TimeZone, Timelike, Utc};
+use serde::Serialize;
+
+use super::connections::ConnectionSubject;
+
+/// Requests a user or API key may send to a route per UTC day and month.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Quota {
+    pub daily: Option<u64>,
+    pub monthly: Option<u64>,
+}
+
+impl Quota {
+    pub fn is_set(&self) -> bool {
+        self.daily.is_some() || self.monthly.is_some()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QuotaPeriod {
+    Daily,
+    Monthly,
+}
+
+/// Why a request was refused, and when the quota resets.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QuotaExceeded {
+    pub period: QuotaPeriod,
+    pub retry_after: Duration,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct UsageKey {
+    pub subject: ConnectionSubject,
+    pub route: String,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct UsageCounts {
+    pub requests: u64,
+    pub response_bytes: u64,
+    pub duration_ms: u64,
+}
+
+/// Usage of one subject on one route within an hour, waiting to be written.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UsageRecord {
+    pub key: UsageKey,
+    pub bucket_start: DateTime<Utc>,
+    pub counts: UsageCounts,
+}
+
+/// Requests of one subject on one route in the current day and month.
+#[derive(Debug, Clone, Copy)]
+struct PeriodTotals {
+    day: NaiveDate,
+    day_requests: u64,
+    month: NaiveDate,
+    month_requests: u64,
+}
+
+impl PeriodTotals {
+    fn new(now: DateTime<Utc>) -> Self {
+        Self {
+            day: now.date_naive(),
+            day_requests: 0,
+            month: month_start(now),
+            month_requests: 0,
+        }
+    }
+
+    /// Resets counts of a day or month that has ended.
+    fn roll(&mut self, now: DateTime<Utc>) {
+        if self.day != now.date_naive() {
+            self.day = now.date_naive();
+            self.day_requests = 0;
+        }
+        if self.month != month_start(now) {
+            self.month = month_start(now);
+            self.month_requests = 0;
+        }
+    }
+}
+
+/// Per-route usage of one subject, for `/auth/usage` and the admin report.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct QuotaUsage {
+    pub route: String,
+    pub requests_today: u64,
+    pub requests_this_month: u64,
+}
+
+/// Counts proxied requests per user or API key and route. Counts are kept
+/// in memory for quota checks and written to the database in hourly buckets
+/// by a background task.
+#[derive(Default)]
+pub struct UsageMeter {
+    pending: Mutex<HashMap<(UsageKey, DateTime<Utc>), UsageCounts>>,
+    totals: Mutex<HashMap<UsageKey, PeriodTotals>>,
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    match mutex.lock() {
+        Ok(guard) => guard,
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
+
+impl UsageMeter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Counts a request unless it would exceed `quota`.
+    pub fn admit(
+        &self,
+        key: &UsageKey,
+        quota: &Quota,
+        now: DateTime<Utc>,
+    ) -> Result<(), QuotaExceeded> {
+        {
+            let mut totals = lock(&self.totals);
+            let period = totals
+                .entry(key.clone())
+                .or_insert_with(|| PeriodTotals::new(now));
+            period.roll(now);
+            if quota
+                .daily
+                .is_some_and(|limit| period.day_requests >= limit)
+            {
+                return Err(QuotaExceeded {
+                    period: QuotaPeriod::Daily,
+                    retry_after: until(now, next_day(now)),
+                });
+            }
+            if quota
+                .monthly
+                .is_some_and(|limit| period.month_requests >= limit)
+            {
+                return Err(QuotaExceeded {
+                    period: QuotaPeriod::Monthly,
+                    retry_after: until(now, next_month(now)),
+                });
+            }
+            period.day_requests += 1;
+            period.month_requests += 1;
+        }
+        lock(&self.pending)
+            .entry((key.clone(), hour_start(now)))
+            .or_default()
+            .requests += 1;
+        Ok(())
+    }
+
+    /// Takes back a request counted by `admit` that was refused before it
+    /// reached an upstream.
+    pub fn refund(&self, key: &UsageKey, now: DateTime<Utc>) {
+        if let Some(period) = lock(&self.totals).get_mut(key) {
+            period.roll(now);
+            period.day_requests = period.day_requests.saturating_sub(1);
+            period.month_requests = period.month_requests.saturating_sub(1);
+        }
+        let mut pending = lock(&self.pending);
+        if let Some(counts) = pending.get_mut(&(key.clone(), hour_start(now))) {
+            counts.requests = counts.requests.saturating_sub(1);
+        }
+    }
+
+    /// Adds the size and duration of a finished response.
+    pub fn record_response(
+        &self,
+        key: &UsageKey,
+        response_bytes: u64,
+        duration: Duration,
+        now: DateTime<Utc>,
+    ) {
+        let mut pending = lock(&self.pending);
+        let counts = pending.entry((key.clone(), hour_start(now))).or_default();
+        counts.response_bytes += response_bytes;
+        counts.duration_ms += duration.as_millis() as u64;
+    }
+
+    /// Removes and returns everything counted since the last call.
+    pub fn take_pending(&self) -> Vec<UsageRecord> {
+        lock(&self.pending)
+            .drain()
+            .map(|((key, bucket_start), counts)| UsageRecord {
+                key,
+                bucket_start,
+                counts,
+            })
+            .collect()
+    }
+
+    /// Puts back records that could not be written.
+    pub fn restore(&self, records: Vec<UsageRecord>) {
+        let mut pending = lock(&self.pending);
+        for record in records {
+            let counts = pending
+                .entry((record.key, record.bucket_start))
+                .or_default();
+            counts.requests += record.counts.requests;
+            counts.response_bytes += record.counts.response_bytes;
+            counts.duration_ms += record.counts.duration_ms;
+        }
+    }
+
+    /// Replaces the in-memory totals with the database totals for the current
+    /// day and month, which include other gateway instances, plus requests
+    /// counted here but not yet written.
+    pub fn sync_totals(&self, stored: Vec<(UsageKey, u64, u64)>, now: DateTime<Utc>) {
+        let pending = lock(&self.pending);
+        let mut totals: HashMap<UsageKey, PeriodTotals> = stored
+            .into_iter()
+            .map(|(key, day_requests, month_requests)| {
+                let mut period = PeriodTotals::new(now);
+                period.day_requests = day_requests;
+                period.month_requests = month_requests;
+                (key, period)
+            })
+            .collect();
+        for ((key, bucket_start), counts) in pending.iter() {
+            let period = totals
+                .entry(key.clone())
+                .or_insert_with(|| PeriodTotals::new(now));
+            if bucket_start.date_naive() == period.day {
+                period.day_requests += counts.requests;
+            }
+            if month_start(*bucket_start) == period.month {
+                period.month_requests += counts.requests;
+            }
+        }
+        *lock(&self.totals) = totals;
+    }
+
+    /// Requests of `subject` today and this month, per route.
+    pub fn usage(&self, subject: &ConnectionSubject, now: DateTime<Utc>) -> Vec<QuotaUsage> {
+        let totals = lock(&self.totals);
+        let mut usage: Vec<QuotaUsage> = totals
+            .iter()
+            .filter(|(key, _)| &key.subject == subject)
+            .map(|(key, period)| {
+                let mut period = *period;
+                period.roll(now);
+                QuotaUsage {
+                    route: key.route.clone(),
+                    requests_today: period.day_requests,
+                    requests_this_month: period.month_requests,
+                }
+            })
+            .filter(|u| u.requests_this_month > 0)
+            .collect();
+        usage.sort_by(|a, b| a.route.cmp(&b.route));
+        usage
+    }
+}
+
+pub fn hour_start(now: DateTime<Utc>) -> DateTime<Utc> {
+    now.with_minute(0)
+        .and_then(|t| t.with_second(0))
+        .and_then(|t| t.with_nanosecond(0))
+        .unwrap_or(now)
+}
+
+pub fn day_start(now: DateTime<Utc>) -> DateTime<Utc> {
+    Utc.from_utc_datetime(&now.date_naive().and_hms_opt(0, 0, 0).unwrap_or_default())
+}
+
+fn month_start(now: DateTime<Utc>) -> NaiveDate {
+    now.date_naive().with_day(1).unwrap_or(now.date_naive())
+}
+
+pub fn month_start_time(now: DateTime<Utc>) -> DateTime<Utc> {
+    Utc.from_utc_datetime(&month_start(now).and_hms_opt(0, 0, 0).unwrap_or_default())
+}
+
+fn next_day(now: DateTime<Utc>) -> DateTime<Utc> {
+    day_start(now) + ChronoDuration::days(1)
+}
+
+fn next_month(now: DateTime<Utc>) -> DateTime<Utc> {
+    let start = month_start(now);
+    let next = if start.month() == 12 {
+        NaiveDate::from_ymd_opt(start.year() + 1, 1, 1)
+    } else {
+        NaiveDate::from_ymd_opt(start.year(), start.month() + 1, 1)
+    };
+    next.and_then(|d| d.and_hms_opt(0, 0, 0))
+        .map(|t| Utc.from_utc_datetime(&t))
+        .unwrap_or(now)
+}
+
+fn until(now: DateTime<Utc>, then: DateTime<Utc>) -> Duration {
+    (then - now).to_std().unwrap_or(Duration::ZERO)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(user: &str) -> UsageKey {
+        UsageKey {
+            subject: ConnectionSubject::User(user.to_string()),
+            route: "/models".to_string(),
+        }
+    }
+
+    fn at(value: &str) -> DateTime<Utc> {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn test_daily_quota_resets_at_midnight() {
+        let meter = UsageMeter::new();
+        let quota = Quota {
+            daily: Some(2),
+            monthly: None,
+        };
+        let now = at("2026-03-10T23:30:00Z");
+        assert!(meter.admit(&key("a"), &quota, now).is_ok());
+        assert!(meter.admit(&key("a"), &quota, now).is_ok());
+        assert_eq!(
+            meter.admit(&key("a"), &quota, now),
+            Err(QuotaExceeded {
+                period: QuotaPeriod::Daily,
+                retry_after: Duration::from_secs(30 * 60),
+            })
+        );
+        // Other users have their own count
+        assert!(meter.admit(&key("b"), &quota, now).is_ok());
+
+        assert!(meter
+            .admit(&key("a"), &quota, at("2026-03-11T00:00:01Z"))
+            .is_ok());
+    }
+
+    #[test]
+    fn test_monthly_quota() {
+        let meter = UsageMeter::new();
+        let quota = Quota {
+            daily: None,
+            monthly: Some(1),
+        };
+        assert!(meter
+            .admit(&key("a"), &quota, at("2026-12-01T10:00:00Z"))
+            .is_ok());
+        let refused = meter
+            .admit(&key("a"), &quota, at("2026-12-31T00:00:00Z"))
+            .unwrap_err();
+        assert_eq!(refused.period, QuotaPeriod::Monthly);
+        assert_eq!(refused.retry_after, Duration::from_secs(24 * 3600));
+    }
+
+    #[test]
+    fn test_pending_buckets_and_sync() {
+        let meter = UsageMeter::new();
+        let now = at("2026-03-10T12:15:00Z");
+        let quota = Quota::default();
+        meter.admit(&key("a"), &quota, now).unwrap();
+        meter.record_response(&key("a"), 512, Duration::from_millis(40), now);
+
+        let records = meter.take_pending();
+        assert_eq!(
+            records,
+            vec![UsageRecord {
+                key: key("a"),
+                bucket_start: at("2026-03-10T12:00:00Z"),
+                counts: UsageCounts {
+                    requests: 1,
+                    response_bytes: 512,
+                    duration_ms: 40,
+                },
+            }]
+        );
+        assert!(meter.take_pending().is_empty());
+
+        // Stored totals include other instances; unwritten requests are kept
+        meter.admit(&key("a"), &quota, now).unwrap();
+        meter.sync_totals(vec![(key("a"), 5, 20)], now);
+        assert_eq!(
+            meter.usage(&key("a").subject, now),
+            vec![QuotaUsage {
+                route: "/models".to_string(),
+                requests_today: 6,
+                requests_this_month: 21,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_refund_frees_quota() {
+        let meter = UsageMeter::new();
+        let quota = Quota {
+            daily: Some(1),
+            monthly: None,
+        };
+        let now = at("2026-03-10T12:15:00Z");
+        meter.admit(&key("a"), &quota, now).unwrap();
+        meter.refund(&key("a"), now);
+        assert!(meter.admit(&key("a"), &quota, now).is_ok());
+        assert!(meter.admit(&key("a"), &quota, now).is_err());
+
+        let records = meter.take_pending();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].counts.requests, 1);
+    }
+}
@@ -0,0 +1,129 @@
+use std::fmt;
+
+/// Host name a route is limited to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HostPattern {
+    /// `api.example.com`
+    Exact(String),
+    /// `*.example.com`, stored as `.example.com`; matches every subdomain
+    /// but not `example.com` itself
+    Wildcard(String),
+}
+
+impl HostPattern {
+    /// Parses `api.example.com` or `*.example.com`, ignoring case and a
+    /// trailing dot. Returns `None` for anything else, including ports.
+    pub fn parse(value: &str) -> Option<Self> {
+        let value = value.trim().trim_end_matches('.').to_ascii_lowercase();
+        match value.strip_prefix("*.") {
+            Some(domain) if is_valid_host_name(domain) => {
+                Some(Self::Wildcard(format!(".{}", domain)))
+            }
+            Some(_) => None,
+            None if is_valid_host_name(&value) => Some(Self::Exact(value)),
+            None => None,
+        }
+    }
+
+    /// Whether the normalized request host `host` matches.
+    pub fn matches(&self, host: &str) -> bool {
+        match self {
+            Self::Exact(name) => host == name,
+            Self::Wildcard(suffix) => host.len() > suffix.len() && host.ends_with(suffix.as_str()),
+        }
+    }
+
+    /// Higher for patterns that match fewer hosts: exact names beat
+    /// wildcards, and `*.api.example.com` beats `*.example.com`.
+    pub fn specificity(&self) -> usize {
+        match self {
+            Self::Exact(_) => usize::MAX,
+            Self::Wildcard(suffix) => suffix.len(),
+        }
+    }
+}
+
+impl fmt::Display for HostPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Exact(name) => f.write_str(name),
+            Self::Wildcard(suffix) => write!(f, "*{}", suffix),
+        }
+    }
+}
+
+fn is_valid_host_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 253
+        && name.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+}
+
+/// Host of a `Host` header or `:authority`, lowercased and without port or
+/// trailing dot.
+pub fn normalize_host(value: &str) -> Option<String> {
+    let value = value.trim();
+    let host = if value.starts_with('[') {
+        // IPv6 literal
+        &value[..=value.find(']')?]
+    } else {
+        match value.rsplit_once(':') {
+            Some((host, port)) if port.chars().all(|c| c.is_ascii_digit()) => host,
+            _ => value,
+        }
+    };
+    let host = host.trim_end_matches('.').to_ascii_lowercase();
+    (!host.is_empty()).then_some(host)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_match() {
+        let exact = HostPattern::parse("API.example.com.").unwrap();
+        assert_eq!(exact, HostPattern::Exact("api.example.com".to_string()));
+        assert!(exact.matches("api.example.com"));
+        assert!(!exact.matches("www.api.example.com"));
+
+        let wildcard = HostPattern::parse("*.example.com").unwrap();
+        assert_eq!(wildcard.to_string(), "*.example.com");
+        assert!(wildcard.matches("api.example.com"));
+        assert!(wildcard.matches("a.b.example.com"));
+        assert!(!wildcard.matches("example.com"));
+        assert!(!wildcard.matches("badexample.com"));
+        assert!(exact.specificity() > wildcard.specificity());
+
+        for invalid in [
+            "",
+            "*",
+            "*.",
+            "api.*.com",
+            "example.com:8080",
+            "-api.example.com",
+            "a b",
+        ] {
+            assert_eq!(HostPattern::parse(invalid), None, "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_normalize_host() {
+        assert_eq!(
+            normalize_host("API.Example.com:8443").as_deref(),
+            Some("api.example.com")
+        );
+        assert_eq!(
+            normalize_host("example.com.").as_deref(),
+            Some("example.com")
+        );
+        assert_eq!(normalize_host("[::1]:8080").as_deref(), Some("[::1]"));
+        assert_eq!(normalize_host(""), None);
+    }
+}
@@ -0,0 +1,269 @@
+//! Signed identity assertions sent by the gateway in `X-Arc-Identity`.
+//!
+//! The gateway signs a short-lived HS256 JWT for every request it proxies to
+//! an upstream that has an identity key (see `/api/config/identity-keys`).
+//! The assertion binds the user id to the request id, method, path and query
+//! the upstream receives, so a request that bypasses the gateway, or replays
+//! an assertion for another request, fails verification:
+//!
+//! ```ignore
+//! use arc_auth::identity_assertion::IdentityVerifier;
+//!
+//! let verifier = IdentityVerifier::new().with_key(&kid, secret.as_bytes());
+//! let identity = verifier.verify_headers(req.headers(), req.method().as_str(), req.uri())?;
+//! if let Some(user_id) = identity.sub { /* authenticated user */ }
+//! ```
+
+use std::collections::HashMap;
+
+use http::header::HeaderMap;
+use http::Uri;
+use jsonwebtoken::{
+    decode, decode_header, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation,
+};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+pub const IDENTITY_ASSERTION_HEADER: &str = "x-arc-identity";
+
+/// How long an assertion is valid after the gateway signs it.
+pub const ASSERTION_TTL_SECS: i64 = 60;
+
+/// Clock skew tolerated between the gateway and upstreams.
+const DEFAULT_LEEWAY_SECS: u64 = 5;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IdentityAssertion {
+    /// Authenticated user; absent on routes that do not require auth
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sub: Option<Uuid>,
+    /// Request id, also sent as `X-Request-Id`
+    pub rid: String,
+    pub method: String,
+    /// Path and query as received by the upstream, after `strip_prefix`
+    pub path: String,
+    pub iat: i64,
+    pub exp: i64,
+}
+
+impl IdentityAssertion {
+    pub fn new(sub: Option<Uuid>, rid: &str, method: &str, path: &str, now: i64) -> Self {
+        Self {
+            sub,
+            rid: rid.to_string(),
+            method: method.to_string(),
+            path: path.to_string(),
+            iat: now,
+            exp: now + ASSERTION_TTL_SECS,
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error, PartialEq)]
+pub enum AssertionError {
+    #[error("identity assertion header missing")]
+    Missing,
+    #[error("identity assertion signed with unknown key")]
+    UnknownKey,
+    #[error("identity assertion expired")]
+    Expired,
+    #[error("identity assertion invalid")]
+    Invalid,
+    #[error("identity assertion does not match the request")]
+    RequestMismatch,
+}
+
+/// Signs assertions for one upstream.
+#[derive(Clone)]
+pub struct IdentitySigner {
+    kid: String,
+    key: EncodingKey,
+}
+
+impl IdentitySigner {
+    pub fn new(kid: &str, secret: &[u8]) -> Self {
+        Self {
+            kid: kid.to_string(),
+            key: EncodingKey::from_secret(secret),
+        }
+    }
+
+    pub fn sign(&self, assertion: &IdentityAssertion) -> Result<String, AssertionError> {
+        let mut header = Header::new(Algorithm::HS256);
+        header.kid = Some(self.kid.clone());
+        encode(&header, assertion, &self.key).map_err(|_| AssertionError::Invalid)
+    }
+}
+
+/// Verifies assertions on the upstream side. Holds every key the upstream
+/// accepts, so the pending key of a rotation can be added before it is
+/// promoted and the gateway switches to it.
+#[derive(Clone)]
+pub struct IdentityVerifier {
+    keys: HashMap<String, DecodingKey>,
+    validation: Validation,
+}
+
+impl Default for IdentityVerifier {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl IdentityVerifier {
+    pub fn new() -> Self {
+        let mut validation = Validation::new(Algorithm::HS256);
+        validation.set_required_spec_claims(&["exp", "iat"]);
+        validation.leeway = DEFAULT_LEEWAY_SECS;
+        Self {
+            keys: HashMap::new(),
+            validation,
+        }
+    }
+
+    pub fn with_key(mut self, kid: &str, secret: &[u8]) -> Self {
+        self.keys
+            .insert(kid.to_string(), DecodingKey::from_secret(secret));
+        self
+    }
+
+    /// Checks the signature and expiry, and that the assertion was issued
+    /// for this `method` and `path` (with the query, if any).
+    pub fn verify(
+        &self,
+        token: &str,
+        method: &str,
+        path: &str,
+    ) -> Result<IdentityAssertion, AssertionError> {
+        let header = decode_header(token).map_err(|_| AssertionError::Invalid)?;
+        let key = header
+            .kid
+            .as_deref()
+            .and_then(|kid| self.keys.get(kid))
+            .ok_or(AssertionError::UnknownKey)?;
+
+        let assertion = decode::<IdentityAssertion>(token, key, &self.validation)
+            .map_err(|e| match e.kind() {
+                jsonwebtoken::errors::ErrorKind::ExpiredSignature => AssertionError::Expired,
+                _ => AssertionError::Invalid,
+            })?
+            .claims;
+
+        if !assertion.method.eq_ignore_ascii_case(method) || assertion.path != path {
+            return Err(AssertionError::RequestMismatch);
+        }
+        Ok(assertion)
+    }
+
+    /// [`verify`](Self::verify) reading the assertion from request headers.
+    pub fn verify_headers(
+        &self,
+        headers: &HeaderMap,
+        method: &str,
+        uri: &Uri,
+    ) -> Result<IdentityAssertion, AssertionError> {
+        let token = headers
+            .get(IDENTITY_ASSERTION_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .ok_or(AssertionError::Missing)?;
+        self.verify(token, method, path_and_query(uri))
+    }
+}
+
+/// Path and query of `uri` as signed in [`IdentityAssertion::path`].
+pub fn path_and_query(uri: &Uri) -> &str {
+    uri.path_and_query().map_or("/", |pq| pq.as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &[u8] = b"upstream-secret";
+
+    fn signed(path: &str, now: i64) -> String {
+        let assertion = IdentityAssertion::new(Some(Uuid::new_v4()), "req-1", "GET", path, now);
+        IdentitySigner::new("k1", SECRET).sign(&assertion).unwrap()
+    }
+
+    #[test]
+    fn test_verify_round_trip() {
+        let now = chrono::Utc::now().timestamp();
+        let verifier = IdentityVerifier::new().with_key("k1", SECRET);
+
+        let assertion = verifier
+            .verify(&signed("/orders", now), "get", "/orders")
+            .unwrap();
+        assert_eq!(assertion.rid, "req-1");
+        assert!(assertion.sub.is_some());
+    }
+
+    #[test]
+    fn test_verify_rejects_other_request_and_key() {
+        let now = chrono::Utc::now().timestamp();
+        let token = signed("/orders", now);
+
+        let verifier = IdentityVerifier::new().with_key("k1", SECRET);
+        assert_eq!(
+            verifier.verify(&token, "GET", "/admin"),
+            Err(AssertionError::RequestMismatch)
+        );
+        assert_eq!(
+            verifier.verify(&token, "DELETE", "/orders"),
+            Err(AssertionError::RequestMismatch)
+        );
+        assert_eq!(
+            verifier.verify(&token, "GET", "/orders?all=true"),
+            Err(AssertionError::RequestMismatch)
+        );
+
+        let wrong_secret = IdentityVerifier::new().with_key("k1", b"other");
+        assert_eq!(
+            wrong_secret.verify(&token, "GET", "/orders"),
+            Err(AssertionError::Invalid)
+        );
+        let unknown_kid = IdentityVerifier::new().with_key("k2", SECRET);
+        assert_eq!(
+            unknown_kid.verify(&token, "GET", "/orders"),
+            Err(AssertionError::UnknownKey)
+        );
+    }
+
+    #[test]
+    fn test_verify_rejects_expired() {
+        let issued = chrono::Utc::now().timestamp() - ASSERTION_TTL_SECS - 60;
+        let verifier = IdentityVerifier::new().with_key("k1", SECRET);
+        assert_eq!(
+            verifier.verify(&signed("/orders", issued), "GET", "/orders"),
+            Err(AssertionError::Expired)
+        );
+    }
+
+    #[test]
+    fn test_verify_headers_missing() {
+        let verifier = IdentityVerifier::new().with_key("k1", SECRET);
+        assert_eq!(
+            verifier.verify_headers(&HeaderMap::new(), "GET", &Uri::from_static("/")),
+            Err(AssertionError::Missing)
+        );
+    }
+
+    #[test]
+    fn test_verify_headers_binds_query() {
+        let now = chrono::Utc::now().timestamp();
+        let verifier = IdentityVerifier::new().with_key("k1", SECRET);
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            IDENTITY_ASSERTION_HEADER,
+            signed("/orders?page=2", now).parse().unwrap(),
+        );
+
+        let uri = Uri::from_static("http://upstream/orders?page=2");
+        assert!(verifier.verify_headers(&headers, "GET", &uri).is_ok());
+        let uri = Uri::from_static("/orders?page=3");
+        assert_eq!(
+            verifier.verify_headers(&headers, "GET", &uri),
+            Err(AssertionError::RequestMismatch)
+        );
+    }
+}
@@ -3,5 +3,6 @@ pub mod config;
 pub mod db;
 pub mod error;
 pub mod gateway;
+pub mod identity_assertion;
 pub mod models;
 pub mod services;
@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::sync::{atomic::AtomicU64, Arc};
 use tokio::net::TcpListener;
@@ -8,16 +9,37 @@ mod config;
 mod db;
 mod error;
 mod gateway;
+// The verifying half is for upstream services using the library crate.
+#[allow(dead_code)]
+mod identity_assertion;
 mod models;
 mod services;
 
+use api::handlers::validate_route;
 use api::AppState;
-use config::AppConfig;
-use gateway::config_cache::CachedRoute;
-use gateway::{JwtValidator, ProxyConfigCache};
+use config::{AppConfig, RouteConfig};
+use gateway::concurrency::ConcurrencyLimit;
+use gateway::config_cache::{AuthMode, CachedRoute};
+use gateway::health_check::check_target;
+use gateway::identity::IdentityHeaders;
+use gateway::proxy::AuthGateway;
+use gateway::rate_limit::CachedRateLimitRule;
+use gateway::route_matcher::{check_pattern, normalize_methods, MatchType};
+use gateway::token_source::TokenSources;
+use gateway::upstream_options::UpstreamOptions;
+use gateway::upstream_pool::CachedUpstreamPool;
+use gateway::usage::Quota;
+use gateway::virtual_host::HostPattern;
+use gateway::{
+    ApiKeyCache, CircuitBreaker, ConcurrencyLimiter, ConnectionTracker, JwtValidator,
+    ProxyConfigCache, RateLimitCache, RevocationCache, UsageMeter,
+};
+use identity_assertion::IdentitySigner;
+use models::ProxyRouteInput;
+use services::usage::USAGE_RETENTION_DAYS;
 use services::{
-    AdminService, ApiKeyService, CaptchaService, EmailService, ProxyConfigService, SrpService,
-    SystemConfigService, TokenService, UserService,
+    AdminService, ApiKeyService, CaptchaService, EmailService, JwtConfigService, MfaService,
+    ProxyConfigService, SrpService, SystemConfigService, TokenService, UsageService, UserService,
 };
 
 #[tokio::main]
@@ -33,18 +55,19 @@ async fn main() -> anyhow::Result<()> {
     let system_config_service = Arc::new(SystemConfigService::new(db_pool.clone()));
     system_config_service.initialize().await?;
 
+    let jwt_config_service = Arc::new(JwtConfigService::new(db_pool.clone()));
+    jwt_config_service.initialize(&config.jwt).await?;
+
     let jwt_validator = Arc::new(JwtValidator::new(
         system_config_service.clone(),
-        config.jwt.auto_refresh_threshold,
+        jwt_config_service.clone(),
     ));
     jwt_validator.init().await?;
     let user_service = Arc::new(UserService::new(db_pool.clone()));
     let token_service = Arc::new(TokenService::new(
         db_pool.clone(),
         system_config_service.clone(),
-        config.jwt.access_token_ttl,
-        config.jwt.refresh_token_ttl,
-        config.jwt.auto_refresh_threshold,
+        jwt_config_service.clone(),
     ));
     let email_service = Arc::new(EmailService::new(system_config_service.clone()));
     let admin_service = Arc::new(AdminService::new(
@@ -54,7 +77,9 @@ async fn main() -> anyhow::Result<()> {
     let proxy_config_service = Arc::new(ProxyConfigService::new(db_pool.clone()));
     let api_key_service = Arc::new(ApiKeyService::new(db_pool.clone()));
     let srp_service = Arc::new(SrpService::new(db_pool.clone()));
+    let mfa_service = Arc::new(MfaService::new(db_pool.clone()));
     let captcha_service = Arc::new(CaptchaService::new(db_pool.clone()));
+    let usage_service = Arc::new(UsageService::new(db_pool.clone()));
 
     let default_upstream = if config.upstream.default_upstream.is_empty() {
         None
@@ -70,13 +95,83 @@ async fn main() -> anyhow::Result<()> {
         .routing
         .routes
         .iter()
-        .map(|r| CachedRoute {
-            path_prefix: r.path.clone(),
-            upstream_address: r.upstream.clone(),
-            require_auth: r.auth,
-            strip_prefix: r.strip_prefix.clone(),
+        .map(|r| {
+            let mut input =
+                route_input(r).map_err(|e| anyhow::anyhow!("Route {}: {}", r.path, e))?;
+            validate_route(&mut input).map_err(|e| anyhow::anyhow!("Route {}: {}", r.path, e))?;
+            let identity_headers = IdentityHeaders::from_map(&r.identity_headers)
+                .map_err(|e| anyhow::anyhow!("Route {}: {}", r.path, e))?;
+            let auth_mode = match r.auth_mode.as_deref() {
+                Some(mode) => AuthMode::parse(mode).ok_or_else(|| {
+                    anyhow::anyhow!("Route {}: unknown auth_mode {}", r.path, mode)
+                })?,
+                None if r.auth => AuthMode::Required,
+                None => AuthMode::None,
+            };
+            let host = r
+                .host
+                .as_deref()
+                .map(|host| {
+                    HostPattern::parse(host)
+                        .ok_or_else(|| anyhow::anyhow!("Route {}: invalid host {}", r.path, host))
+                })
+                .transpose()?;
+            let match_type = match r.match_type.as_deref() {
+                Some(value) => MatchType::parse(value).ok_or_else(|| {
+                    anyhow::anyhow!("Route {}: unknown match_type {}", r.path, value)
+                })?,
+                None => MatchType::Prefix,
+            };
+            check_pattern(match_type, &r.path, r.rewrite.as_deref())
+                .map_err(|e| anyhow::anyhow!("Route {}: {}", r.path, e))?;
+            let methods = normalize_methods(&r.methods)
+                .map_err(|e| anyhow::anyhow!("Route {}: {}", r.path, e))?;
+            Ok(CachedRoute {
+                host,
+                path_prefix: r.path.clone(),
+                match_type,
+                priority: r.priority,
+                methods,
+                rewrite: r.rewrite.clone(),
+                upstream_address: r.upstream.clone(),
+                auth_mode,
+                strip_prefix: r.strip_prefix.clone(),
+                max_auth_age_secs: r.max_auth_age_secs,
+                require_mfa: r.require_mfa,
+                required_roles: r.required_roles.clone(),
+                identity_headers,
+                token_sources: TokenSources {
+                    query_param: r.token_query_param.clone(),
+                    cookie: r.token_cookie.clone(),
+                    subprotocol: r.token_subprotocol,
+                },
+                max_connection_secs: r.max_connection_secs,
+                close_at_token_expiry: r.close_at_token_expiry,
+                max_connections_per_user: r.max_connections_per_user,
+                upstream_pool: r.upstream_pool.clone(),
+                upstream_options: UpstreamOptions {
+                    connect_timeout: r.connect_timeout_ms.map(std::time::Duration::from_millis),
+                    read_timeout: r.read_timeout_ms.map(std::time::Duration::from_millis),
+                    write_timeout: r.write_timeout_ms.map(std::time::Duration::from_millis),
+                    idle_timeout: r.idle_timeout_ms.map(std::time::Duration::from_millis),
+                    max_retries: r.max_retries,
+                    keepalive: r.upstream_keepalive,
+                },
+                concurrency_limit: r.max_in_flight.filter(|max| *max > 0).map(|max| {
+                    ConcurrencyLimit {
+                        max_in_flight: max,
+                        max_queue: r.max_queue,
+                        queue_timeout: std::time::Duration::from_millis(r.queue_timeout_ms),
+                        fair: r.fair_queue,
+                    }
+                }),
+                quota: Quota {
+                    daily: r.daily_quota,
+                    monthly: r.monthly_quota,
+                },
+            })
         })
-        .collect();
+        .collect::<anyhow::Result<_>>()?;
     if !static_routes.is_empty() {
         tracing::info!(
             "Loaded {} static routes from config/env",
@@ -87,9 +182,30 @@ async fn main() -> anyhow::Result<()> {
 
     let config_cache = Arc::new(config_cache);
     load_proxy_config(&proxy_config_service, &config_cache).await?;
+    load_upstream_pools(&proxy_config_service, &config_cache).await?;
+    load_identity_keys(&system_config_service, &config_cache).await?;
+
+    let rate_limit_cache = Arc::new(RateLimitCache::new());
+    load_rate_limits(&proxy_config_service, &rate_limit_cache).await?;
+
+    let revocation_cache = Arc::new(RevocationCache::new());
+    load_revocations(&user_service, &token_service, &revocation_cache).await?;
+
+    let api_key_cache = Arc::new(ApiKeyCache::new());
+    load_api_keys(&api_key_service, &api_key_cache).await?;
+
+    let connection_tracker = Arc::new(ConnectionTracker::new());
+    let concurrency_limiter = Arc::new(ConcurrencyLimiter::new());
+    let circuit_breaker = Arc::new(CircuitBreaker::new(
+        config.upstream.circuit_failure_threshold,
+        std::time::Duration::from_secs(config.upstream.circuit_cooldown_secs),
+    ));
+
+    let usage_meter = Arc::new(UsageMeter::new());
+    load_usage_totals(&usage_service, &usage_meter).await?;
 
     tracing::info!("Pre-resolving upstream DNS...");
-    config_cache.resolve_all_upstreams();
+    config_cache.resolve_upstreams().await;
 
     initialize_admin_token(&admin_service).await?;
 
@@ -97,6 +213,7 @@ async fn main() -> anyhow::Result<()> {
 
     let system_config_for_scheduler = system_config_service.clone();
     let jwt_validator_for_scheduler = jwt_validator.clone();
+    let jwt_config_for_scheduler = jwt_config_service.clone();
 
     let state = AppState {
         db_pool: db_pool.clone(),
@@ -106,17 +223,38 @@ async fn main() -> anyhow::Result<()> {
         admin_service,
         proxy_config_service,
         system_config_service,
+        jwt_config_service,
         api_key_service,
         srp_service,
+        mfa_service,
         captcha_service,
         captcha_enabled: config.captcha.enabled,
         jwt_validator: Some(jwt_validator.clone()),
         config_cache: Some(config_cache.clone()),
+        rate_limit_cache: Some(rate_limit_cache.clone()),
+        revocation_cache: Some(revocation_cache.clone()),
+        api_key_cache: Some(api_key_cache.clone()),
+        connection_tracker: Some(connection_tracker.clone()),
+        concurrency_limiter: Some(concurrency_limiter.clone()),
+        circuit_breaker: Some(circuit_breaker.clone()),
+        usage_service: usage_service.clone(),
+        usage_meter: Some(usage_meter.clone()),
         request_counter,
     };
 
+    let system_config_for_retirement = system_config_for_scheduler.clone();
+    let jwt_validator_for_retirement = jwt_validator_for_scheduler.clone();
+    tokio::spawn(async move {
+        jwt_rotation_scheduler(
+            system_config_for_scheduler,
+            jwt_config_for_scheduler,
+            jwt_validator_for_scheduler,
+        )
+        .await;
+    });
     tokio::spawn(async move {
-        jwt_rotation_scheduler(system_config_for_scheduler, jwt_validator_for_scheduler).await;
+        jwt_key_retirement_scheduler(system_config_for_retirement, jwt_validator_for_retirement)
+            .await;
     });
 
     let db_pool_for_cleanup = db_pool.clone();
@@ -124,6 +262,23 @@ async fn main() -> anyhow::Result<()> {
         database_cleanup_scheduler(db_pool_for_cleanup).await;
     });
 
+    let config_cache_for_dns = config_cache.clone();
+    let dns_refresh = std::time::Duration::from_secs(config.upstream.dns_refresh_secs.max(1));
+    tokio::spawn(async move {
+        dns_refresh_scheduler(config_cache_for_dns, dns_refresh).await;
+    });
+
+    let usage_flush = std::time::Duration::from_secs(config.metering.flush_secs.max(1));
+    let usage_meter_for_flush = usage_meter.clone();
+    tokio::spawn(async move {
+        usage_flush_scheduler(usage_service, usage_meter_for_flush, usage_flush).await;
+    });
+
+    let config_cache_for_health = config_cache.clone();
+    tokio::spawn(async move {
+        upstream_health_scheduler(config_cache_for_health).await;
+    });
+
     let api_port = config.server.api_port;
     let admin_port = config.server.admin_port;
     let state_for_admin = state.clone();
@@ -146,7 +301,18 @@ async fn main() -> anyhow::Result<()> {
     );
 
     std::thread::spawn(move || {
-        start_gateway(config, jwt_validator, config_cache);
+        let gateway = AuthGateway::new(
+            jwt_validator,
+            config_cache,
+            rate_limit_cache,
+            revocation_cache,
+            api_key_cache,
+            connection_tracker,
+            concurrency_limiter,
+            circuit_breaker,
+            usage_meter,
+        );
+        start_gateway(config, gateway);
     });
 
     tokio::signal::ctrl_c().await?;
@@ -209,12 +375,7 @@ async fn initialize_admin_token(admin_service: &AdminService) -> anyhow::Result<
     Ok(())
 }
 
-fn start_gateway(
-    config: Arc<AppConfig>,
-    jwt_validator: Arc<JwtValidator>,
-    config_cache: Arc<ProxyConfigCache>,
-) {
-    use gateway::proxy::AuthGateway;
+fn start_gateway(config: Arc<AppConfig>, gateway: AuthGateway) {
     use pingora::proxy::http_proxy_service;
     use pingora::server::configuration::ServerConf;
     use pingora::server::Server;
@@ -232,8 +393,6 @@ fn start_gateway(
     let mut server = Server::new_with_opt_and_conf(None, server_conf);
     server.bootstrap();
 
-    let gateway = AuthGateway::new(jwt_validator, config_cache);
-
     let mut proxy = http_proxy_service(&server.configuration, gateway);
     proxy.add_tcp(&format!("0.0.0.0:{}", config.server.gateway_port));
 
@@ -241,6 +400,57 @@ fn start_gateway(
     server.run_forever();
 }
 
+/// A static route as API input, so it is validated like routes created
+/// through the admin API.
+fn route_input(r: &RouteConfig) -> Result<ProxyRouteInput, String> {
+    fn int<T: TryInto<i32>>(field: &str, value: T) -> Result<i32, String> {
+        value
+            .try_into()
+            .map_err(|_| format!("{} is too large", field))
+    }
+    fn opt<T: TryInto<i32>>(field: &str, value: Option<T>) -> Result<Option<i32>, String> {
+        value.map(|v| int(field, v)).transpose()
+    }
+    Ok(ProxyRouteInput {
+        host: r.host.clone(),
+        path_prefix: r.path.clone(),
+        upstream_address: r.upstream.clone(),
+        auth_mode: r.auth_mode.clone(),
+        require_auth: Some(r.auth),
+        strip_prefix: r.strip_prefix.clone(),
+        max_auth_age_secs: opt("max_auth_age_secs", r.max_auth_age_secs)?,
+        require_mfa: r.require_mfa,
+        required_roles: r.required_roles.clone(),
+        identity_headers: r.identity_headers.clone(),
+        token_query_param: r.token_query_param.clone(),
+        token_cookie: r.token_cookie.clone(),
+        token_subprotocol: r.token_subprotocol,
+        max_connection_secs: opt("max_connection_secs", r.max_connection_secs)?,
+        close_at_token_expiry: r.close_at_token_expiry,
+        max_connections_per_user: opt("max_connections_per_user", r.max_connections_per_user)?,
+        upstream_pool: r.upstream_pool.clone(),
+        connect_timeout_ms: opt("connect_timeout_ms", r.connect_timeout_ms)?,
+        read_timeout_ms: opt("read_timeout_ms", r.read_timeout_ms)?,
+        write_timeout_ms: opt("write_timeout_ms", r.write_timeout_ms)?,
+        idle_timeout_ms: opt("idle_timeout_ms", r.idle_timeout_ms)?,
+        max_retries: int("max_retries", r.max_retries)?,
+        upstream_keepalive: r.upstream_keepalive,
+        max_in_flight: opt("max_in_flight", r.max_in_flight)?,
+        max_queue: int("max_queue", r.max_queue)?,
+        queue_timeout_ms: int("queue_timeout_ms", r.queue_timeout_ms)?,
+        fair_queue: r.fair_queue,
+        daily_quota: opt("daily_quota", r.daily_quota)?,
+        monthly_quota: opt("monthly_quota", r.monthly_quota)?,
+        match_type: r
+            .match_type
+            .clone()
+            .unwrap_or_else(|| MatchType::Prefix.as_str().to_string()),
+        priority: r.priority,
+        methods: r.methods.clone(),
+        rewrite: r.rewrite.clone(),
+    })
+}
+
 async fn load_proxy_config(
     service: &ProxyConfigService,
     cache: &ProxyConfigCache,
@@ -250,12 +460,7 @@ async fn load_proxy_config(
     let cached_routes: Vec<CachedRoute> = routes
         .into_iter()
         .filter(|r| r.enabled)
-        .map(|r| CachedRoute {
-            path_prefix: r.path_prefix,
-            upstream_address: r.upstream_address,
-            require_auth: r.require_auth,
-            strip_prefix: r.strip_prefix,
-        })
+        .map(CachedRoute::from)
         .collect();
 
     let routes_count = cached_routes.len();
@@ -264,6 +469,124 @@ async fn load_proxy_config(
     Ok(())
 }
 
+async fn load_upstream_pools(
+    service: &ProxyConfigService,
+    cache: &ProxyConfigCache,
+) -> anyhow::Result<()> {
+    let pools: Vec<CachedUpstreamPool> = service
+        .list_upstream_pools()
+        .await?
+        .into_iter()
+        .map(CachedUpstreamPool::from_pool)
+        .collect();
+
+    let pools_count = pools.len();
+    cache.update_upstream_pools(pools);
+    tracing::info!("Loaded {} upstream pools from database", pools_count);
+    Ok(())
+}
+
+async fn load_identity_keys(
+    service: &SystemConfigService,
+    cache: &ProxyConfigCache,
+) -> anyhow::Result<()> {
+    let signers: HashMap<String, IdentitySigner> = service
+        .list_identity_keys()
+        .await?
+        .into_iter()
+        .map(|k| {
+            let signer = IdentitySigner::new(&k.kid, k.secret.as_bytes());
+            (k.upstream_address, signer)
+        })
+        .collect();
+
+    let keys_count = signers.len();
+    cache.update_identity_signers(signers);
+    tracing::info!("Loaded {} identity assertion keys", keys_count);
+    Ok(())
+}
+
+async fn load_rate_limits(
+    service: &ProxyConfigService,
+    cache: &RateLimitCache,
+) -> anyhow::Result<()> {
+    let rules = service.list_rate_limits().await?;
+
+    let cached_rules: Vec<CachedRateLimitRule> = rules
+        .into_iter()
+        .filter_map(CachedRateLimitRule::from_rule)
+        .collect();
+
+    let rules_count = cached_rules.len();
+    cache.update_rules(cached_rules);
+    tracing::info!("Loaded {} rate limit rules from database", rules_count);
+    Ok(())
+}
+
+async fn load_revocations(
+    user_service: &UserService,
+    token_service: &TokenService,
+    cache: &RevocationCache,
+) -> anyhow::Result<()> {
+    let users = user_service.list_revoked_users().await?;
+    let tokens = token_service.list_revoked_access_tokens().await?;
+
+    let (users_count, tokens_count) = (users.len(), tokens.len());
+    cache.update_users(users);
+    cache.update_tokens(tokens);
+    tracing::info!(
+        "Loaded {} revoked users and {} revoked tokens from database",
+        users_count,
+        tokens_count
+    );
+    Ok(())
+}
+
+async fn load_api_keys(service: &ApiKeyService, cache: &ApiKeyCache) -> anyhow::Result<()> {
+    let hashes = service.list_key_hashes().await?;
+    let count = hashes.len();
+    cache.update_keys(hashes);
+    tracing::info!("Loaded {} API keys from database", count);
+    Ok(())
+}
+
+async fn load_usage_totals(service: &UsageService, meter: &UsageMeter) -> anyhow::Result<()> {
+    let now = chrono::Utc::now();
+    let totals = service.period_totals(now).await?;
+    let count = totals.len();
+    meter.sync_totals(totals, now);
+    tracing::info!("Loaded {} usage totals from database", count);
+    Ok(())
+}
+
+/// Writes metered usage to the database, then refreshes the quota counts
+/// with the usage recorded by every gateway instance.
+async fn usage_flush_scheduler(
+    usage_service: Arc<UsageService>,
+    meter: Arc<UsageMeter>,
+    every: std::time::Duration,
+) {
+    let mut ticker = tokio::time::interval(every);
+    loop {
+        ticker.tick().await;
+
+        let records = meter.take_pending();
+        if !records.is_empty() {
+            if let Err(e) = usage_service.record(&records).await {
+                tracing::error!("Failed to write usage, retrying later: {}", e);
+                meter.restore(records);
+                continue;
+            }
+        }
+
+        let now = chrono::Utc::now();
+        match usage_service.period_totals(now).await {
+            Ok(totals) => meter.sync_totals(totals, now),
+            Err(e) => tracing::warn!("Failed to refresh usage totals: {}", e),
+        }
+    }
+}
+
 async fn database_cleanup_scheduler(db_pool: Arc<sqlx::PgPool>) {
     use tokio::time::{interval, Duration};
 
@@ -288,16 +611,17 @@ async fn database_cleanup_scheduler(db_pool: Arc<sqlx::PgPool>) {
             Err(e) => tracing::error!("Failed to cleanup verification codes: {}", e),
         }
 
-        let deleted_tokens =
-            sqlx::query("DELETE FROM refresh_tokens WHERE expires_at < NOW() OR revoked = TRUE")
-                .execute(db_pool.as_ref())
-                .await;
+        // Revoked tokens are kept until they expire so a replayed rotated
+        // token is still recognised and its family revoked.
+        let deleted_tokens = sqlx::query("DELETE FROM refresh_tokens WHERE expires_at < NOW()")
+            .execute(db_pool.as_ref())
+            .await;
 
         match deleted_tokens {
             Ok(result) => {
                 if result.rows_affected() > 0 {
                     tracing::info!(
-                        "Cleaned up {} expired/revoked refresh tokens",
+                        "Cleaned up {} expired refresh tokens",
                         result.rows_affected()
                     );
                 }
@@ -305,6 +629,56 @@ async fn database_cleanup_scheduler(db_pool: Arc<sqlx::PgPool>) {
             Err(e) => tracing::error!("Failed to cleanup refresh tokens: {}", e),
         }
 
+        let deleted_revocations =
+            sqlx::query("DELETE FROM user_revocations WHERE expires_at < NOW()")
+                .execute(db_pool.as_ref())
+                .await;
+
+        match deleted_revocations {
+            Ok(result) => {
+                if result.rows_affected() > 0 {
+                    tracing::info!(
+                        "Cleaned up {} expired user revocations",
+                        result.rows_affected()
+                    );
+                }
+            }
+            Err(e) => tracing::error!("Failed to cleanup user revocations: {}", e),
+        }
+
+        let deleted_revoked_tokens =
+            sqlx::query("DELETE FROM revoked_tokens WHERE expires_at < NOW()")
+                .execute(db_pool.as_ref())
+                .await;
+
+        match deleted_revoked_tokens {
+            Ok(result) => {
+                if result.rows_affected() > 0 {
+                    tracing::info!(
+                        "Cleaned up {} expired access token revocations",
+                        result.rows_affected()
+                    );
+                }
+            }
+            Err(e) => tracing::error!("Failed to cleanup revoked tokens: {}", e),
+        }
+
+        let deleted_challenges = sqlx::query("DELETE FROM mfa_challenges WHERE expires_at < NOW()")
+            .execute(db_pool.as_ref())
+            .await;
+
+        match deleted_challenges {
+            Ok(result) => {
+                if result.rows_affected() > 0 {
+                    tracing::info!(
+                        "Cleaned up {} expired MFA challenges",
+                        result.rows_affected()
+                    );
+                }
+            }
+            Err(e) => tracing::error!("Failed to cleanup MFA challenges: {}", e),
+        }
+
         let deleted_captchas =
             sqlx::query("DELETE FROM captchas WHERE expires_at < NOW() OR used = TRUE")
                 .execute(db_pool.as_ref())
@@ -321,11 +695,95 @@ async fn database_cleanup_scheduler(db_pool: Arc<sqlx::PgPool>) {
             }
             Err(e) => tracing::error!("Failed to cleanup captchas: {}", e),
         }
+
+        let deleted_usage = sqlx::query(
+            "DELETE FROM usage_buckets WHERE bucket_start < NOW() - make_interval(days => $1)",
+        )
+        .bind(USAGE_RETENTION_DAYS as i32)
+        .execute(db_pool.as_ref())
+        .await;
+
+        match deleted_usage {
+            Ok(result) => {
+                if result.rows_affected() > 0 {
+                    tracing::info!("Cleaned up {} old usage buckets", result.rows_affected());
+                }
+            }
+            Err(e) => tracing::error!("Failed to cleanup usage buckets: {}", e),
+        }
+    }
+}
+
+/// Re-resolves upstream addresses on an interval and whenever routes or
+/// pools change, so the proxy path only reads the cache.
+async fn dns_refresh_scheduler(config_cache: Arc<ProxyConfigCache>, every: std::time::Duration) {
+    use tokio::time::{interval, MissedTickBehavior};
+
+    let mut tick = interval(every);
+    tick.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    // The first tick completes immediately; startup already resolved
+    tick.tick().await;
+
+    loop {
+        tokio::select! {
+            _ = tick.tick() => {}
+            _ = config_cache.resolve_requested() => {}
+        }
+        config_cache.resolve_upstreams().await;
+    }
+}
+
+/// Probes the targets of every pool with a health check path, each pool at
+/// its own interval.
+async fn upstream_health_scheduler(config_cache: Arc<ProxyConfigCache>) {
+    use tokio::time::{interval, Duration, Instant, MissedTickBehavior};
+
+    let mut tick = interval(Duration::from_secs(1));
+    tick.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut last_checked: HashMap<String, Instant> = HashMap::new();
+
+    loop {
+        tick.tick().await;
+        let now = Instant::now();
+        let mut checks = tokio::task::JoinSet::new();
+
+        for pool in config_cache.upstream_pools() {
+            let path = match pool.health_check_path {
+                Some(ref path) => path.clone(),
+                None => continue,
+            };
+            if last_checked
+                .get(&pool.name)
+                .is_some_and(|t| now.duration_since(*t) < pool.health_check_interval)
+            {
+                continue;
+            }
+            last_checked.insert(pool.name.clone(), now);
+
+            for target in pool.targets() {
+                let target = target.clone();
+                let path = path.clone();
+                let pool_name = pool.name.clone();
+                checks.spawn(async move {
+                    let ok = check_target(&target.address, &path).await;
+                    if target.record_check(ok) {
+                        if ok {
+                            tracing::info!(pool = %pool_name, upstream = %target.address, "Upstream target healthy");
+                        } else {
+                            tracing::warn!(pool = %pool_name, upstream = %target.address, "Upstream target unhealthy");
+                        }
+                    }
+                });
+            }
+        }
+
+        while checks.join_next().await.is_some() {}
     }
 }
 
 async fn jwt_rotation_scheduler(
     system_config: Arc<SystemConfigService>,
+    jwt_config: Arc<JwtConfigService>,
     jwt_validator: Arc<JwtValidator>,
 ) {
     use tokio::time::{interval, Duration};
@@ -338,7 +796,11 @@ async fn jwt_rotation_scheduler(
         match system_config.should_auto_rotate().await {
             Ok(true) => {
                 tracing::info!("JWT secret is older than 30 days, rotating...");
-                if let Err(e) = system_config.rotate_jwt_secret().await {
+                let rotated = match jwt_config.key_grace_period().await {
+                    Ok(grace) => system_config.rotate_jwt_secret(None, grace).await,
+                    Err(e) => Err(e),
+                };
+                if let Err(e) = rotated {
                     tracing::error!("Failed to auto-rotate JWT secret: {}", e);
                     continue;
                 }
@@ -356,3 +818,28 @@ async fn jwt_rotation_scheduler(
         }
     }
 }
+
+/// Retires rotated-out JWT keys once their grace has ended.
+async fn jwt_key_retirement_scheduler(
+    system_config: Arc<SystemConfigService>,
+    jwt_validator: Arc<JwtValidator>,
+) {
+    use tokio::time::{interval, Duration};
+
+    let mut check_interval = interval(Duration::from_secs(5 * 60));
+
+    loop {
+        check_interval.tick().await;
+
+        match system_config.retire_jwt_keys(false).await {
+            Ok(0) => {}
+            Ok(count) => {
+                tracing::info!("Retired {} JWT keys past their grace period", count);
+                if let Err(e) = jwt_validator.refresh_secret().await {
+                    tracing::error!("Failed to refresh JWT validator: {}", e);
+                }
+            }
+            Err(e) => tracing::error!("Failed to retire expired JWT keys: {}", e),
+        }
+    }
+}
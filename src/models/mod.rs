@@ -1,3 +1,5 @@
+use std::collections::BTreeMap;
+
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
@@ -42,6 +44,71 @@ pub struct RefreshToken {
     pub expires_at: DateTime<Utc>,
     pub revoked: bool,
     pub created_at: DateTime<Utc>,
+    /// Shared by every token rotated from the same login
+    pub family_id: Uuid,
+    /// Set when the token was exchanged for a successor
+    pub rotated_at: Option<DateTime<Utc>>,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+    pub label: Option<String>,
+    /// Login time of the family, carried forward on rotation
+    pub session_created_at: DateTime<Utc>,
+    pub last_used_at: DateTime<Utc>,
+    /// Authentication methods of the login, carried forward on rotation
+    pub amr: Vec<String>,
+}
+
+/// Client details recorded with a refresh token when it is issued.
+#[derive(Debug, Clone, Default)]
+pub struct SessionMetadata {
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+}
+
+/// A login session (refresh token family) as shown to users and admins.
+#[derive(Debug, Clone, FromRow, Serialize)]
+pub struct Session {
+    pub id: Uuid,
+    pub label: Option<String>,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+pub const SECURITY_EVENT_REFRESH_TOKEN_REUSE: &str = "refresh_token_reuse";
+
+#[derive(Debug, Clone, FromRow, Serialize)]
+pub struct SecurityEvent {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub event_type: String,
+    pub details: serde_json::Value,
+    pub created_at: DateTime<Utc>,
+}
+
+/// `amr` values (RFC 8176) recorded in access tokens.
+pub const AMR_PASSWORD: &str = "pwd";
+pub const AMR_OTP: &str = "otp";
+pub const AMR_MFA: &str = "mfa";
+
+/// How and when the user authenticated for a login. Stamped into every
+/// access token issued for that login, including refreshed ones.
+#[derive(Debug, Clone)]
+pub struct AuthContext {
+    pub amr: Vec<String>,
+    pub auth_time: DateTime<Utc>,
+}
+
+impl AuthContext {
+    /// Context of a login completing now.
+    pub fn new(amr: Vec<String>) -> Self {
+        Self {
+            amr,
+            auth_time: Utc::now(),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -51,6 +118,15 @@ pub struct AccessTokenClaims {
     pub exp: i64,
     pub iat: i64,
     pub jti: Uuid,
+    /// Authentication methods used at login, e.g. `["pwd", "otp", "mfa"]`
+    #[serde(default)]
+    pub amr: Vec<String>,
+    /// When the user logged in (unix seconds); unchanged by refresh
+    #[serde(default)]
+    pub auth_time: i64,
+    /// Roles of the user when the token was issued
+    #[serde(default)]
+    pub roles: Vec<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -85,13 +161,238 @@ pub struct AdminRegistrationToken {
 #[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
 pub struct ProxyRoute {
     pub id: Uuid,
+    /// Host name or `*.domain` the route is limited to; `None` serves any host
+    pub host: Option<String>,
     pub path_prefix: String,
     pub upstream_address: String,
-    pub require_auth: bool,
+    /// `required`, `optional` or `none`
+    pub auth_mode: String,
     pub strip_prefix: Option<String>,
     pub enabled: bool,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
+    /// Step-up: maximum seconds since the user logged in
+    pub max_auth_age_secs: Option<i32>,
+    /// Step-up: login must have used a second factor
+    pub require_mfa: bool,
+    /// Users need at least one of these roles; empty allows everyone
+    pub required_roles: Vec<String>,
+    /// Token claims forwarded upstream, `{"claim": "Header-Name"}`
+    pub identity_headers: serde_json::Value,
+    /// Query parameter carrying the access token, stripped before forwarding
+    pub token_query_param: Option<String>,
+    /// Cookie carrying the access token
+    pub token_cookie: Option<String>,
+    /// Accept the token as a `Sec-WebSocket-Protocol` entry after `bearer`
+    pub token_subprotocol: bool,
+    /// WebSocket/SSE connections are closed after this many seconds
+    pub max_connection_secs: Option<i32>,
+    /// WebSocket/SSE connections are closed when their token expires
+    pub close_at_token_expiry: bool,
+    /// Concurrent WebSocket/SSE connections allowed per user, API key or anonymous IP
+    pub max_connections_per_user: Option<i32>,
+    /// Name of the upstream pool serving the route instead of `upstream_address`
+    pub upstream_pool: Option<String>,
+    /// Upstream timeouts; `None` keeps the gateway default
+    pub connect_timeout_ms: Option<i32>,
+    pub read_timeout_ms: Option<i32>,
+    pub write_timeout_ms: Option<i32>,
+    /// How long an idle upstream connection stays in the keepalive pool
+    pub idle_timeout_ms: Option<i32>,
+    /// Retries of idempotent requests when connecting to the upstream fails
+    pub max_retries: i32,
+    /// Reuse upstream connections across requests
+    pub upstream_keepalive: bool,
+    /// Requests in flight to the upstream; `None` is unlimited
+    pub max_in_flight: Option<i32>,
+    /// Requests that may wait for a free slot
+    pub max_queue: i32,
+    pub queue_timeout_ms: i32,
+    /// Give free slots to the waiting user with the fewest requests in flight
+    pub fair_queue: bool,
+    /// Requests per user or API key per UTC day; `None` is unlimited
+    pub daily_quota: Option<i32>,
+    /// Requests per user or API key per UTC month; `None` is unlimited
+    pub monthly_quota: Option<i32>,
+    /// How `path_prefix` is matched: `prefix`, `exact`, `regex` or `glob`
+    pub match_type: String,
+    /// Higher priorities are matched first
+    pub priority: i32,
+    /// Allowed request methods; empty allows every method
+    pub methods: Vec<String>,
+    /// Upstream path template with `{1}` or `{name}` captures of the pattern
+    pub rewrite: Option<String>,
+}
+
+pub const AUTH_MODE_REQUIRED: &str = "required";
+pub const AUTH_MODE_OPTIONAL: &str = "optional";
+pub const AUTH_MODE_NONE: &str = "none";
+
+pub const MATCH_PREFIX: &str = "prefix";
+pub const MATCH_EXACT: &str = "exact";
+pub const MATCH_REGEX: &str = "regex";
+pub const MATCH_GLOB: &str = "glob";
+
+/// Identity headers of routes that do not configure them: the user id only.
+pub fn default_identity_headers() -> BTreeMap<String, String> {
+    BTreeMap::from([("sub".to_string(), "X-User-Id".to_string())])
+}
+
+/// Route fields accepted by the create and update route endpoints.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ProxyRouteInput {
+    #[serde(default)]
+    pub host: Option<String>,
+    pub path_prefix: String,
+    pub upstream_address: String,
+    #[serde(default)]
+    pub auth_mode: Option<String>,
+    /// Before `auth_mode`: `true` is `required`, `false` is `none`
+    #[serde(default)]
+    pub require_auth: Option<bool>,
+    pub strip_prefix: Option<String>,
+    #[serde(default)]
+    pub max_auth_age_secs: Option<i32>,
+    #[serde(default)]
+    pub require_mfa: bool,
+    #[serde(default)]
+    pub required_roles: Vec<String>,
+    #[serde(default = "default_identity_headers")]
+    pub identity_headers: BTreeMap<String, String>,
+    #[serde(default)]
+    pub token_query_param: Option<String>,
+    #[serde(default)]
+    pub token_cookie: Option<String>,
+    #[serde(default)]
+    pub token_subprotocol: bool,
+    #[serde(default)]
+    pub max_connection_secs: Option<i32>,
+    #[serde(default)]
+    pub close_at_token_expiry: bool,
+    #[serde(default)]
+    pub max_connections_per_user: Option<i32>,
+    #[serde(default)]
+    pub upstream_pool: Option<String>,
+    #[serde(default)]
+    pub connect_timeout_ms: Option<i32>,
+    #[serde(default)]
+    pub read_timeout_ms: Option<i32>,
+    #[serde(default)]
+    pub write_timeout_ms: Option<i32>,
+    #[serde(default)]
+    pub idle_timeout_ms: Option<i32>,
+    #[serde(default)]
+    pub max_retries: i32,
+    #[serde(default = "default_upstream_keepalive")]
+    pub upstream_keepalive: bool,
+    #[serde(default)]
+    pub max_in_flight: Option<i32>,
+    #[serde(default)]
+    pub max_queue: i32,
+    #[serde(default = "default_queue_timeout_ms")]
+    pub queue_timeout_ms: i32,
+    #[serde(default)]
+    pub fair_queue: bool,
+    #[serde(default)]
+    pub daily_quota: Option<i32>,
+    #[serde(default)]
+    pub monthly_quota: Option<i32>,
+    #[serde(default = "default_match_type")]
+    pub match_type: String,
+    #[serde(default)]
+    pub priority: i32,
+    #[serde(default)]
+    pub methods: Vec<String>,
+    #[serde(default)]
+    pub rewrite: Option<String>,
+}
+
+fn default_match_type() -> String {
+    MATCH_PREFIX.to_string()
+}
+
+fn default_upstream_keepalive() -> bool {
+    true
+}
+
+fn default_queue_timeout_ms() -> i32 {
+    5000
+}
+
+impl ProxyRouteInput {
+    /// `auth_mode`, falling back to the legacy `require_auth` flag.
+    pub fn auth_mode(&self) -> &str {
+        match (&self.auth_mode, self.require_auth) {
+            (Some(mode), _) => mode,
+            (None, Some(false)) => AUTH_MODE_NONE,
+            (None, _) => AUTH_MODE_REQUIRED,
+        }
+    }
+}
+
+pub const LB_ROUND_ROBIN: &str = "round_robin";
+pub const LB_LEAST_CONNECTIONS: &str = "least_connections";
+pub const LB_CONSISTENT_HASH: &str = "consistent_hash";
+
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
+pub struct UpstreamPool {
+    pub id: Uuid,
+    pub name: String,
+    /// `round_robin`, `least_connections` or `consistent_hash`
+    pub strategy: String,
+    /// `[{"address": "host:port", "weight": 1}]`
+    pub targets: serde_json::Value,
+    /// Path probed on every target; `None` disables health checks
+    pub health_check_path: Option<String>,
+    pub health_check_interval_secs: i32,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UpstreamTargetInput {
+    pub address: String,
+    #[serde(default = "default_target_weight")]
+    pub weight: i32,
+}
+
+fn default_target_weight() -> i32 {
+    1
+}
+
+fn default_lb_strategy() -> String {
+    LB_ROUND_ROBIN.to_string()
+}
+
+fn default_health_check_path() -> Option<String> {
+    Some("/health".to_string())
+}
+
+fn default_health_check_interval_secs() -> i32 {
+    10
+}
+
+/// Pool fields accepted by the create and update pool endpoints.
+#[derive(Debug, Clone, Deserialize)]
+pub struct UpstreamPoolInput {
+    pub name: String,
+    #[serde(default = "default_lb_strategy")]
+    pub strategy: String,
+    pub targets: Vec<UpstreamTargetInput>,
+    #[serde(default = "default_health_check_path")]
+    pub health_check_path: Option<String>,
+    #[serde(default = "default_health_check_interval_secs")]
+    pub health_check_interval_secs: i32,
+}
+
+/// Usage of one route on one UTC day.
+#[derive(Debug, Clone, FromRow, Serialize)]
+pub struct UsageDay {
+    pub route: String,
+    pub day: chrono::NaiveDate,
+    pub requests: i64,
+    pub response_bytes: i64,
+    pub duration_ms: i64,
 }
 
 #[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
@@ -155,6 +456,71 @@ pub struct SmtpConfig {
 #[derive(Debug, Clone, Serialize)]
 pub struct JwtSecretInfo {
     pub updated_at: DateTime<Utc>,
+    pub keys: Vec<JwtKeyInfo>,
+}
+
+pub const JWT_KEY_ACTIVE: &str = "active";
+pub const JWT_KEY_VERIFYING: &str = "verifying";
+pub const JWT_KEY_RETIRED: &str = "retired";
+
+/// Key id assumed for tokens issued before signing keys carried a `kid`.
+pub const JWT_LEGACY_KID: &str = "legacy";
+
+#[derive(Debug, Clone, FromRow)]
+#[allow(dead_code)]
+pub struct JwtKey {
+    pub id: Uuid,
+    pub kid: String,
+    pub secret: String,
+    pub status: String,
+    /// Access token signing algorithm: HS256 uses `secret`, EdDSA/RS256 use
+    /// `private_key` and publish `public_jwk` via JWKS.
+    pub algorithm: String,
+    pub private_key: Option<String>,
+    pub public_jwk: Option<serde_json::Value>,
+    pub created_at: DateTime<Utc>,
+    pub rotated_at: Option<DateTime<Utc>>,
+    /// When a `verifying` key is retired, set when it is rotated out.
+    pub verify_until: Option<DateTime<Utc>>,
+    pub retired_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct JwtKeyInfo {
+    pub kid: String,
+    pub status: String,
+    pub algorithm: String,
+    pub created_at: DateTime<Utc>,
+    pub rotated_at: Option<DateTime<Utc>>,
+    pub verify_until: Option<DateTime<Utc>>,
+}
+
+impl From<&JwtKey> for JwtKeyInfo {
+    fn from(key: &JwtKey) -> Self {
+        Self {
+            kid: key.kid.clone(),
+            status: key.status.clone(),
+            algorithm: key.algorithm.clone(),
+            created_at: key.created_at,
+            rotated_at: key.rotated_at,
+            verify_until: key.verify_until,
+        }
+    }
+}
+
+/// Key the gateway signs `X-Arc-Identity` assertions with for one upstream.
+#[derive(Debug, Clone, FromRow, Serialize)]
+pub struct IdentityKey {
+    pub id: Uuid,
+    pub upstream_address: String,
+    pub kid: String,
+    #[serde(skip_serializing)]
+    pub secret: String,
+    pub created_at: DateTime<Utc>,
+    /// Key waiting to be promoted after a rotation
+    pub next_kid: Option<String>,
+    #[serde(skip_serializing)]
+    pub next_secret: Option<String>,
 }
 
 #[derive(Debug, Clone, FromRow, Serialize)]
@@ -106,7 +106,7 @@ impl AdminService {
     pub async fn generate_admin_jwt(&self, admin: &Admin) -> Result<String> {
         use jsonwebtoken::{encode, EncodingKey, Header};
 
-        let jwt_secret = self.system_config.get_jwt_secret().await?;
+        let key = self.system_config.get_signing_key().await?;
 
         const ADMIN_TOKEN_TTL_SECS: i64 = 86400;
         let now = chrono::Utc::now().timestamp();
@@ -121,10 +121,14 @@ impl AdminService {
             jti: Uuid::new_v4(),
         };
 
+        let header = Header {
+            kid: Some(key.kid.clone()),
+            ..Header::default()
+        };
         let token = encode(
-            &Header::default(),
+            &header,
             &claims,
-            &EncodingKey::from_secret(jwt_secret.as_bytes()),
+            &EncodingKey::from_secret(key.secret.as_bytes()),
         )
         .map_err(|_| AppError::Internal(anyhow::anyhow!("JWT encoding failed")))?;
 
@@ -132,13 +136,18 @@ impl AdminService {
     }
 
     pub async fn validate_admin_jwt(&self, token: &str) -> Result<AdminTokenClaims> {
-        use jsonwebtoken::{decode, DecodingKey, Validation};
+        use jsonwebtoken::{decode, decode_header, DecodingKey, Validation};
 
-        let jwt_secret = self.system_config.get_jwt_secret().await?;
+        let header = decode_header(token).map_err(|_| AppError::InvalidToken)?;
+        let key = self
+            .system_config
+            .find_verification_key(header.kid.as_deref())
+            .await?
+            .ok_or(AppError::InvalidToken)?;
 
         let token_data = decode::<AdminTokenClaims>(
             token,
-            &DecodingKey::from_secret(jwt_secret.as_bytes()),
+            &DecodingKey::from_secret(key.secret.as_bytes()),
             &Validation::default(),
         )
         .map_err(|_| AppError::InvalidToken)?;
@@ -78,6 +78,15 @@ impl ApiKeyService {
         Ok(key)
     }
 
+    /// Hashes of every key, for the gateway's key cache.
+    pub async fn list_key_hashes(&self) -> Result<Vec<String>> {
+        let hashes: Vec<(String,)> = sqlx::query_as("SELECT key_hash FROM api_keys")
+            .fetch_all(self.db_pool.as_ref())
+            .await?;
+
+        Ok(hashes.into_iter().map(|(hash,)| hash).collect())
+    }
+
     pub async fn delete(&self, id: Uuid, admin_id: Uuid) -> Result<bool> {
         let result = sqlx::query("DELETE FROM api_keys WHERE id = $1 AND admin_id = $2")
             .bind(id)
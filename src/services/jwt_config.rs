@@ -0,0 +1,105 @@
+use sqlx::PgPool;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+use crate::config::JwtConfig;
+use crate::error::Result;
+use crate::models::JwtConfigRow;
+
+pub struct JwtConfigService {
+    pool: Arc<PgPool>,
+    cache: Arc<RwLock<Option<JwtConfigRow>>>,
+}
+
+impl JwtConfigService {
+    pub fn new(pool: Arc<PgPool>) -> Self {
+        Self {
+            pool,
+            cache: Arc::new(RwLock::new(None)),
+        }
+    }
+
+    /// Seeds `jwt_config` from the static config the first time it runs.
+    /// Once seeded (or edited via the dashboard) the table is authoritative.
+    pub async fn initialize(&self, seed: &JwtConfig) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO jwt_config (id, access_token_ttl_secs, refresh_token_ttl_secs, 
+                auto_refresh_threshold_secs, seeded)
+             VALUES (1, $1, $2, $3, TRUE)
+             ON CONFLICT (id) DO UPDATE SET 
+                access_token_ttl_secs = EXCLUDED.access_token_ttl_secs,
+                refresh_token_ttl_secs = EXCLUDED.refresh_token_ttl_secs,
+                auto_refresh_threshold_secs = EXCLUDED.auto_refresh_threshold_secs,
+                seeded = TRUE, updated_at = NOW()
+             WHERE jwt_config.seeded = FALSE",
+        )
+        .bind(seed.access_token_ttl as i32)
+        .bind(seed.refresh_token_ttl as i32)
+        .bind(seed.auto_refresh_threshold as i32)
+        .execute(self.pool.as_ref())
+        .await?;
+
+        self.reload_cache().await?;
+        Ok(())
+    }
+
+    async fn reload_cache(&self) -> Result<()> {
+        let config = sqlx::query_as::<_, JwtConfigRow>("SELECT * FROM jwt_config WHERE id = 1")
+            .fetch_one(self.pool.as_ref())
+            .await?;
+
+        let mut cache = self.cache.write().await;
+        *cache = Some(config);
+        Ok(())
+    }
+
+    pub async fn invalidate_cache(&self) {
+        let mut cache = self.cache.write().await;
+        *cache = None;
+    }
+
+    pub async fn get_config(&self) -> Result<JwtConfigRow> {
+        {
+            let cache = self.cache.read().await;
+            if let Some(ref config) = *cache {
+                return Ok(config.clone());
+            }
+        }
+        self.reload_cache().await?;
+        let cache = self.cache.read().await;
+        cache.as_ref().cloned().ok_or_else(|| {
+            crate::error::AppError::Internal(anyhow::anyhow!("Failed to load JWT config"))
+        })
+    }
+
+    /// How long a rotated-out signing key keeps verifying: long enough for
+    /// every token it signed to expire on its own.
+    pub async fn key_grace_period(&self) -> Result<chrono::Duration> {
+        let config = self.get_config().await?;
+        let secs = config
+            .access_token_ttl_secs
+            .max(config.refresh_token_ttl_secs);
+        Ok(chrono::Duration::seconds(secs as i64))
+    }
+
+    pub async fn update_config(
+        &self,
+        access_token_ttl_secs: i32,
+        refresh_token_ttl_secs: i32,
+        auto_refresh_threshold_secs: i32,
+    ) -> Result<JwtConfigRow> {
+        sqlx::query(
+            "UPDATE jwt_config SET access_token_ttl_secs = $1, refresh_token_ttl_secs = $2, 
+             auto_refresh_threshold_secs = $3, seeded = TRUE, updated_at = NOW() 
+             WHERE id = 1",
+        )
+        .bind(access_token_ttl_secs)
+        .bind(refresh_token_ttl_secs)
+        .bind(auto_refresh_threshold_secs)
+        .execute(self.pool.as_ref())
+        .await?;
+
+        self.invalidate_cache().await;
+        self.get_config().await
+    }
+}
@@ -0,0 +1,491 @@
+use std::sync::Arc;
+
+use chrono::{Duration, Utc};
+use hmac::{Hmac, Mac};
+use rand::Rng;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::error::{AppError, Result};
+use crate::models::{AMR_MFA, AMR_OTP, AMR_PASSWORD};
+
+type HmacSha1 = Hmac<Sha1>;
+
+const TOTP_ISSUER: &str = "ARC Auth";
+const TOTP_SECRET_BYTES: usize = 20;
+const TOTP_DIGITS: u32 = 6;
+const TOTP_PERIOD_SECS: u64 = 30;
+/// Accepted clock drift, in time steps on either side of now
+const TOTP_SKEW_STEPS: u64 = 1;
+
+const RECOVERY_CODE_COUNT: usize = 10;
+const RECOVERY_CODE_ALPHABET: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+
+const CHALLENGE_TTL_MINUTES: i64 = 5;
+const MAX_CHALLENGE_ATTEMPTS: i32 = 5;
+
+const BASE32_ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+fn base32_encode(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len().div_ceil(5) * 8);
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for &byte in data {
+        buffer = (buffer << 8) | byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            out.push(BASE32_ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        out.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+    out
+}
+
+fn base32_decode(input: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(input.len() * 5 / 8);
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for c in input.trim_end_matches('=').bytes() {
+        let value = BASE32_ALPHABET
+            .iter()
+            .position(|&a| a == c.to_ascii_uppercase())? as u32;
+        buffer = (buffer << 5) | value;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buffer >> bits) as u8);
+        }
+    }
+    Some(out)
+}
+
+/// RFC 4226 HOTP value for `counter`, truncated to `TOTP_DIGITS` digits.
+fn hotp(secret: &[u8], counter: u64) -> u32 {
+    let mut mac = HmacSha1::new_from_slice(secret).unwrap_or_else(|_| {
+        tracing::error!("HMAC initialization failed - this should never happen");
+        panic!("HMAC initialization failed with valid key")
+    });
+    mac.update(&counter.to_be_bytes());
+    let digest = mac.finalize().into_bytes();
+
+    let offset = (digest[19] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([
+        digest[offset] & 0x7f,
+        digest[offset + 1],
+        digest[offset + 2],
+        digest[offset + 3],
+    ]);
+    binary % 10u32.pow(TOTP_DIGITS)
+}
+
+/// Time step a valid `code` belongs to, checking the steps around `now`.
+fn match_totp(secret: &[u8], code: &str, now: u64) -> Option<u64> {
+    if code.len() != TOTP_DIGITS as usize || !code.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let code: u32 = code.parse().ok()?;
+    let current = now / TOTP_PERIOD_SECS;
+    (current.saturating_sub(TOTP_SKEW_STEPS)..=current + TOTP_SKEW_STEPS)
+        .find(|&step| hotp(secret, step) == code)
+}
+
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+fn otpauth_uri(account: &str, secret: &str) -> String {
+    let issuer = percent_encode(TOTP_ISSUER);
+    format!(
+        "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        issuer,
+        percent_encode(account),
+        secret,
+        issuer,
+        TOTP_DIGITS,
+        TOTP_PERIOD_SECS
+    )
+}
+
+fn generate_recovery_code() -> String {
+    let mut rng = rand::thread_rng();
+    let mut code: String = (0..10)
+        .map(|_| RECOVERY_CODE_ALPHABET[rng.gen_range(0..RECOVERY_CODE_ALPHABET.len())] as char)
+        .collect();
+    code.insert(5, '-');
+    code
+}
+
+fn hash_recovery_code(code: &str) -> String {
+    let normalized: String = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    hex::encode(Sha256::digest(normalized.as_bytes()))
+}
+
+fn now_secs() -> u64 {
+    Utc::now().timestamp().max(0) as u64
+}
+
+/// Second factor a code was accepted as.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SecondFactor {
+    Totp,
+    RecoveryCode,
+}
+
+impl SecondFactor {
+    /// `amr` of a login completed with this factor.
+    fn amr(self) -> Vec<String> {
+        let methods: &[&str] = match self {
+            Self::Totp => &[AMR_PASSWORD, AMR_OTP, AMR_MFA],
+            Self::RecoveryCode => &[AMR_PASSWORD, AMR_MFA],
+        };
+        methods.iter().map(|m| m.to_string()).collect()
+    }
+}
+
+/// Secret and provisioning URI shown to the user when enrolling.
+pub struct TotpEnrollment {
+    pub secret: String,
+    pub otpauth_uri: String,
+}
+
+pub struct MfaService {
+    db_pool: Arc<PgPool>,
+}
+
+impl MfaService {
+    pub fn new(db_pool: Arc<PgPool>) -> Self {
+        Self { db_pool }
+    }
+
+    pub async fn is_enabled(&self, user_id: Uuid) -> Result<bool> {
+        let row: Option<(bool,)> =
+            sqlx::query_as("SELECT enabled FROM user_totp WHERE user_id = $1")
+                .bind(user_id)
+                .fetch_optional(self.db_pool.as_ref())
+                .await?;
+        Ok(row.is_some_and(|(enabled,)| enabled))
+    }
+
+    /// Starts (or restarts) enrollment with a fresh secret. 2FA stays off
+    /// until `confirm_enrollment` sees a valid code.
+    pub async fn begin_enrollment(&self, user_id: Uuid, email: &str) -> Result<TotpEnrollment> {
+        if self.is_enabled(user_id).await? {
+            return Err(AppError::InvalidRequest(
+                "Two-factor authentication is already enabled".into(),
+            ));
+        }
+
+        let mut secret = [0u8; TOTP_SECRET_BYTES];
+        rand::RngCore::fill_bytes(&mut rand::thread_rng(), &mut secret);
+        let secret = base32_encode(&secret);
+
+        sqlx::query(
+            "INSERT INTO user_totp (user_id, secret) VALUES ($1, $2)
+             ON CONFLICT (user_id) DO UPDATE
+             SET secret = EXCLUDED.secret, last_used_step = NULL, created_at = NOW()",
+        )
+        .bind(user_id)
+        .bind(&secret)
+        .execute(self.db_pool.as_ref())
+        .await?;
+
+        Ok(TotpEnrollment {
+            otpauth_uri: otpauth_uri(email, &secret),
+            secret,
+        })
+    }
+
+    /// Enables 2FA once the authenticator produces a valid code and returns
+    /// freshly generated recovery codes, shown to the user only once.
+    pub async fn confirm_enrollment(&self, user_id: Uuid, code: &str) -> Result<Vec<String>> {
+        let row: Option<(bool,)> =
+            sqlx::query_as("SELECT enabled FROM user_totp WHERE user_id = $1")
+                .bind(user_id)
+                .fetch_optional(self.db_pool.as_ref())
+                .await?;
+        match row {
+            Some((false,)) => {}
+            Some((true,)) => {
+                return Err(AppError::InvalidRequest(
+                    "Two-factor authentication is already enabled".into(),
+                ))
+            }
+            None => {
+                return Err(AppError::InvalidRequest(
+                    "Two-factor enrollment has not been started".into(),
+                ))
+            }
+        }
+
+        if !self.verify_totp(user_id, code).await? {
+            return Err(AppError::InvalidCode);
+        }
+
+        sqlx::query("UPDATE user_totp SET enabled = TRUE, confirmed_at = NOW() WHERE user_id = $1")
+            .bind(user_id)
+            .execute(self.db_pool.as_ref())
+            .await?;
+
+        tracing::info!(user_id = %user_id, "Two-factor authentication enabled");
+        self.regenerate_recovery_codes(user_id).await
+    }
+
+    /// Turns 2FA off after checking a current TOTP or recovery code.
+    pub async fn disable(&self, user_id: Uuid, code: &str) -> Result<()> {
+        if !self.is_enabled(user_id).await? {
+            return Err(AppError::InvalidRequest(
+                "Two-factor authentication is not enabled".into(),
+            ));
+        }
+        if self.verify_second_factor(user_id, code).await?.is_none() {
+            return Err(AppError::InvalidCode);
+        }
+
+        sqlx::query("DELETE FROM user_totp WHERE user_id = $1")
+            .bind(user_id)
+            .execute(self.db_pool.as_ref())
+            .await?;
+        sqlx::query("DELETE FROM mfa_recovery_codes WHERE user_id = $1")
+            .bind(user_id)
+            .execute(self.db_pool.as_ref())
+            .await?;
+
+        tracing::info!(user_id = %user_id, "Two-factor authentication disabled");
+        Ok(())
+    }
+
+    /// Replaces all recovery codes of a user with a new set.
+    pub async fn regenerate_recovery_codes(&self, user_id: Uuid) -> Result<Vec<String>> {
+        let codes: Vec<String> = (0..RECOVERY_CODE_COUNT)
+            .map(|_| generate_recovery_code())
+            .collect();
+        let hashes: Vec<String> = codes.iter().map(|c| hash_recovery_code(c)).collect();
+
+        let mut tx = self.db_pool.begin().await?;
+        sqlx::query("DELETE FROM mfa_recovery_codes WHERE user_id = $1")
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(
+            "INSERT INTO mfa_recovery_codes (user_id, code_hash)
+             SELECT $1, UNNEST($2::VARCHAR[])",
+        )
+        .bind(user_id)
+        .bind(&hashes)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(codes)
+    }
+
+    /// Regenerates recovery codes after checking a current second factor.
+    pub async fn reset_recovery_codes(&self, user_id: Uuid, code: &str) -> Result<Vec<String>> {
+        if !self.is_enabled(user_id).await? {
+            return Err(AppError::InvalidRequest(
+                "Two-factor authentication is not enabled".into(),
+            ));
+        }
+        if self.verify_second_factor(user_id, code).await?.is_none() {
+            return Err(AppError::InvalidCode);
+        }
+        self.regenerate_recovery_codes(user_id).await
+    }
+
+    /// Short-lived challenge handed out instead of tokens after the SRP proof.
+    pub async fn create_challenge(&self, user_id: Uuid) -> Result<Uuid> {
+        let expires_at = Utc::now() + Duration::minutes(CHALLENGE_TTL_MINUTES);
+        let (id,): (Uuid,) = sqlx::query_as(
+            "INSERT INTO mfa_challenges (user_id, expires_at) VALUES ($1, $2) RETURNING id",
+        )
+        .bind(user_id)
+        .bind(expires_at)
+        .fetch_one(self.db_pool.as_ref())
+        .await?;
+        Ok(id)
+    }
+
+    /// Completes a login challenge with a TOTP or recovery code. Returns the
+    /// user and the `amr` of the login. The challenge is single use and
+    /// dropped after too many wrong codes.
+    pub async fn verify_challenge(
+        &self,
+        challenge_id: Uuid,
+        code: &str,
+    ) -> Result<(Uuid, Vec<String>)> {
+        let row: Option<(Uuid, i32)> = sqlx::query_as(
+            "UPDATE mfa_challenges SET attempts = attempts + 1
+             WHERE id = $1 AND expires_at > NOW()
+             RETURNING user_id, attempts",
+        )
+        .bind(challenge_id)
+        .fetch_optional(self.db_pool.as_ref())
+        .await?;
+
+        let (user_id, attempts) = row.ok_or(AppError::InvalidToken)?;
+        if attempts > MAX_CHALLENGE_ATTEMPTS {
+            self.delete_challenge(challenge_id).await?;
+            tracing::warn!(user_id = %user_id, "MFA challenge exhausted (max attempts reached)");
+            return Err(AppError::InvalidToken);
+        }
+
+        let factor = self
+            .verify_second_factor(user_id, code)
+            .await?
+            .ok_or(AppError::InvalidCode)?;
+
+        self.delete_challenge(challenge_id).await?;
+        Ok((user_id, factor.amr()))
+    }
+
+    async fn delete_challenge(&self, challenge_id: Uuid) -> Result<()> {
+        sqlx::query("DELETE FROM mfa_challenges WHERE id = $1")
+            .bind(challenge_id)
+            .execute(self.db_pool.as_ref())
+            .await?;
+        Ok(())
+    }
+
+    /// Checks a TOTP code, falling back to a recovery code.
+    async fn verify_second_factor(
+        &self,
+        user_id: Uuid,
+        code: &str,
+    ) -> Result<Option<SecondFactor>> {
+        let code = code.trim();
+        if self.verify_totp(user_id, code).await? {
+            return Ok(Some(SecondFactor::Totp));
+        }
+        if self.use_recovery_code(user_id, code).await? {
+            tracing::info!(user_id = %user_id, "Recovery code used");
+            return Ok(Some(SecondFactor::RecoveryCode));
+        }
+        Ok(None)
+    }
+
+    /// Accepts a code only for a time step newer than the last accepted one.
+    async fn verify_totp(&self, user_id: Uuid, code: &str) -> Result<bool> {
+        let row: Option<(String,)> =
+            sqlx::query_as("SELECT secret FROM user_totp WHERE user_id = $1")
+                .bind(user_id)
+                .fetch_optional(self.db_pool.as_ref())
+                .await?;
+        let secret = match row.and_then(|(s,)| base32_decode(&s)) {
+            Some(s) => s,
+            None => return Ok(false),
+        };
+
+        let step = match match_totp(&secret, code, now_secs()) {
+            Some(step) => step,
+            None => return Ok(false),
+        };
+
+        let result = sqlx::query(
+            "UPDATE user_totp SET last_used_step = $2
+             WHERE user_id = $1 AND (last_used_step IS NULL OR last_used_step < $2)",
+        )
+        .bind(user_id)
+        .bind(step as i64)
+        .execute(self.db_pool.as_ref())
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn use_recovery_code(&self, user_id: Uuid, code: &str) -> Result<bool> {
+        let result = sqlx::query(
+            "UPDATE mfa_recovery_codes SET used_at = NOW()
+             WHERE user_id = $1 AND code_hash = $2 AND used_at IS NULL",
+        )
+        .bind(user_id)
+        .bind(hash_recovery_code(code))
+        .execute(self.db_pool.as_ref())
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RFC_SECRET: &[u8] = b"12345678901234567890";
+
+    #[test]
+    fn test_base32_roundtrip() {
+        assert_eq!(base32_encode(b"foobar"), "MZXW6YTBOI");
+        assert_eq!(base32_decode("MZXW6YTBOI").unwrap(), b"foobar");
+        assert_eq!(base32_decode("mzxw6ytboi======").unwrap(), b"foobar");
+        assert!(base32_decode("MZXW1").is_none());
+    }
+
+    #[test]
+    fn test_hotp_rfc4226_vectors() {
+        let expected = [755224, 287082, 359152, 969429, 338314];
+        for (counter, code) in expected.iter().enumerate() {
+            assert_eq!(hotp(RFC_SECRET, counter as u64), *code);
+        }
+    }
+
+    #[test]
+    fn test_totp_rfc6238_vector() {
+        // T = 59s -> step 1, 8-digit value 94287082
+        assert_eq!(match_totp(RFC_SECRET, "287082", 59), Some(1));
+    }
+
+    #[test]
+    fn test_totp_skew_window() {
+        let now = 1_111_111_109;
+        let step = now / TOTP_PERIOD_SECS;
+        let previous = format!("{:06}", hotp(RFC_SECRET, step - 1));
+        let stale = format!("{:06}", hotp(RFC_SECRET, step - 2));
+
+        assert_eq!(match_totp(RFC_SECRET, &previous, now), Some(step - 1));
+        assert_eq!(match_totp(RFC_SECRET, &stale, now), None);
+        assert_eq!(match_totp(RFC_SECRET, "12345", now), None);
+        assert_eq!(match_totp(RFC_SECRET, "abcdef", now), None);
+    }
+
+    #[test]
+    fn test_otpauth_uri_encodes_account() {
+        let uri = otpauth_uri("a+b@example.com", "JBSWY3DPEHPK3PXP");
+        assert_eq!(
+            uri,
+            "otpauth://totp/ARC%20Auth:a%2Bb%40example.com?secret=JBSWY3DPEHPK3PXP\
+             &issuer=ARC%20Auth&algorithm=SHA1&digits=6&period=30"
+        );
+    }
+
+    #[test]
+    fn test_second_factor_amr() {
+        assert_eq!(SecondFactor::Totp.amr(), vec!["pwd", "otp", "mfa"]);
+        assert_eq!(SecondFactor::RecoveryCode.amr(), vec!["pwd", "mfa"]);
+    }
+
+    #[test]
+    fn test_recovery_code_format_and_hash() {
+        let code = generate_recovery_code();
+        assert_eq!(code.len(), 11);
+        assert_eq!(code.as_bytes()[5], b'-');
+        assert_eq!(
+            hash_recovery_code(&code),
+            hash_recovery_code(&code.to_uppercase().replace('-', " "))
+        );
+    }
+}
@@ -2,18 +2,25 @@ pub mod admin;
 pub mod api_key;
 pub mod captcha;
 pub mod email;
+pub mod jwt_config;
+pub mod mfa;
 pub mod proxy_config;
+pub mod signing_key;
 pub mod srp;
 pub mod system_config;
 pub mod token;
+pub mod usage;
 pub mod user;
 
 pub use admin::AdminService;
 pub use api_key::ApiKeyService;
 pub use captcha::CaptchaService;
 pub use email::EmailService;
+pub use jwt_config::JwtConfigService;
+pub use mfa::MfaService;
 pub use proxy_config::ProxyConfigService;
 pub use srp::SrpService;
 pub use system_config::SystemConfigService;
 pub use token::TokenService;
+pub use usage::UsageService;
 pub use user::UserService;
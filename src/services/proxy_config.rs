@@ -1,9 +1,10 @@
+use sqlx::types::Json;
 use sqlx::PgPool;
 use std::sync::Arc;
 use uuid::Uuid;
 
 use crate::error::Result;
-use crate::models::{JwtConfigRow, ProxyRoute, RateLimitRule};
+use crate::models::{ProxyRoute, ProxyRouteInput, RateLimitRule, UpstreamPool, UpstreamPoolInput};
 
 pub struct ProxyConfigService {
     pool: Arc<PgPool>,
@@ -15,28 +16,61 @@ impl ProxyConfigService {
     }
 
     pub async fn list_routes(&self) -> Result<Vec<ProxyRoute>> {
-        let routes =
-            sqlx::query_as::<_, ProxyRoute>("SELECT * FROM proxy_routes ORDER BY path_prefix")
-                .fetch_all(self.pool.as_ref())
-                .await?;
+        let routes = sqlx::query_as::<_, ProxyRoute>(
+            "SELECT * FROM proxy_routes ORDER BY priority DESC, path_prefix",
+        )
+        .fetch_all(self.pool.as_ref())
+        .await?;
         Ok(routes)
     }
 
-    pub async fn create_route(
-        &self,
-        path_prefix: &str,
-        upstream_address: &str,
-        require_auth: bool,
-        strip_prefix: Option<&str>,
-    ) -> Result<ProxyRoute> {
+    pub async fn create_route(&self, input: &ProxyRouteInput) -> Result<ProxyRoute> {
         let route = sqlx::query_as::<_, ProxyRoute>(
-            "INSERT INTO proxy_routes (path_prefix, upstream_address, require_auth, strip_prefix) 
-             VALUES ($1, $2, $3, $4) RETURNING *",
+            "INSERT INTO proxy_routes (path_prefix, upstream_address, auth_mode, strip_prefix, 
+             max_auth_age_secs, require_mfa, required_roles, identity_headers, 
+             token_query_param, token_cookie, token_subprotocol, max_connection_secs, 
+             close_at_token_expiry, max_connections_per_user, upstream_pool, 
+             connect_timeout_ms, read_timeout_ms, write_timeout_ms, idle_timeout_ms, 
+             max_retries, upstream_keepalive, max_in_flight, max_queue, queue_timeout_ms, 
+             fair_queue, daily_quota, monthly_quota, host, match_type, priority, methods, 
+             rewrite) 
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, 
+             $16, $17, $18, $19, $20, $21, $22, $23, $24, $25, $26, $27, $28, $29, $30, 
+             $31, $32) 
+             RETURNING *",
         )
-        .bind(path_prefix)
-        .bind(upstream_address)
-        .bind(require_auth)
-        .bind(strip_prefix)
+        .bind(&input.path_prefix)
+        .bind(&input.upstream_address)
+        .bind(input.auth_mode())
+        .bind(input.strip_prefix.as_deref())
+        .bind(input.max_auth_age_secs)
+        .bind(input.require_mfa)
+        .bind(&input.required_roles)
+        .bind(Json(&input.identity_headers))
+        .bind(input.token_query_param.as_deref())
+        .bind(input.token_cookie.as_deref())
+        .bind(input.token_subprotocol)
+        .bind(input.max_connection_secs)
+        .bind(input.close_at_token_expiry)
+        .bind(input.max_connections_per_user)
+        .bind(input.upstream_pool.as_deref())
+        .bind(input.connect_timeout_ms)
+        .bind(input.read_timeout_ms)
+        .bind(input.write_timeout_ms)
+        .bind(input.idle_timeout_ms)
+        .bind(input.max_retries)
+        .bind(input.upstream_keepalive)
+        .bind(input.max_in_flight)
+        .bind(input.max_queue)
+        .bind(input.queue_timeout_ms)
+        .bind(input.fair_queue)
+        .bind(input.daily_quota)
+        .bind(input.monthly_quota)
+        .bind(input.host.as_deref())
+        .bind(&input.match_type)
+        .bind(input.priority)
+        .bind(&input.methods)
+        .bind(input.rewrite.as_deref())
         .fetch_one(self.pool.as_ref())
         .await?;
         Ok(route)
@@ -45,23 +79,57 @@ impl ProxyConfigService {
     pub async fn update_route(
         &self,
         id: Uuid,
-        path_prefix: &str,
-        upstream_address: &str,
-        require_auth: bool,
-        strip_prefix: Option<&str>,
+        input: &ProxyRouteInput,
         enabled: bool,
     ) -> Result<ProxyRoute> {
         let route = sqlx::query_as::<_, ProxyRoute>(
             "UPDATE proxy_routes SET path_prefix = $2, upstream_address = $3, 
-             require_auth = $4, strip_prefix = $5, enabled = $6, updated_at = NOW() 
+             auth_mode = $4, strip_prefix = $5, enabled = $6, max_auth_age_secs = $7, 
+             require_mfa = $8, required_roles = $9, 
+             identity_headers = $10, token_query_param = $11, token_cookie = $12, 
+             token_subprotocol = $13, max_connection_secs = $14, close_at_token_expiry = $15, 
+             max_connections_per_user = $16, upstream_pool = $17, connect_timeout_ms = $18, 
+             read_timeout_ms = $19, write_timeout_ms = $20, idle_timeout_ms = $21, 
+             max_retries = $22, upstream_keepalive = $23, max_in_flight = $24, 
+             max_queue = $25, queue_timeout_ms = $26, fair_queue = $27, daily_quota = $28, 
+             monthly_quota = $29, host = $30, match_type = $31, priority = $32, 
+             methods = $33, rewrite = $34, updated_at = NOW() 
              WHERE id = $1 RETURNING *",
         )
         .bind(id)
-        .bind(path_prefix)
-        .bind(upstream_address)
-        .bind(require_auth)
-        .bind(strip_prefix)
+        .bind(&input.path_prefix)
+        .bind(&input.upstream_address)
+        .bind(input.auth_mode())
+        .bind(input.strip_prefix.as_deref())
         .bind(enabled)
+        .bind(input.max_auth_age_secs)
+        .bind(input.require_mfa)
+        .bind(&input.required_roles)
+        .bind(Json(&input.identity_headers))
+        .bind(input.token_query_param.as_deref())
+        .bind(input.token_cookie.as_deref())
+        .bind(input.token_subprotocol)
+        .bind(input.max_connection_secs)
+        .bind(input.close_at_token_expiry)
+        .bind(input.max_connections_per_user)
+        .bind(input.upstream_pool.as_deref())
+        .bind(input.connect_timeout_ms)
+        .bind(input.read_timeout_ms)
+        .bind(input.write_timeout_ms)
+        .bind(input.idle_timeout_ms)
+        .bind(input.max_retries)
+        .bind(input.upstream_keepalive)
+        .bind(input.max_in_flight)
+        .bind(input.max_queue)
+        .bind(input.queue_timeout_ms)
+        .bind(input.fair_queue)
+        .bind(input.daily_quota)
+        .bind(input.monthly_quota)
+        .bind(input.host.as_deref())
+        .bind(&input.match_type)
+        .bind(input.priority)
+        .bind(&input.methods)
+        .bind(input.rewrite.as_deref())
         .fetch_one(self.pool.as_ref())
         .await?;
         Ok(route)
@@ -75,6 +143,66 @@ impl ProxyConfigService {
         Ok(())
     }
 
+    pub async fn list_upstream_pools(&self) -> Result<Vec<UpstreamPool>> {
+        let pools = sqlx::query_as::<_, UpstreamPool>("SELECT * FROM upstream_pools ORDER BY name")
+            .fetch_all(self.pool.as_ref())
+            .await?;
+        Ok(pools)
+    }
+
+    pub async fn create_upstream_pool(&self, input: &UpstreamPoolInput) -> Result<UpstreamPool> {
+        let pool = sqlx::query_as::<_, UpstreamPool>(
+            "INSERT INTO upstream_pools (name, strategy, targets, health_check_path, 
+             health_check_interval_secs) 
+             VALUES ($1, $2, $3, $4, $5) RETURNING *",
+        )
+        .bind(&input.name)
+        .bind(&input.strategy)
+        .bind(Json(&input.targets))
+        .bind(input.health_check_path.as_deref())
+        .bind(input.health_check_interval_secs)
+        .fetch_one(self.pool.as_ref())
+        .await?;
+        Ok(pool)
+    }
+
+    pub async fn update_upstream_pool(
+        &self,
+        id: Uuid,
+        input: &UpstreamPoolInput,
+    ) -> Result<UpstreamPool> {
+        let pool = sqlx::query_as::<_, UpstreamPool>(
+            "UPDATE upstream_pools SET name = $2, strategy = $3, targets = $4, 
+             health_check_path = $5, health_check_interval_secs = $6, updated_at = NOW() 
+             WHERE id = $1 RETURNING *",
+        )
+        .bind(id)
+        .bind(&input.name)
+        .bind(&input.strategy)
+        .bind(Json(&input.targets))
+        .bind(input.health_check_path.as_deref())
+        .bind(input.health_check_interval_secs)
+        .fetch_one(self.pool.as_ref())
+        .await?;
+        Ok(pool)
+    }
+
+    pub async fn find_upstream_pool(&self, id: Uuid) -> Result<Option<UpstreamPool>> {
+        let pool = sqlx::query_as::<_, UpstreamPool>("SELECT * FROM upstream_pools WHERE id = $1")
+            .bind(id)
+            .fetch_optional(self.pool.as_ref())
+            .await?;
+        Ok(pool)
+    }
+
+    pub async fn delete_upstream_pool(&self, id: Uuid) -> Result<()> {
+        sqlx::query("DELETE FROM upstream_pools WHERE id = $1")
+            .bind(id)
+            .execute(self.pool.as_ref())
+            .await?;
+        Ok(())
+    }
+
     pub async fn list_rate_limits(&self) -> Result<Vec<RateLimitRule>> {
         let rules =
             sqlx::query_as::<_, RateLimitRule>("SELECT * FROM rate_limit_rules ORDER BY name")
@@ -139,30 +267,4 @@ impl ProxyConfigService {
             .await?;
         Ok(())
     }
-
-    pub async fn get_jwt_config(&self) -> Result<JwtConfigRow> {
-        let config = sqlx::query_as::<_, JwtConfigRow>("SELECT * FROM jwt_config WHERE id = 1")
-            .fetch_one(self.pool.as_ref())
-            .await?;
-        Ok(config)
-    }
-
-    pub async fn update_jwt_config(
-        &self,
-        access_token_ttl_secs: i32,
-        refresh_token_ttl_secs: i32,
-        auto_refresh_threshold_secs: i32,
-    ) -> Result<JwtConfigRow> {
-        let config = sqlx::query_as::<_, JwtConfigRow>(
-            "UPDATE jwt_config SET access_token_ttl_secs = $1, refresh_token_ttl_secs = $2, 
-             auto_refresh_threshold_secs = $3, updated_at = NOW() 
-             WHERE id = 1 RETURNING *",
-        )
-        .bind(access_token_ttl_secs)
-        .bind(refresh_token_ttl_secs)
-        .bind(auto_refresh_threshold_secs)
-        .fetch_one(self.pool.as_ref())
-        .await?;
-        Ok(config)
-    }
 }
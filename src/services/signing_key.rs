@@ -0,0 +1,234 @@
+use std::str::FromStr;
+
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine;
+use jsonwebtoken::jwk::{
+    AlgorithmParameters, CommonParameters, EllipticCurve, Jwk, KeyAlgorithm,
+    OctetKeyPairParameters, OctetKeyPairType, PublicKeyUse, RSAKeyParameters, RSAKeyType,
+};
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey};
+use ring::signature::{Ed25519KeyPair, KeyPair};
+use rsa::pkcs1::EncodeRsaPrivateKey;
+use rsa::traits::PublicKeyParts;
+use rsa::RsaPrivateKey;
+
+use crate::models::JwtKey;
+
+const RSA_KEY_BITS: usize = 2048;
+
+/// Algorithms access tokens can be signed with. Refresh and admin tokens
+/// are only ever read by this service and always use HS256.
+pub const SUPPORTED_ALGORITHMS: &[Algorithm] =
+    &[Algorithm::HS256, Algorithm::EdDSA, Algorithm::RS256];
+
+pub fn parse_algorithm(value: &str) -> Option<Algorithm> {
+    Algorithm::from_str(value)
+        .ok()
+        .filter(|alg| SUPPORTED_ALGORITHMS.contains(alg))
+}
+
+pub fn algorithm_name(alg: Algorithm) -> &'static str {
+    match alg {
+        Algorithm::EdDSA => "EdDSA",
+        Algorithm::RS256 => "RS256",
+        _ => "HS256",
+    }
+}
+
+/// Private key (base64 DER) and public JWK generated for a new signing key.
+pub struct GeneratedKeyPair {
+    pub private_key: String,
+    pub public_jwk: serde_json::Value,
+}
+
+/// Generates an asymmetric keypair for `alg`, or `None` for HS256 which
+/// only needs the shared secret.
+pub fn generate_keypair(alg: Algorithm, kid: &str) -> anyhow::Result<Option<GeneratedKeyPair>> {
+    let (private_der, params) = match alg {
+        Algorithm::EdDSA => {
+            let rng = ring::rand::SystemRandom::new();
+            let pkcs8 = Ed25519KeyPair::generate_pkcs8(&rng)
+                .map_err(|_| anyhow::anyhow!("Ed25519 key generation failed"))?;
+            let keypair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref())
+                .map_err(|e| anyhow::anyhow!("Invalid Ed25519 key: {}", e))?;
+            let params = AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                key_type: OctetKeyPairType::OctetKeyPair,
+                curve: EllipticCurve::Ed25519,
+                x: URL_SAFE_NO_PAD.encode(keypair.public_key().as_ref()),
+            });
+            (pkcs8.as_ref().to_vec(), params)
+        }
+        Algorithm::RS256 => {
+            let key = RsaPrivateKey::new(&mut rand::thread_rng(), RSA_KEY_BITS)?;
+            let der = key.to_pkcs1_der()?;
+            let params = AlgorithmParameters::RSA(RSAKeyParameters {
+                key_type: RSAKeyType::RSA,
+                n: URL_SAFE_NO_PAD.encode(key.n().to_bytes_be()),
+                e: URL_SAFE_NO_PAD.encode(key.e().to_bytes_be()),
+            });
+            (der.as_bytes().to_vec(), params)
+        }
+        _ => return Ok(None),
+    };
+
+    let jwk = Jwk {
+        common: CommonParameters {
+            public_key_use: Some(PublicKeyUse::Signature),
+            key_algorithm: KeyAlgorithm::from_str(algorithm_name(alg)).ok(),
+            key_id: Some(kid.to_string()),
+            ..Default::default()
+        },
+        algorithm: params,
+    };
+
+    Ok(Some(GeneratedKeyPair {
+        private_key: STANDARD.encode(private_der),
+        public_jwk: serde_json::to_value(jwk)?,
+    }))
+}
+
+/// Algorithm and key used to sign access tokens with `key`.
+pub fn access_token_encoding_key(key: &JwtKey) -> anyhow::Result<(Algorithm, EncodingKey)> {
+    let alg = parse_algorithm(&key.algorithm)
+        .ok_or_else(|| anyhow::anyhow!("Unsupported signing algorithm: {}", key.algorithm))?;
+    let encoding_key = match alg {
+        Algorithm::HS256 => EncodingKey::from_secret(key.secret.as_bytes()),
+        _ => {
+            let der = key
+                .private_key
+                .as_deref()
+                .map(|k| STANDARD.decode(k))
+                .transpose()?
+                .ok_or_else(|| anyhow::anyhow!("Signing key {} has no private key", key.kid))?;
+            match alg {
+                Algorithm::EdDSA => EncodingKey::from_ed_der(&der),
+                _ => EncodingKey::from_rsa_der(&der),
+            }
+        }
+    };
+    Ok((alg, encoding_key))
+}
+
+/// Key that verifies a token signed by `key` with `alg`: the shared secret
+/// for HS256, otherwise the public key if it was generated for `alg`.
+pub fn decoding_key(key: &JwtKey, alg: Algorithm) -> Option<DecodingKey> {
+    if alg == Algorithm::HS256 {
+        return Some(DecodingKey::from_secret(key.secret.as_bytes()));
+    }
+    if parse_algorithm(&key.algorithm) != Some(alg) {
+        return None;
+    }
+    public_jwk(key).and_then(|jwk| DecodingKey::from_jwk(&jwk).ok())
+}
+
+/// Key that verifies an access token signed by `key` with `alg`. Unlike
+/// [`decoding_key`], the shared secret of an EdDSA/RS256 key is refused:
+/// access tokens are only ever signed with the key's own algorithm.
+pub fn access_token_decoding_key(key: &JwtKey, alg: Algorithm) -> Option<DecodingKey> {
+    if parse_algorithm(&key.algorithm) != Some(alg) {
+        return None;
+    }
+    decoding_key(key, alg)
+}
+
+pub fn public_jwk(key: &JwtKey) -> Option<Jwk> {
+    key.public_jwk
+        .as_ref()
+        .and_then(|value| serde_json::from_value(value.clone()).ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonwebtoken::{decode, encode, Header, Validation};
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct Claims {
+        sub: String,
+        exp: i64,
+    }
+
+    fn key_with(alg: Algorithm) -> JwtKey {
+        let pair = generate_keypair(alg, "test-kid").unwrap();
+        JwtKey {
+            id: uuid::Uuid::new_v4(),
+            kid: "test-kid".to_string(),
+            secret: "shared_secret".to_string(),
+            status: crate::models::JWT_KEY_ACTIVE.to_string(),
+            algorithm: algorithm_name(alg).to_string(),
+            private_key: pair.as_ref().map(|p| p.private_key.clone()),
+            public_jwk: pair.map(|p| p.public_jwk),
+            created_at: chrono::Utc::now(),
+            rotated_at: None,
+            verify_until: None,
+            retired_at: None,
+        }
+    }
+
+    fn roundtrip(alg: Algorithm) {
+        let key = key_with(alg);
+        let (signing_alg, encoding_key) = access_token_encoding_key(&key).unwrap();
+        assert_eq!(signing_alg, alg);
+
+        let claims = Claims {
+            sub: "user".to_string(),
+            exp: chrono::Utc::now().timestamp() + 60,
+        };
+        let token = encode(&Header::new(alg), &claims, &encoding_key).unwrap();
+
+        let decoding_key = decoding_key(&key, alg).expect("decoding key");
+        let decoded = decode::<Claims>(&token, &decoding_key, &Validation::new(alg)).unwrap();
+        assert_eq!(decoded.claims.sub, "user");
+    }
+
+    #[test]
+    fn test_eddsa_roundtrip() {
+        roundtrip(Algorithm::EdDSA);
+    }
+
+    #[test]
+    fn test_rs256_roundtrip() {
+        roundtrip(Algorithm::RS256);
+    }
+
+    #[test]
+    fn test_hs256_has_no_public_key() {
+        let key = key_with(Algorithm::HS256);
+        assert!(key.public_jwk.is_none());
+        assert!(decoding_key(&key, Algorithm::HS256).is_some());
+        assert!(decoding_key(&key, Algorithm::EdDSA).is_none());
+    }
+
+    #[test]
+    fn test_access_tokens_only_verify_with_key_algorithm() {
+        let key = key_with(Algorithm::EdDSA);
+        assert!(access_token_decoding_key(&key, Algorithm::EdDSA).is_some());
+        assert!(access_token_decoding_key(&key, Algorithm::HS256).is_none());
+        // Refresh and admin tokens still use the shared secret
+        assert!(decoding_key(&key, Algorithm::HS256).is_some());
+
+        let key = key_with(Algorithm::HS256);
+        assert!(access_token_decoding_key(&key, Algorithm::HS256).is_some());
+    }
+
+    #[test]
+    fn test_public_jwk_carries_kid() {
+        let key = key_with(Algorithm::EdDSA);
+        let jwk = public_jwk(&key).unwrap();
+        assert_eq!(jwk.common.key_id.as_deref(), Some("test-kid"));
+        assert!(matches!(
+            jwk.algorithm,
+            AlgorithmParameters::OctetKeyPair(_)
+        ));
+    }
+
+    #[test]
+    fn test_parse_algorithm() {
+        assert_eq!(parse_algorithm("EdDSA"), Some(Algorithm::EdDSA));
+        assert_eq!(parse_algorithm("RS256"), Some(Algorithm::RS256));
+        assert_eq!(parse_algorithm("HS256"), Some(Algorithm::HS256));
+        assert_eq!(parse_algorithm("HS512"), None);
+        assert_eq!(parse_algorithm("none"), None);
+    }
+}
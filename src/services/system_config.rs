@@ -1,14 +1,22 @@
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::Algorithm;
 use rand::Rng;
 use sqlx::PgPool;
 use std::sync::Arc;
 use tokio::sync::RwLock;
 
-use crate::error::Result;
-use crate::models::{SmtpConfig, SystemConfig};
+use crate::error::{AppError, Result};
+use crate::models::{
+    IdentityKey, JwtKey, SmtpConfig, SystemConfig, JWT_KEY_ACTIVE, JWT_KEY_RETIRED,
+    JWT_KEY_VERIFYING, JWT_LEGACY_KID,
+};
+use crate::services::signing_key;
 
 pub struct SystemConfigService {
     pool: Arc<PgPool>,
     cache: Arc<RwLock<Option<SystemConfig>>>,
+    /// Non-retired signing keys, newest first
+    keys: Arc<RwLock<Option<Vec<JwtKey>>>>,
 }
 
 impl SystemConfigService {
@@ -16,6 +24,7 @@ impl SystemConfigService {
         Self {
             pool,
             cache: Arc::new(RwLock::new(None)),
+            keys: Arc::new(RwLock::new(None)),
         }
     }
 
@@ -33,6 +42,22 @@ impl SystemConfigService {
             tracing::info!("System config initialized with new JWT secret");
         }
 
+        let active: Option<(String,)> =
+            sqlx::query_as("SELECT kid FROM jwt_keys WHERE status = $1")
+                .bind(JWT_KEY_ACTIVE)
+                .fetch_optional(self.pool.as_ref())
+                .await?;
+
+        if active.is_none() {
+            sqlx::query("INSERT INTO jwt_keys (kid, secret, status) VALUES ($1, $2, $3)")
+                .bind(generate_kid())
+                .bind(generate_jwt_secret())
+                .bind(JWT_KEY_ACTIVE)
+                .execute(self.pool.as_ref())
+                .await?;
+            tracing::info!("JWT keyring initialized with new signing key");
+        }
+
         self.reload_cache().await?;
         Ok(())
     }
@@ -50,6 +75,9 @@ impl SystemConfigService {
     pub async fn invalidate_cache(&self) {
         let mut cache = self.cache.write().await;
         *cache = None;
+        drop(cache);
+        let mut keys = self.keys.write().await;
+        *keys = None;
     }
 
     async fn get_config(&self) -> Result<SystemConfig> {
@@ -99,32 +127,209 @@ impl SystemConfigService {
         self.get_smtp_config().await
     }
 
-    pub async fn get_jwt_secret(&self) -> Result<String> {
-        let config = self.get_config().await?;
-        Ok(config.jwt_secret)
+    /// Returns all non-retired signing keys, newest first.
+    pub async fn get_jwt_keys(&self) -> Result<Vec<JwtKey>> {
+        {
+            let keys = self.keys.read().await;
+            if let Some(ref keys) = *keys {
+                return Ok(keys.clone());
+            }
+        }
+
+        let loaded = sqlx::query_as::<_, JwtKey>(
+            "SELECT * FROM jwt_keys WHERE status IN ($1, $2) ORDER BY created_at DESC",
+        )
+        .bind(JWT_KEY_ACTIVE)
+        .bind(JWT_KEY_VERIFYING)
+        .fetch_all(self.pool.as_ref())
+        .await?;
+
+        let mut keys = self.keys.write().await;
+        *keys = Some(loaded.clone());
+        Ok(loaded)
+    }
+
+    pub async fn get_signing_key(&self) -> Result<JwtKey> {
+        self.get_jwt_keys()
+            .await?
+            .into_iter()
+            .find(|k| k.status == JWT_KEY_ACTIVE)
+            .ok_or_else(|| AppError::Internal(anyhow::anyhow!("No active JWT signing key")))
+    }
+
+    /// Finds the key a token was signed with. Tokens without a `kid` were
+    /// issued before the keyring existed and map to the legacy key.
+    pub async fn find_verification_key(&self, kid: Option<&str>) -> Result<Option<JwtKey>> {
+        let kid = kid.unwrap_or(JWT_LEGACY_KID);
+        Ok(self
+            .get_jwt_keys()
+            .await?
+            .into_iter()
+            .find(|k| k.kid == kid))
+    }
+
+    /// Public keys of all non-retired asymmetric signing keys, so upstream
+    /// services can verify access tokens without the shared secret.
+    pub async fn get_jwks(&self) -> Result<JwkSet> {
+        let keys = self
+            .get_jwt_keys()
+            .await?
+            .iter()
+            .filter_map(signing_key::public_jwk)
+            .collect();
+        Ok(JwkSet { keys })
     }
 
     pub async fn get_jwt_secret_updated_at(&self) -> Result<chrono::DateTime<chrono::Utc>> {
-        let config = self.get_config().await?;
-        Ok(config.jwt_secret_updated_at)
+        Ok(self.get_signing_key().await?.created_at)
     }
 
-    pub async fn rotate_jwt_secret(&self) -> Result<chrono::DateTime<chrono::Utc>> {
-        let new_secret = generate_jwt_secret();
+    /// Creates a new active signing key and demotes the current one to
+    /// `verifying`, so tokens it signed keep working for `grace`, until it
+    /// is retired. `algorithm` switches how access tokens are signed; `None`
+    /// keeps the current key's algorithm.
+    pub async fn rotate_jwt_secret(
+        &self,
+        algorithm: Option<Algorithm>,
+        grace: chrono::Duration,
+    ) -> Result<chrono::DateTime<chrono::Utc>> {
+        let algorithm = match algorithm {
+            Some(alg) => alg,
+            None => signing_key::parse_algorithm(&self.get_signing_key().await?.algorithm)
+                .unwrap_or(Algorithm::HS256),
+        };
+        let kid = generate_kid();
+        let keypair = signing_key::generate_keypair(algorithm, &kid)?;
+
+        let mut tx = self.pool.begin().await?;
+
         sqlx::query(
-            "UPDATE system_config SET 
-                jwt_secret = $1, jwt_secret_updated_at = NOW(), updated_at = NOW()
-             WHERE id = 1",
+            "UPDATE jwt_keys SET status = $1, rotated_at = NOW(), 
+             verify_until = NOW() + make_interval(secs => $3) WHERE status = $2",
         )
-        .bind(&new_secret)
-        .execute(self.pool.as_ref())
+        .bind(JWT_KEY_VERIFYING)
+        .bind(JWT_KEY_ACTIVE)
+        .bind(grace.num_seconds() as f64)
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query(
+            "INSERT INTO jwt_keys (kid, secret, status, algorithm, private_key, public_jwk) 
+             VALUES ($1, $2, $3, $4, $5, $6)",
+        )
+        .bind(&kid)
+        .bind(generate_jwt_secret())
+        .bind(JWT_KEY_ACTIVE)
+        .bind(signing_key::algorithm_name(algorithm))
+        .bind(keypair.as_ref().map(|k| k.private_key.clone()))
+        .bind(keypair.map(|k| k.public_jwk))
+        .execute(&mut *tx)
         .await?;
 
+        sqlx::query(
+            "UPDATE system_config SET jwt_secret_updated_at = NOW(), updated_at = NOW() WHERE id = 1",
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
         self.invalidate_cache().await;
-        tracing::warn!("JWT secret rotated - all existing tokens are now invalid");
+        tracing::info!(
+            kid = %kid,
+            algorithm = signing_key::algorithm_name(algorithm),
+            "JWT signing key rotated - previous key remains valid for verification"
+        );
         self.get_jwt_secret_updated_at().await
     }
 
+    /// Retires `verifying` keys whose grace has ended, or all of them when
+    /// `all` is set. Returns the number of keys retired.
+    pub async fn retire_jwt_keys(&self, all: bool) -> Result<u64> {
+        let query = if all {
+            "UPDATE jwt_keys SET status = $1, retired_at = NOW() WHERE status = $2"
+        } else {
+            "UPDATE jwt_keys SET status = $1, retired_at = NOW() 
+             WHERE status = $2 AND verify_until <= NOW()"
+        };
+
+        let result = sqlx::query(query)
+            .bind(JWT_KEY_RETIRED)
+            .bind(JWT_KEY_VERIFYING)
+            .execute(self.pool.as_ref())
+            .await?;
+
+        if result.rows_affected() > 0 {
+            self.invalidate_cache().await;
+            tracing::warn!(
+                count = result.rows_affected(),
+                "Retired JWT signing keys - tokens signed with them are now invalid"
+            );
+        }
+        Ok(result.rows_affected())
+    }
+
+    pub async fn list_identity_keys(&self) -> Result<Vec<IdentityKey>> {
+        let keys = sqlx::query_as::<_, IdentityKey>(
+            "SELECT * FROM identity_keys ORDER BY upstream_address",
+        )
+        .fetch_all(self.pool.as_ref())
+        .await?;
+        Ok(keys)
+    }
+
+    /// Creates the identity key of an upstream. An upstream that already has
+    /// a key gets a pending next key instead, replacing any earlier pending
+    /// one; the gateway keeps signing with the current key until
+    /// [`promote_identity_key`](Self::promote_identity_key), so the upstream
+    /// can be configured to accept both keys first.
+    pub async fn rotate_identity_key(&self, upstream_address: &str) -> Result<IdentityKey> {
+        let key = sqlx::query_as::<_, IdentityKey>(
+            "INSERT INTO identity_keys (upstream_address, kid, secret) VALUES ($1, $2, $3) 
+             ON CONFLICT (upstream_address) 
+             DO UPDATE SET next_kid = EXCLUDED.kid, next_secret = EXCLUDED.secret 
+             RETURNING *",
+        )
+        .bind(upstream_address)
+        .bind(generate_kid())
+        .bind(generate_jwt_secret())
+        .fetch_one(self.pool.as_ref())
+        .await?;
+        tracing::info!(
+            upstream = %upstream_address,
+            kid = %key.next_kid.as_deref().unwrap_or(&key.kid),
+            "Identity key rotated"
+        );
+        Ok(key)
+    }
+
+    /// Makes the pending key of a rotation the one the gateway signs with.
+    /// Assertions signed with the previous key stop being issued; `None` if
+    /// the key does not exist or has no pending key.
+    pub async fn promote_identity_key(&self, id: uuid::Uuid) -> Result<Option<IdentityKey>> {
+        let key = sqlx::query_as::<_, IdentityKey>(
+            "UPDATE identity_keys SET kid = next_kid, secret = next_secret, 
+             next_kid = NULL, next_secret = NULL, created_at = NOW() 
+             WHERE id = $1 AND next_kid IS NOT NULL 
+             RETURNING *",
+        )
+        .bind(id)
+        .fetch_optional(self.pool.as_ref())
+        .await?;
+        if let Some(ref key) = key {
+            tracing::info!(upstream = %key.upstream_address, kid = %key.kid, "Identity key promoted");
+        }
+        Ok(key)
+    }
+
+    pub async fn delete_identity_key(&self, id: uuid::Uuid) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM identity_keys WHERE id = $1")
+            .bind(id)
+            .execute(self.pool.as_ref())
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
     pub async fn should_auto_rotate(&self) -> Result<bool> {
         let updated_at = self.get_jwt_secret_updated_at().await?;
         let now = chrono::Utc::now();
@@ -143,3 +348,9 @@ fn generate_jwt_secret() -> String {
         })
         .collect()
 }
+
+fn generate_kid() -> String {
+    let mut bytes = [0u8; 8];
+    rand::thread_rng().fill(&mut bytes);
+    hex::encode(bytes)
+}
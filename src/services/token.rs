@@ -1,49 +1,110 @@
 use std::sync::Arc;
 
-use chrono::{Duration, Utc};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chrono::{DateTime, Duration, Utc};
 use hmac::{Hmac, Mac};
-use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
-use sha2::Sha256;
-use sqlx::PgPool;
+use jsonwebtoken::{
+    decode, decode_header, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation,
+};
+use rand::RngCore;
+use ring::aead::{self, Aad, LessSafeKey, Nonce, UnboundKey, NONCE_LEN};
+use sha2::{Digest, Sha256};
+use sqlx::{PgConnection, PgPool};
 use uuid::Uuid;
 
 use crate::error::{AppError, Result};
-use crate::models::{AccessTokenClaims, RefreshTokenClaims};
-use crate::services::SystemConfigService;
+use crate::models::{
+    AccessTokenClaims, AuthContext, JwtKey, RefreshTokenClaims, Session, SessionMetadata,
+    SECURITY_EVENT_REFRESH_TOKEN_REUSE,
+};
+use crate::services::{signing_key, user, JwtConfigService, SystemConfigService};
 
 type HmacSha256 = Hmac<Sha256>;
 
+const MAX_USER_AGENT_LEN: usize = 512;
+
+/// How long a rotated refresh token keeps returning its successor, so a
+/// client that retries or races a refresh is not mistaken for a thief.
+const ROTATION_GRACE_SECS: i64 = 30;
+
+fn truncate_user_agent(user_agent: &str) -> &str {
+    match user_agent.char_indices().nth(MAX_USER_AGENT_LEN) {
+        Some((idx, _)) => &user_agent[..idx],
+        None => user_agent,
+    }
+}
+
+/// Login-wide state shared by every refresh token of a family.
+#[derive(sqlx::FromRow)]
+struct TokenFamily {
+    family_id: Uuid,
+    label: Option<String>,
+    session_created_at: DateTime<Utc>,
+    amr: Vec<String>,
+}
+
+/// A rotated refresh token presented again.
+#[derive(sqlx::FromRow)]
+struct ReplayedToken {
+    family_id: Uuid,
+    successor_token: Option<String>,
+    in_grace: bool,
+    session_created_at: DateTime<Utc>,
+    amr: Vec<String>,
+}
+
 pub struct TokenService {
     pool: Arc<PgPool>,
     system_config: Arc<SystemConfigService>,
-    access_token_ttl: i64,
-    refresh_token_ttl: i64,
-    auto_refresh_threshold: i64,
+    jwt_config: Arc<JwtConfigService>,
 }
 
 impl TokenService {
     pub fn new(
         pool: Arc<PgPool>,
         system_config: Arc<SystemConfigService>,
-        access_token_ttl: i64,
-        refresh_token_ttl: i64,
-        auto_refresh_threshold: i64,
+        jwt_config: Arc<JwtConfigService>,
     ) -> Self {
         Self {
             pool,
             system_config,
-            access_token_ttl,
-            refresh_token_ttl,
-            auto_refresh_threshold,
+            jwt_config,
         }
     }
 
-    pub async fn generate_access_token(&self, user_id: Uuid, email: &str) -> Result<String> {
-        let secret = self.system_config.get_jwt_secret().await?;
-        let encoding_key = EncodingKey::from_secret(secret.as_bytes());
+    fn signing_header(key: &JwtKey, alg: Algorithm) -> Header {
+        Header {
+            kid: Some(key.kid.clone()),
+            ..Header::new(alg)
+        }
+    }
+
+    /// Returns the key named by the token's `kid` and the algorithm it claims.
+    async fn verification_key(&self, token: &str) -> Result<(JwtKey, Algorithm)> {
+        let header = decode_header(token).map_err(|_| AppError::InvalidToken)?;
+        let key = self
+            .system_config
+            .find_verification_key(header.kid.as_deref())
+            .await?
+            .ok_or(AppError::InvalidToken)?;
+        Ok((key, header.alg))
+    }
+
+    pub async fn generate_access_token(
+        &self,
+        user_id: Uuid,
+        email: &str,
+        auth: &AuthContext,
+    ) -> Result<String> {
+        let key = self.system_config.get_signing_key().await?;
+        let (alg, encoding_key) = signing_key::access_token_encoding_key(&key)?;
+        let ttl = self.jwt_config.get_config().await?.access_token_ttl_secs;
+
+        let roles = user::get_roles(&self.pool, user_id).await?;
 
         let now = Utc::now();
-        let exp = now + Duration::seconds(self.access_token_ttl);
+        let exp = now + Duration::seconds(ttl as i64);
 
         let claims = AccessTokenClaims {
             sub: user_id,
@@ -51,17 +112,21 @@ impl TokenService {
             exp: exp.timestamp(),
             iat: now.timestamp(),
             jti: Uuid::new_v4(),
+            amr: auth.amr.clone(),
+            auth_time: auth.auth_time.timestamp(),
+            roles,
         };
 
-        encode(&Header::default(), &claims, &encoding_key)
+        encode(&Self::signing_header(&key, alg), &claims, &encoding_key)
             .map_err(|_| AppError::Internal(anyhow::anyhow!("Failed to generate token")))
     }
 
     pub async fn validate_access_token(&self, token: &str) -> Result<AccessTokenClaims> {
-        let secret = self.system_config.get_jwt_secret().await?;
-        let decoding_key = DecodingKey::from_secret(secret.as_bytes());
+        let (key, alg) = self.verification_key(token).await?;
+        let decoding_key =
+            signing_key::access_token_decoding_key(&key, alg).ok_or(AppError::InvalidToken)?;
 
-        let validation = Validation::default();
+        let validation = Validation::new(alg);
         let token_data =
             decode::<AccessTokenClaims>(token, &decoding_key, &validation).map_err(|e| match e
                 .kind()
@@ -73,17 +138,56 @@ impl TokenService {
         Ok(token_data.claims)
     }
 
-    pub fn should_refresh(&self, claims: &AccessTokenClaims) -> bool {
+    pub async fn should_refresh(&self, claims: &AccessTokenClaims) -> Result<bool> {
+        let threshold = self
+            .jwt_config
+            .get_config()
+            .await?
+            .auto_refresh_threshold_secs;
         let now = Utc::now().timestamp();
-        claims.exp - now < self.auto_refresh_threshold
+        Ok(claims.exp - now < threshold as i64)
+    }
+
+    /// Issues a refresh token for a new login, starting a new token family.
+    pub async fn generate_refresh_token(
+        &self,
+        user_id: Uuid,
+        auth: &AuthContext,
+        metadata: &SessionMetadata,
+    ) -> Result<String> {
+        let family = TokenFamily {
+            family_id: Uuid::new_v4(),
+            label: None,
+            session_created_at: auth.auth_time,
+            amr: auth.amr.clone(),
+        };
+        self.issue_refresh_token(user_id, &family, metadata).await
+    }
+
+    async fn issue_refresh_token(
+        &self,
+        user_id: Uuid,
+        family: &TokenFamily,
+        metadata: &SessionMetadata,
+    ) -> Result<String> {
+        let mut conn = self.pool.acquire().await?;
+        self.insert_refresh_token(&mut conn, user_id, family, metadata)
+            .await
     }
 
-    pub async fn generate_refresh_token(&self, user_id: Uuid) -> Result<String> {
-        let secret = self.system_config.get_jwt_secret().await?;
-        let encoding_key = EncodingKey::from_secret(secret.as_bytes());
+    async fn insert_refresh_token(
+        &self,
+        conn: &mut PgConnection,
+        user_id: Uuid,
+        family: &TokenFamily,
+        metadata: &SessionMetadata,
+    ) -> Result<String> {
+        let key = self.system_config.get_signing_key().await?;
+        let encoding_key = EncodingKey::from_secret(key.secret.as_bytes());
+        let ttl = self.jwt_config.get_config().await?.refresh_token_ttl_secs;
 
         let now = Utc::now();
-        let exp = now + Duration::seconds(self.refresh_token_ttl);
+        let exp = now + Duration::seconds(ttl as i64);
         let jti = Uuid::new_v4();
 
         let claims = RefreshTokenClaims {
@@ -93,25 +197,41 @@ impl TokenService {
             jti,
         };
 
-        let token = encode(&Header::default(), &claims, &encoding_key)
-            .map_err(|_| AppError::Internal(anyhow::anyhow!("Failed to generate refresh token")))?;
+        let token = encode(
+            &Self::signing_header(&key, Algorithm::HS256),
+            &claims,
+            &encoding_key,
+        )
+        .map_err(|_| AppError::Internal(anyhow::anyhow!("Failed to generate refresh token")))?;
 
-        let token_hash = Self::hmac_hash_token(&token, &secret);
+        let token_hash = Self::hmac_hash_token(&token, &key.secret);
         sqlx::query(
-            "INSERT INTO refresh_tokens (user_id, token_hash, expires_at) VALUES ($1, $2, $3)",
+            "INSERT INTO refresh_tokens 
+             (user_id, token_hash, expires_at, family_id, user_agent, ip_address, label, 
+              session_created_at, amr, last_used_at) 
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, NOW())",
         )
         .bind(user_id)
         .bind(&token_hash)
         .bind(exp)
-        .execute(self.pool.as_ref())
+        .bind(family.family_id)
+        .bind(metadata.user_agent.as_deref().map(truncate_user_agent))
+        .bind(&metadata.ip_address)
+        .bind(&family.label)
+        .bind(family.session_created_at)
+        .bind(&family.amr)
+        .execute(&mut *conn)
         .await?;
 
         Ok(token)
     }
 
+    /// Validates a refresh token. Presenting a token that was already
+    /// rotated means it leaked, so its whole family is revoked, unless it
+    /// was rotated within the grace window and can return its successor.
     pub async fn validate_refresh_token(&self, token: &str) -> Result<RefreshTokenClaims> {
-        let secret = self.system_config.get_jwt_secret().await?;
-        let decoding_key = DecodingKey::from_secret(secret.as_bytes());
+        let (key, _) = self.verification_key(token).await?;
+        let decoding_key = DecodingKey::from_secret(key.secret.as_bytes());
 
         let validation = Validation::default();
         let token_data =
@@ -122,31 +242,249 @@ impl TokenService {
                 _ => AppError::InvalidToken,
             })?;
 
-        let token_hash = Self::hmac_hash_token(token, &secret);
-        let exists: Option<(bool,)> = sqlx::query_as(
-            "SELECT revoked FROM refresh_tokens WHERE token_hash = $1 AND expires_at > NOW()",
+        let token_hash = Self::hmac_hash_token(token, &key.secret);
+        let exists: Option<(bool, bool, Uuid, bool)> = sqlx::query_as(
+            "SELECT revoked, rotated_at IS NOT NULL, family_id, 
+             successor_token IS NOT NULL AND rotated_at > NOW() - make_interval(secs => $2) 
+             FROM refresh_tokens WHERE token_hash = $1 AND expires_at > NOW()",
         )
         .bind(&token_hash)
+        .bind(ROTATION_GRACE_SECS as f64)
         .fetch_optional(self.pool.as_ref())
         .await?;
 
         match exists {
-            Some((true,)) => Err(AppError::TokenRevoked),
-            Some((false,)) => Ok(token_data.claims),
+            Some((true, true, _, true)) => Ok(token_data.claims),
+            Some((true, true, family_id, false)) => {
+                self.handle_refresh_token_reuse(token_data.claims.sub, family_id)
+                    .await?;
+                Err(AppError::TokenRevoked)
+            }
+            Some((true, false, _, _)) => Err(AppError::TokenRevoked),
+            Some((false, _, _, _)) => Ok(token_data.claims),
             None => Err(AppError::InvalidToken),
         }
     }
 
-    pub async fn revoke_refresh_token(&self, token: &str) -> Result<()> {
-        let secret = self.system_config.get_jwt_secret().await?;
-        let token_hash = Self::hmac_hash_token(token, &secret);
-        sqlx::query("UPDATE refresh_tokens SET revoked = TRUE WHERE token_hash = $1")
-            .bind(&token_hash)
-            .execute(self.pool.as_ref())
+    /// Revokes a validated refresh token and issues its successor in the
+    /// same family. A token rotated within the grace window returns the
+    /// successor it was rotated to; later replays count as reuse.
+    /// Returns the new token and the context of the original login.
+    pub async fn rotate_refresh_token(
+        &self,
+        token: &str,
+        user_id: Uuid,
+        metadata: &SessionMetadata,
+    ) -> Result<(String, AuthContext)> {
+        let (key, _) = self.verification_key(token).await?;
+        let token_hash = Self::hmac_hash_token(token, &key.secret);
+
+        // A concurrent rotation of the same token waits for this transaction
+        // and then finds the successor stored
+        let mut tx = self.pool.begin().await?;
+        let rotated: Option<TokenFamily> = sqlx::query_as(
+            "UPDATE refresh_tokens SET revoked = TRUE, rotated_at = NOW() 
+             WHERE token_hash = $1 AND revoked = FALSE 
+             RETURNING family_id, label, session_created_at, amr",
+        )
+        .bind(&token_hash)
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        if let Some(family) = rotated {
+            let successor = self
+                .insert_refresh_token(&mut tx, user_id, &family, metadata)
+                .await?;
+            sqlx::query("UPDATE refresh_tokens SET successor_token = $2 WHERE token_hash = $1")
+                .bind(&token_hash)
+                .bind(seal_successor(token, &successor))
+                .execute(&mut *tx)
+                .await?;
+            tx.commit().await?;
+            return Ok((
+                successor,
+                AuthContext {
+                    amr: family.amr,
+                    auth_time: family.session_created_at,
+                },
+            ));
+        }
+        tx.rollback().await?;
+
+        let replayed: Option<ReplayedToken> = sqlx::query_as(
+            "SELECT family_id, successor_token, 
+             rotated_at > NOW() - make_interval(secs => $2) AS in_grace, session_created_at, amr 
+             FROM refresh_tokens WHERE token_hash = $1",
+        )
+        .bind(&token_hash)
+        .bind(ROTATION_GRACE_SECS as f64)
+        .fetch_optional(self.pool.as_ref())
+        .await?;
+        let Some(replayed) = replayed else {
+            return Err(AppError::TokenRevoked);
+        };
+        if replayed.in_grace {
+            let sealed = replayed.successor_token;
+            if let Some(successor) = sealed.and_then(|s| open_successor(token, &s)) {
+                // Not once the session was ended, e.g. by logout everywhere
+                if self.is_refresh_token_active(&successor, &key).await? {
+                    return Ok((
+                        successor,
+                        AuthContext {
+                            amr: replayed.amr,
+                            auth_time: replayed.session_created_at,
+                        },
+                    ));
+                }
+                return Err(AppError::TokenRevoked);
+            }
+        }
+        self.handle_refresh_token_reuse(user_id, replayed.family_id)
             .await?;
+        Err(AppError::TokenRevoked)
+    }
+
+    async fn is_refresh_token_active(&self, token: &str, key: &JwtKey) -> Result<bool> {
+        let token_hash = Self::hmac_hash_token(token, &key.secret);
+        let active: Option<(bool,)> = sqlx::query_as(
+            "SELECT revoked FROM refresh_tokens WHERE token_hash = $1 AND expires_at > NOW()",
+        )
+        .bind(&token_hash)
+        .fetch_optional(self.pool.as_ref())
+        .await?;
+        Ok(matches!(active, Some((false,))))
+    }
+
+    async fn handle_refresh_token_reuse(&self, user_id: Uuid, family_id: Uuid) -> Result<()> {
+        let result = sqlx::query(
+            "UPDATE refresh_tokens SET revoked = TRUE WHERE family_id = $1 AND revoked = FALSE",
+        )
+        .bind(family_id)
+        .execute(self.pool.as_ref())
+        .await?;
+
+        sqlx::query(
+            "INSERT INTO security_events (user_id, event_type, details) VALUES ($1, $2, $3)",
+        )
+        .bind(user_id)
+        .bind(SECURITY_EVENT_REFRESH_TOKEN_REUSE)
+        .bind(serde_json::json!({
+            "family_id": family_id,
+            "revoked_tokens": result.rows_affected(),
+        }))
+        .execute(self.pool.as_ref())
+        .await?;
+
+        tracing::warn!(
+            user_id = %user_id,
+            family_id = %family_id,
+            revoked = result.rows_affected(),
+            "Security: rotated refresh token reused, token family revoked"
+        );
         Ok(())
     }
 
+    /// Revokes a refresh token of `user_id`. Returns false if the token
+    /// belongs to someone else or does not exist.
+    pub async fn revoke_refresh_token(&self, token: &str, user_id: Uuid) -> Result<bool> {
+        let (key, _) = self.verification_key(token).await?;
+        let token_hash = Self::hmac_hash_token(token, &key.secret);
+        let result = sqlx::query(
+            "UPDATE refresh_tokens SET revoked = TRUE WHERE token_hash = $1 AND user_id = $2",
+        )
+        .bind(&token_hash)
+        .bind(user_id)
+        .execute(self.pool.as_ref())
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Revokes every refresh token of a user. Returns the number revoked.
+    pub async fn revoke_all_refresh_tokens(&self, user_id: Uuid) -> Result<u64> {
+        let result = sqlx::query(
+            "UPDATE refresh_tokens SET revoked = TRUE WHERE user_id = $1 AND revoked = FALSE",
+        )
+        .bind(user_id)
+        .execute(self.pool.as_ref())
+        .await?;
+        Ok(result.rows_affected())
+    }
+
+    /// Active sessions of a user, most recently used first.
+    pub async fn list_sessions(&self, user_id: Uuid) -> Result<Vec<Session>> {
+        let sessions = sqlx::query_as::<_, Session>(
+            "SELECT family_id AS id, label, user_agent, ip_address, 
+                    session_created_at AS created_at, last_used_at, expires_at 
+             FROM refresh_tokens 
+             WHERE user_id = $1 AND revoked = FALSE AND expires_at > NOW() 
+             ORDER BY last_used_at DESC",
+        )
+        .bind(user_id)
+        .fetch_all(self.pool.as_ref())
+        .await?;
+        Ok(sessions)
+    }
+
+    /// Revokes one session of a user. Returns false if it is not active.
+    pub async fn revoke_session(&self, user_id: Uuid, session_id: Uuid) -> Result<bool> {
+        let result = sqlx::query(
+            "UPDATE refresh_tokens SET revoked = TRUE 
+             WHERE user_id = $1 AND family_id = $2 AND revoked = FALSE",
+        )
+        .bind(user_id)
+        .bind(session_id)
+        .execute(self.pool.as_ref())
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Renames one session of a user. Returns false if it is not active.
+    pub async fn update_session_label(
+        &self,
+        user_id: Uuid,
+        session_id: Uuid,
+        label: Option<&str>,
+    ) -> Result<bool> {
+        let result = sqlx::query(
+            "UPDATE refresh_tokens SET label = $3 
+             WHERE user_id = $1 AND family_id = $2 AND revoked = FALSE",
+        )
+        .bind(user_id)
+        .bind(session_id)
+        .bind(label)
+        .execute(self.pool.as_ref())
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Deny-lists an access token by `jti` until it expires on its own.
+    pub async fn revoke_access_token(&self, claims: &AccessTokenClaims) -> Result<()> {
+        let expires_at =
+            chrono::DateTime::from_timestamp(claims.exp, 0).ok_or(AppError::InvalidToken)?;
+        sqlx::query(
+            "INSERT INTO revoked_tokens (jti, user_id, expires_at) VALUES ($1, $2, $3)
+             ON CONFLICT (jti) DO NOTHING",
+        )
+        .bind(claims.jti)
+        .bind(claims.sub)
+        .bind(expires_at)
+        .execute(self.pool.as_ref())
+        .await?;
+        Ok(())
+    }
+
+    /// Unexpired deny-listed access tokens as `(jti, exp)`.
+    pub async fn list_revoked_access_tokens(&self) -> Result<Vec<(Uuid, i64)>> {
+        let rows: Vec<(Uuid, chrono::DateTime<Utc>)> =
+            sqlx::query_as("SELECT jti, expires_at FROM revoked_tokens WHERE expires_at > NOW()")
+                .fetch_all(self.pool.as_ref())
+                .await?;
+        Ok(rows
+            .into_iter()
+            .map(|(jti, expires_at)| (jti, expires_at.timestamp()))
+            .collect())
+    }
+
     fn hmac_hash_token(token: &str, secret: &str) -> String {
         let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).unwrap_or_else(|_| {
             tracing::error!("HMAC initialization failed - this should never happen");
@@ -157,10 +495,55 @@ impl TokenService {
     }
 }
 
+fn successor_key(token: &str) -> LessSafeKey {
+    let digest = Sha256::digest(token.as_bytes());
+    LessSafeKey::new(
+        UnboundKey::new(&aead::CHACHA20_POLY1305, &digest).expect("SHA-256 is a valid key size"),
+    )
+}
+
+/// Encrypts a rotated token's successor with a key derived from the rotated
+/// token, so the stored value is useless to anyone who does not hold it.
+fn seal_successor(token: &str, successor: &str) -> String {
+    let mut nonce = [0u8; NONCE_LEN];
+    rand::thread_rng().fill_bytes(&mut nonce);
+    let mut data = successor.as_bytes().to_vec();
+    successor_key(token)
+        .seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::empty(), &mut data)
+        .expect("successor fits in one message");
+    let mut sealed = nonce.to_vec();
+    sealed.extend(data);
+    STANDARD.encode(sealed)
+}
+
+fn open_successor(token: &str, sealed: &str) -> Option<String> {
+    let mut nonce = STANDARD.decode(sealed).ok()?;
+    if nonce.len() < NONCE_LEN {
+        return None;
+    }
+    let mut data = nonce.split_off(NONCE_LEN);
+    let nonce = Nonce::try_assume_unique_for_key(&nonce).ok()?;
+    let successor = successor_key(token)
+        .open_in_place(nonce, Aad::empty(), &mut data)
+        .ok()?;
+    String::from_utf8(successor.to_vec()).ok()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_successor_opens_only_with_rotated_token() {
+        let sealed = seal_successor("old.refresh.token", "new.refresh.token");
+        assert_eq!(
+            open_successor("old.refresh.token", &sealed).as_deref(),
+            Some("new.refresh.token")
+        );
+        assert_eq!(open_successor("other.refresh.token", &sealed), None);
+        assert_eq!(open_successor("old.refresh.token", "bm9wZQ=="), None);
+    }
+
     #[test]
     fn test_hmac_hash_consistency() {
         let token = "test_token_12345";
@@ -192,6 +575,16 @@ mod tests {
         assert_ne!(hash1, hash2);
     }
 
+    #[test]
+    fn test_truncate_user_agent() {
+        assert_eq!(truncate_user_agent("curl/8.0"), "curl/8.0");
+        let long = "é".repeat(MAX_USER_AGENT_LEN + 10);
+        assert_eq!(
+            truncate_user_agent(&long).chars().count(),
+            MAX_USER_AGENT_LEN
+        );
+    }
+
     #[test]
     fn test_hmac_hash_output_format() {
         let hash = TokenService::hmac_hash_token("token", "secret");
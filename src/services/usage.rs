@@ -0,0 +1,100 @@
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use std::sync::Arc;
+
+use crate::error::Result;
+use crate::gateway::connections::ConnectionSubject;
+use crate::gateway::usage::{day_start, month_start_time, UsageKey, UsageRecord};
+use crate::models::UsageDay;
+
+/// Days of usage buckets kept; older ones are deleted by the cleanup task.
+pub const USAGE_RETENTION_DAYS: i64 = 366;
+
+pub struct UsageService {
+    db_pool: Arc<PgPool>,
+}
+
+impl UsageService {
+    pub fn new(db_pool: Arc<PgPool>) -> Self {
+        Self { db_pool }
+    }
+
+    /// Adds metered usage to its hourly buckets.
+    pub async fn record(&self, records: &[UsageRecord]) -> Result<()> {
+        let mut tx = self.db_pool.begin().await?;
+        for record in records {
+            let (subject_type, subject_id) = record.key.subject.parts();
+            sqlx::query(
+                "INSERT INTO usage_buckets (subject_type, subject_id, route, bucket_start, 
+                 requests, response_bytes, duration_ms) 
+                 VALUES ($1, $2, $3, $4, $5, $6, $7) 
+                 ON CONFLICT (subject_type, subject_id, route, bucket_start) DO UPDATE SET 
+                 requests = usage_buckets.requests + EXCLUDED.requests, 
+                 response_bytes = usage_buckets.response_bytes + EXCLUDED.response_bytes, 
+                 duration_ms = usage_buckets.duration_ms + EXCLUDED.duration_ms",
+            )
+            .bind(subject_type)
+            .bind(subject_id)
+            .bind(&record.key.route)
+            .bind(record.bucket_start)
+            .bind(record.counts.requests as i64)
+            .bind(record.counts.response_bytes as i64)
+            .bind(record.counts.duration_ms as i64)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Requests of every subject and route in the current UTC day and month.
+    pub async fn period_totals(&self, now: DateTime<Utc>) -> Result<Vec<(UsageKey, u64, u64)>> {
+        let rows: Vec<(String, String, String, i64, i64)> = sqlx::query_as(
+            "SELECT subject_type, subject_id, route, 
+             COALESCE(SUM(requests) FILTER (WHERE bucket_start >= $1), 0)::BIGINT, 
+             SUM(requests)::BIGINT 
+             FROM usage_buckets WHERE bucket_start >= $2 
+             GROUP BY subject_type, subject_id, route",
+        )
+        .bind(day_start(now))
+        .bind(month_start_time(now))
+        .fetch_all(self.db_pool.as_ref())
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .filter_map(|(kind, id, route, day, month)| {
+                let subject = ConnectionSubject::from_parts(&kind, id)?;
+                Some((
+                    UsageKey { subject, route },
+                    day.max(0) as u64,
+                    month.max(0) as u64,
+                ))
+            })
+            .collect())
+    }
+
+    /// Daily usage of `subject` per route since `since`, oldest first.
+    pub async fn daily_usage(
+        &self,
+        subject: &ConnectionSubject,
+        since: DateTime<Utc>,
+    ) -> Result<Vec<UsageDay>> {
+        let (subject_type, subject_id) = subject.parts();
+        let days = sqlx::query_as::<_, UsageDay>(
+            "SELECT route, (bucket_start AT TIME ZONE 'UTC')::DATE AS day, 
+             SUM(requests)::BIGINT AS requests, 
+             SUM(response_bytes)::BIGINT AS response_bytes, 
+             SUM(duration_ms)::BIGINT AS duration_ms 
+             FROM usage_buckets 
+             WHERE subject_type = $1 AND subject_id = $2 AND bucket_start >= $3 
+             GROUP BY route, day ORDER BY day, route",
+        )
+        .bind(subject_type)
+        .bind(subject_id)
+        .bind(since)
+        .fetch_all(self.db_pool.as_ref())
+        .await?;
+        Ok(days)
+    }
+}
@@ -1,3 +1,4 @@
+use chrono::{DateTime, Utc};
 use sqlx::PgPool;
 use std::sync::Arc;
 use uuid::Uuid;
@@ -5,6 +6,16 @@ use uuid::Uuid;
 use crate::error::Result;
 use crate::models::User;
 
+/// Roles of a user, sorted; shared with token issuing.
+pub async fn get_roles(pool: &PgPool, user_id: Uuid) -> Result<Vec<String>> {
+    let rows: Vec<(String,)> =
+        sqlx::query_as("SELECT role FROM user_roles WHERE user_id = $1 ORDER BY role")
+            .bind(user_id)
+            .fetch_all(pool)
+            .await?;
+    Ok(rows.into_iter().map(|(role,)| role).collect())
+}
+
 pub struct UserService {
     pool: Arc<PgPool>,
 }
@@ -29,4 +40,65 @@ impl UserService {
             .await?;
         Ok(user)
     }
+
+    pub async fn get_roles(&self, user_id: Uuid) -> Result<Vec<String>> {
+        get_roles(&self.pool, user_id).await
+    }
+
+    /// Replaces the roles of a user. New tokens carry them; tokens already
+    /// issued keep the old roles until they are refreshed.
+    pub async fn set_roles(&self, user_id: Uuid, roles: &[String]) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("DELETE FROM user_roles WHERE user_id = $1")
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("INSERT INTO user_roles (user_id, role) SELECT $1, UNNEST($2::VARCHAR[])")
+            .bind(user_id)
+            .bind(roles)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Users the gateway must reject: deactivated accounts, with no end,
+    /// plus deleted accounts until their access tokens have expired.
+    pub async fn list_revoked_users(&self) -> Result<Vec<(Uuid, Option<i64>)>> {
+        let rows: Vec<(Uuid, Option<DateTime<Utc>>)> = sqlx::query_as(
+            "SELECT id, NULL::TIMESTAMPTZ FROM users WHERE is_active = FALSE
+             UNION
+             SELECT user_id, expires_at FROM user_revocations WHERE expires_at > NOW()",
+        )
+        .fetch_all(self.pool.as_ref())
+        .await?;
+        Ok(rows
+            .into_iter()
+            .map(|(id, until)| (id, until.map(|t| t.timestamp())))
+            .collect())
+    }
+
+    /// Deletes a user and remembers it until `expires_at`, so tokens issued
+    /// before the deletion keep being rejected. Returns `false` if there was
+    /// no such user.
+    pub async fn delete(&self, user_id: Uuid, expires_at: DateTime<Utc>) -> Result<bool> {
+        let mut tx = self.pool.begin().await?;
+        let result = sqlx::query("DELETE FROM users WHERE id = $1")
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+        if result.rows_affected() == 0 {
+            return Ok(false);
+        }
+        sqlx::query(
+            "INSERT INTO user_revocations (user_id, expires_at) VALUES ($1, $2)
+             ON CONFLICT (user_id) DO UPDATE SET expires_at = EXCLUDED.expires_at",
+        )
+        .bind(user_id)
+        .bind(expires_at)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(true)
+    }
 }
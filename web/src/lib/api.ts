@@ -1,5 +1,14 @@
 type HttpMethod = 'GET' | 'POST' | 'PUT' | 'DELETE';
 
+export type AuthMode = 'required' | 'optional' | 'none';
+export type MatchType = 'prefix' | 'exact' | 'regex' | 'glob';
+export type LoadBalanceStrategy = 'round_robin' | 'least_connections' | 'consistent_hash';
+export type UpstreamTarget = { address: string; weight: number };
+export type UsageReport = {
+  current: Array<{ route: string; requests_today: number; requests_this_month: number; daily_quota: number | null; monthly_quota: number | null }>;
+  days: Array<{ route: string; day: string; requests: number; response_bytes: number; duration_ms: number }>;
+};
+
 interface ApiError {
   code: string;
   message: string;
@@ -77,38 +86,74 @@ export const adminApi = {
       '/api/admin/register', 'POST', { username, password, registration_token }
     ),
 
-  getStats: () => api<{ active_users: number; total_requests: number; system_status: string }>(
+  getStats: () => api<{ active_users: number; total_requests: number; system_status: string; active_connections: number }>(
     '/api/admin/stats'
   ),
 
+  getUpstreamStats: () => api<Array<{ name: string; strategy: LoadBalanceStrategy; health_check_path: string | null; targets: Array<{ address: string; weight: number; healthy: boolean; active_requests: number }> }>>(
+    '/api/admin/stats/upstreams'
+  ),
+
+  getCircuitStats: () => api<Array<{ upstream: string; state: 'closed' | 'open' | 'half_open'; consecutive_failures: number; retry_after_secs: number | null }>>(
+    '/api/admin/stats/circuits'
+  ),
+
+  getConnectionStats: () => api<{ total: number; connections: Array<{ route: string; subject: { type: 'user' | 'api_key'; id: string }; connections: number }> }>(
+    '/api/admin/stats/connections'
+  ),
+
   getActivities: () => api<{ activities: Array<{ id: string; action: string; email: string; status: string; created_at: string }> }>(
     '/api/admin/activities'
   ),
 
-  getUsers: () => api<{ users: Array<{ id: string; email: string; email_verified: boolean; is_active: boolean; created_at: string }> }>(
+  getUsers: () => api<{ users: Array<{ id: string; email: string; email_verified: boolean; is_active: boolean; created_at: string; roles: string[] }> }>(
     '/api/admin/users'
   ),
 
+  getUser: (id: string) => api<{
+    id: string; email: string; email_verified: boolean; is_active: boolean; created_at: string; roles: string[];
+    sessions: Array<{ id: string; label: string | null; user_agent: string | null; ip_address: string | null; created_at: string; last_used_at: string; expires_at: string }>;
+  }>(`/api/admin/users/${id}`),
+
   updateUserStatus: (id: string, is_active: boolean) =>
     api<{ success: boolean }>(`/api/admin/users/${id}`, 'PUT', { is_active }),
 
+  updateUserRoles: (id: string, roles: string[]) =>
+    api<{ success: boolean; roles: string[] }>(`/api/admin/users/${id}/roles`, 'PUT', { roles }),
+
   deleteUser: (id: string) =>
     api<{ success: boolean }>(`/api/admin/users/${id}`, 'DELETE'),
+
+  getUserUsage: (id: string, days?: number) => api<UsageReport>(
+    `/api/admin/users/${id}/usage${days ? `?days=${days}` : ''}`
+  ),
 };
 
 export const configApi = {
-  listRoutes: () => api<Array<{ id: string; path_prefix: string; upstream_address: string; require_auth: boolean; strip_prefix: string | null; enabled: boolean }>>(
+  listRoutes: () => api<Array<{ id: string; host: string | null; path_prefix: string; match_type: MatchType; priority: number; methods: string[]; rewrite: string | null; upstream_address: string; auth_mode: AuthMode; strip_prefix: string | null; enabled: boolean; max_auth_age_secs: number | null; require_mfa: boolean; required_roles: string[]; identity_headers: Record<string, string>; token_query_param: string | null; token_cookie: string | null; token_subprotocol: boolean; max_connection_secs: number | null; close_at_token_expiry: boolean; max_connections_per_user: number | null; upstream_pool: string | null; connect_timeout_ms: number | null; read_timeout_ms: number | null; write_timeout_ms: number | null; idle_timeout_ms: number | null; max_retries: number; upstream_keepalive: boolean; max_in_flight: number | null; max_queue: number; queue_timeout_ms: number; fair_queue: boolean; daily_quota: number | null; monthly_quota: number | null }>>(
     '/api/config/routes'
   ),
 
-  createRoute: (data: { path_prefix: string; upstream_address: string; require_auth: boolean; strip_prefix?: string }) =>
-    api<{ id: string; path_prefix: string; upstream_address: string; require_auth: boolean; strip_prefix: string | null; enabled: boolean }>('/api/config/routes', 'POST', data),
+  createRoute: (data: { host?: string | null; path_prefix: string; match_type?: MatchType; priority?: number; methods?: string[]; rewrite?: string | null; upstream_address: string; auth_mode: AuthMode; strip_prefix?: string; max_auth_age_secs?: number | null; require_mfa?: boolean; required_roles?: string[]; identity_headers?: Record<string, string>; token_query_param?: string | null; token_cookie?: string | null; token_subprotocol?: boolean; max_connection_secs?: number | null; close_at_token_expiry?: boolean; max_connections_per_user?: number | null; upstream_pool?: string | null; connect_timeout_ms?: number | null; read_timeout_ms?: number | null; write_timeout_ms?: number | null; idle_timeout_ms?: number | null; max_retries?: number; upstream_keepalive?: boolean; max_in_flight?: number | null; max_queue?: number; queue_timeout_ms?: number; fair_queue?: boolean; daily_quota?: number | null; monthly_quota?: number | null }) =>
+    api<{ id: string; host: string | null; path_prefix: string; match_type: MatchType; priority: number; methods: string[]; rewrite: string | null; upstream_address: string; auth_mode: AuthMode; strip_prefix: string | null; enabled: boolean; max_auth_age_secs: number | null; require_mfa: boolean; required_roles: string[]; identity_headers: Record<string, string>; token_query_param: string | null; token_cookie: string | null; token_subprotocol: boolean; max_connection_secs: number | null; close_at_token_expiry: boolean; max_connections_per_user: number | null; upstream_pool: string | null; connect_timeout_ms: number | null; read_timeout_ms: number | null; write_timeout_ms: number | null; idle_timeout_ms: number | null; max_retries: number; upstream_keepalive: boolean; max_in_flight: number | null; max_queue: number; queue_timeout_ms: number; fair_queue: boolean; daily_quota: number | null; monthly_quota: number | null }>('/api/config/routes', 'POST', data),
 
-  updateRoute: (id: string, data: { path_prefix: string; upstream_address: string; require_auth: boolean; strip_prefix?: string; enabled: boolean }) =>
-    api<{ id: string; path_prefix: string; upstream_address: string; require_auth: boolean; strip_prefix: string | null; enabled: boolean }>(`/api/config/routes/${id}`, 'PUT', data),
+  updateRoute: (id: string, data: { host?: string | null; path_prefix: string; match_type?: MatchType; priority?: number; methods?: string[]; rewrite?: string | null; upstream_address: string; auth_mode: AuthMode; strip_prefix?: string; enabled: boolean; max_auth_age_secs?: number | null; require_mfa?: boolean; required_roles?: string[]; identity_headers?: Record<string, string>; token_query_param?: string | null; token_cookie?: string | null; token_subprotocol?: boolean; max_connection_secs?: number | null; close_at_token_expiry?: boolean; max_connections_per_user?: number | null; upstream_pool?: string | null; connect_timeout_ms?: number | null; read_timeout_ms?: number | null; write_timeout_ms?: number | null; idle_timeout_ms?: number | null; max_retries?: number; upstream_keepalive?: boolean; max_in_flight?: number | null; max_queue?: number; queue_timeout_ms?: number; fair_queue?: boolean; daily_quota?: number | null; monthly_quota?: number | null }) =>
+    api<{ id: string; host: string | null; path_prefix: string; match_type: MatchType; priority: number; methods: string[]; rewrite: string | null; upstream_address: string; auth_mode: AuthMode; strip_prefix: string | null; enabled: boolean; max_auth_age_secs: number | null; require_mfa: boolean; required_roles: string[]; identity_headers: Record<string, string>; token_query_param: string | null; token_cookie: string | null; token_subprotocol: boolean; max_connection_secs: number | null; close_at_token_expiry: boolean; max_connections_per_user: number | null; upstream_pool: string | null; connect_timeout_ms: number | null; read_timeout_ms: number | null; write_timeout_ms: number | null; idle_timeout_ms: number | null; max_retries: number; upstream_keepalive: boolean; max_in_flight: number | null; max_queue: number; queue_timeout_ms: number; fair_queue: boolean; daily_quota: number | null; monthly_quota: number | null }>(`/api/config/routes/${id}`, 'PUT', data),
 
   deleteRoute: (id: string) => api(`/api/config/routes/${id}`, 'DELETE'),
 
+  listUpstreamPools: () => api<Array<{ id: string; name: string; strategy: LoadBalanceStrategy; targets: UpstreamTarget[]; health_check_path: string | null; health_check_interval_secs: number }>>(
+    '/api/config/upstream-pools'
+  ),
+
+  createUpstreamPool: (data: { name: string; strategy: LoadBalanceStrategy; targets: UpstreamTarget[]; health_check_path: string | null; health_check_interval_secs: number }) =>
+    api<{ id: string; name: string; strategy: LoadBalanceStrategy; targets: UpstreamTarget[]; health_check_path: string | null; health_check_interval_secs: number }>('/api/config/upstream-pools', 'POST', data),
+
+  updateUpstreamPool: (id: string, data: { name: string; strategy: LoadBalanceStrategy; targets: UpstreamTarget[]; health_check_path: string | null; health_check_interval_secs: number }) =>
+    api<{ id: string; name: string; strategy: LoadBalanceStrategy; targets: UpstreamTarget[]; health_check_path: string | null; health_check_interval_secs: number }>(`/api/config/upstream-pools/${id}`, 'PUT', data),
+
+  deleteUpstreamPool: (id: string) => api(`/api/config/upstream-pools/${id}`, 'DELETE'),
+
   listRateLimits: () => api<Array<{ id: string; name: string; path_pattern: string; limit_by: string; max_requests: number; window_secs: number; enabled: boolean }>>(
     '/api/config/rate-limits'
   ),
@@ -135,7 +180,9 @@ export const configApi = {
   updateSmtpConfig: (data: { from_email: string; smtp_pass: string }) =>
     api('/api/config/smtp', 'PUT', data),
 
-  getJwtSecretInfo: () => api<{ updated_at: string }>('/api/config/jwt-secret'),
+  getJwtSecretInfo: () => api<{ updated_at: string; keys: Array<{ kid: string; status: string; algorithm: string; created_at: string; rotated_at: string | null }> }>(
+    '/api/config/jwt-secret'
+  ),
 
   rotateJwtSecret: () => api('/api/config/jwt-secret', 'POST'),
 };
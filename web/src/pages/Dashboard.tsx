@@ -1,13 +1,14 @@
 import { useEffect, useState } from 'react';
 import { useTranslation } from 'react-i18next';
 import { Card, CardContent, CardHeader, CardTitle } from '../components/ui/Card';
-import { Activity, Users, Globe } from 'lucide-react';
+import { Activity, Users, Globe, Radio } from 'lucide-react';
 import { adminApi } from '../lib/api';
 
 interface Stats {
   active_users: number;
   total_requests: number;
   system_status: string;
+  active_connections: number;
 }
 
 interface ActivityItem {
@@ -53,6 +54,12 @@ export default function Dashboard() {
       icon: Globe,
       color: "text-green-600"
     },
+    {
+      title: t('dashboard.activeConnections'),
+      value: stats?.active_connections?.toLocaleString() ?? '-',
+      icon: Radio,
+      color: "text-purple-600"
+    },
     {
       title: t('dashboard.systemStatus'),
       value: stats?.system_status ?? '-',
@@ -5,17 +5,45 @@ import { Button } from '../components/ui/Button';
 import { Input } from '../components/ui/Input';
 import { Plus, Trash2, Save, Route, Shield, Timer, Mail, Check } from 'lucide-react';
 import { cn } from '../lib/utils';
-import { configApi } from '../lib/api';
+import { configApi, type AuthMode, type MatchType } from '../lib/api';
 
 type ConfigTab = 'routes' | 'jwt' | 'ratelimits' | 'smtp';
 
 interface RouteItem {
   id: string;
+  host: string | null;
   path_prefix: string;
+  match_type: MatchType;
+  priority: number;
+  methods: string[];
+  rewrite: string | null;
   upstream_address: string;
-  require_auth: boolean;
+  auth_mode: AuthMode;
   strip_prefix: string | null;
   enabled: boolean;
+  max_auth_age_secs: number | null;
+  require_mfa: boolean;
+  required_roles: string[];
+  identity_headers: Record<string, string>;
+  token_query_param: string | null;
+  token_cookie: string | null;
+  token_subprotocol: boolean;
+  max_connection_secs: number | null;
+  close_at_token_expiry: boolean;
+  max_connections_per_user: number | null;
+  upstream_pool: string | null;
+  connect_timeout_ms: number | null;
+  read_timeout_ms: number | null;
+  write_timeout_ms: number | null;
+  idle_timeout_ms: number | null;
+  max_retries: number;
+  upstream_keepalive: boolean;
+  max_in_flight: number | null;
+  max_queue: number;
+  queue_timeout_ms: number;
+  fair_queue: boolean;
+  daily_quota: number | null;
+  monthly_quota: number | null;
   isNew?: boolean;
   isEditing?: boolean;
 }
@@ -82,11 +110,39 @@ export default function ProxyConfig() {
   const handleAddRoute = () => {
     const newRoute: RouteItem = {
       id: `new-${Date.now()}`,
+      host: null,
       path_prefix: '/new/',
+      match_type: 'prefix',
+      priority: 0,
+      methods: [],
+      rewrite: null,
       upstream_address: '127.0.0.1:8000',
-      require_auth: true,
+      auth_mode: 'required',
       strip_prefix: null,
       enabled: true,
+      max_auth_age_secs: null,
+      require_mfa: false,
+      required_roles: [],
+      identity_headers: { sub: 'X-User-Id' },
+      token_query_param: null,
+      token_cookie: null,
+      token_subprotocol: false,
+      max_connection_secs: null,
+      close_at_token_expiry: false,
+      max_connections_per_user: null,
+      upstream_pool: null,
+      connect_timeout_ms: null,
+      read_timeout_ms: null,
+      write_timeout_ms: null,
+      idle_timeout_ms: null,
+      max_retries: 0,
+      upstream_keepalive: true,
+      max_in_flight: null,
+      max_queue: 0,
+      queue_timeout_ms: 5000,
+      fair_queue: false,
+      daily_quota: null,
+      monthly_quota: null,
       isNew: true,
       isEditing: true,
     };
@@ -97,9 +153,11 @@ export default function ProxyConfig() {
     setSaving(true);
     if (route.isNew) {
       const res = await configApi.createRoute({
+        host: route.host,
         path_prefix: route.path_prefix,
+        match_type: route.match_type,
         upstream_address: route.upstream_address,
-        require_auth: route.require_auth,
+        auth_mode: route.auth_mode,
         strip_prefix: route.strip_prefix || undefined,
       });
       if (res.data) {
@@ -108,11 +166,39 @@ export default function ProxyConfig() {
       }
     } else {
       const res = await configApi.updateRoute(route.id, {
+        host: route.host,
         path_prefix: route.path_prefix,
+        match_type: route.match_type,
+        priority: route.priority,
+        methods: route.methods,
+        rewrite: route.rewrite,
         upstream_address: route.upstream_address,
-        require_auth: route.require_auth,
+        auth_mode: route.auth_mode,
         strip_prefix: route.strip_prefix || undefined,
         enabled: route.enabled,
+        max_auth_age_secs: route.max_auth_age_secs,
+        require_mfa: route.require_mfa,
+        required_roles: route.required_roles,
+        identity_headers: route.identity_headers,
+        token_query_param: route.token_query_param,
+        token_cookie: route.token_cookie,
+        token_subprotocol: route.token_subprotocol,
+        max_connection_secs: route.max_connection_secs,
+        close_at_token_expiry: route.close_at_token_expiry,
+        max_connections_per_user: route.max_connections_per_user,
+        upstream_pool: route.upstream_pool,
+        connect_timeout_ms: route.connect_timeout_ms,
+        read_timeout_ms: route.read_timeout_ms,
+        write_timeout_ms: route.write_timeout_ms,
+        idle_timeout_ms: route.idle_timeout_ms,
+        max_retries: route.max_retries,
+        upstream_keepalive: route.upstream_keepalive,
+        max_in_flight: route.max_in_flight,
+        max_queue: route.max_queue,
+        queue_timeout_ms: route.queue_timeout_ms,
+        fair_queue: route.fair_queue,
+        daily_quota: route.daily_quota,
+        monthly_quota: route.monthly_quota,
       });
       if (res.data) {
         const newData: RouteItem = { ...res.data, isEditing: false };
@@ -297,7 +383,15 @@ function RoutesTab({ routes, onAdd, onSave, onDelete, onUpdate, saving, t }: Rou
               "flex items-start space-x-4 p-4 border rounded-lg",
               route.isNew ? "bg-blue-50/50 border-blue-200" : "bg-slate-50/50"
             )}>
-              <div className="flex-1 grid grid-cols-4 gap-4">
+              <div className="flex-1 grid grid-cols-6 gap-4">
+                <div className="space-y-2">
+                  <label className="text-sm font-medium">{t('proxy.host')}</label>
+                  <Input
+                    value={route.host || ''}
+                    placeholder={t('proxy.hostHint')}
+                    onChange={(e) => onUpdate(route.id, { host: e.target.value || null })}
+                  />
+                </div>
                 <div className="space-y-2">
                   <label className="text-sm font-medium">{t('proxy.pathPrefix')}</label>
                   <Input
@@ -305,6 +399,19 @@ function RoutesTab({ routes, onAdd, onSave, onDelete, onUpdate, saving, t }: Rou
                     onChange={(e) => onUpdate(route.id, { path_prefix: e.target.value })}
                   />
                 </div>
+                <div className="space-y-2">
+                  <label className="text-sm font-medium">{t('proxy.matchType')}</label>
+                  <select
+                    className="flex h-10 w-full rounded-md border border-slate-200 bg-white px-3 py-2 text-sm"
+                    value={route.match_type}
+                    onChange={(e) => onUpdate(route.id, { match_type: e.target.value as MatchType })}
+                  >
+                    <option value="prefix">{t('proxy.matchPrefix')}</option>
+                    <option value="exact">{t('proxy.matchExact')}</option>
+                    <option value="regex">{t('proxy.matchRegex')}</option>
+                    <option value="glob">{t('proxy.matchGlob')}</option>
+                  </select>
+                </div>
                 <div className="space-y-2">
                   <label className="text-sm font-medium">{t('proxy.upstreamAddress')}</label>
                   <Input
@@ -321,14 +428,15 @@ function RoutesTab({ routes, onAdd, onSave, onDelete, onUpdate, saving, t }: Rou
                   />
                 </div>
                 <div className="space-y-2">
-                  <label className="text-sm font-medium">{t('proxy.requireAuth')}</label>
+                  <label className="text-sm font-medium">{t('proxy.authMode')}</label>
                   <select
                     className="flex h-10 w-full rounded-md border border-slate-200 bg-white px-3 py-2 text-sm"
-                    value={route.require_auth ? 'true' : 'false'}
-                    onChange={(e) => onUpdate(route.id, { require_auth: e.target.value === 'true' })}
+                    value={route.auth_mode}
+                    onChange={(e) => onUpdate(route.id, { auth_mode: e.target.value as AuthMode })}
                   >
-                    <option value="true">{t('common.yes')}</option>
-                    <option value="false">{t('common.no')}</option>
+                    <option value="required">{t('proxy.authModeRequired')}</option>
+                    <option value="optional">{t('proxy.authModeOptional')}</option>
+                    <option value="none">{t('proxy.authModeNone')}</option>
                   </select>
                 </div>
               </div>